@@ -1035,6 +1035,60 @@ CREATE DATABASE /*!32312 IF NOT EXISTS*/ `duolingodb` /*!40100 DEFAULT CHARACTER
 
 USE `duolingodb`;
 
+--
+-- Table structure for table `anonymized_answers`
+--
+
+DROP TABLE IF EXISTS `anonymized_answers`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `anonymized_answers` (
+  `id` char(36) NOT NULL,
+  `task_id` char(36) NOT NULL,
+  `content` json DEFAULT NULL,
+  `correct` tinyint(1) DEFAULT NULL,
+  `anonymized_time` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `task_id` (`task_id`),
+  CONSTRAINT `anonymized_answers_ibfk_1` FOREIGN KEY (`task_id`) REFERENCES `tasks` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `anonymized_answers`
+--
+
+LOCK TABLES `anonymized_answers` WRITE;
+/*!40000 ALTER TABLE `anonymized_answers` DISABLE KEYS */;
+/*!40000 ALTER TABLE `anonymized_answers` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `answer_results`
+--
+
+DROP TABLE IF EXISTS `answer_results`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `answer_results` (
+  `answer_id` char(36) NOT NULL,
+  `correct` tinyint(1) NOT NULL,
+  `explanation` text,
+  `creation_time` datetime NOT NULL,
+  PRIMARY KEY (`answer_id`),
+  CONSTRAINT `answer_results_ibfk_1` FOREIGN KEY (`answer_id`) REFERENCES `answers` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `answer_results`
+--
+
+LOCK TABLES `answer_results` WRITE;
+/*!40000 ALTER TABLE `answer_results` DISABLE KEYS */;
+/*!40000 ALTER TABLE `answer_results` ENABLE KEYS */;
+UNLOCK TABLES;
+
 --
 -- Table structure for table `answers`
 --
@@ -1047,6 +1101,7 @@ CREATE TABLE `answers` (
   `task_id` char(36) NOT NULL,
   `user_id` char(36) NOT NULL,
   `content` json DEFAULT NULL,
+  `creation_time` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP,
   PRIMARY KEY (`id`),
   KEY `task_id` (`task_id`),
   KEY `user_id` (`user_id`),
@@ -1064,6 +1119,297 @@ LOCK TABLES `answers` WRITE;
 /*!40000 ALTER TABLE `answers` ENABLE KEYS */;
 UNLOCK TABLES;
 
+--
+-- Table structure for table `assignment_assignees`
+--
+
+DROP TABLE IF EXISTS `assignment_assignees`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `assignment_assignees` (
+  `assignment_id` char(36) NOT NULL,
+  `user_id` char(36) NOT NULL,
+  PRIMARY KEY (`assignment_id`,`user_id`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `assignment_assignees_ibfk_1` FOREIGN KEY (`assignment_id`) REFERENCES `assignments` (`id`),
+  CONSTRAINT `assignment_assignees_ibfk_2` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `assignment_assignees`
+--
+
+LOCK TABLES `assignment_assignees` WRITE;
+/*!40000 ALTER TABLE `assignment_assignees` DISABLE KEYS */;
+/*!40000 ALTER TABLE `assignment_assignees` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `assignment_tasks`
+--
+
+DROP TABLE IF EXISTS `assignment_tasks`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `assignment_tasks` (
+  `assignment_id` char(36) NOT NULL,
+  `task_id` char(36) NOT NULL,
+  `position` int NOT NULL,
+  PRIMARY KEY (`assignment_id`,`task_id`),
+  KEY `task_id` (`task_id`),
+  CONSTRAINT `assignment_tasks_ibfk_1` FOREIGN KEY (`assignment_id`) REFERENCES `assignments` (`id`),
+  CONSTRAINT `assignment_tasks_ibfk_2` FOREIGN KEY (`task_id`) REFERENCES `tasks` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `assignment_tasks`
+--
+
+LOCK TABLES `assignment_tasks` WRITE;
+/*!40000 ALTER TABLE `assignment_tasks` DISABLE KEYS */;
+/*!40000 ALTER TABLE `assignment_tasks` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `assignments`
+--
+
+DROP TABLE IF EXISTS `assignments`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `assignments` (
+  `id` char(36) NOT NULL,
+  `classroom_id` char(36) NOT NULL,
+  `title` varchar(255) NOT NULL,
+  `course` int DEFAULT NULL,
+  `unit` int DEFAULT NULL,
+  `sector` int DEFAULT NULL,
+  `level` int DEFAULT NULL,
+  `open_time` datetime DEFAULT NULL,
+  `due_time` datetime NOT NULL,
+  `close_time` datetime DEFAULT NULL,
+  `max_attempts` int DEFAULT NULL,
+  `late_policy` varchar(16) NOT NULL DEFAULT 'Accept',
+  `late_penalty` int DEFAULT NULL,
+  `creation_time` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `classroom_id` (`classroom_id`),
+  CONSTRAINT `assignments_ibfk_1` FOREIGN KEY (`classroom_id`) REFERENCES `classrooms` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `assignments`
+--
+
+LOCK TABLES `assignments` WRITE;
+/*!40000 ALTER TABLE `assignments` DISABLE KEYS */;
+/*!40000 ALTER TABLE `assignments` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `audit_log`
+--
+
+DROP TABLE IF EXISTS `audit_log`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `audit_log` (
+  `id` char(36) NOT NULL,
+  `user_id` char(36) DEFAULT NULL,
+  `action` varchar(32) NOT NULL,
+  `ip_address` varchar(64) DEFAULT NULL,
+  `details` varchar(256) DEFAULT NULL,
+  `creation_time` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `user_id` (`user_id`,`creation_time`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `audit_log`
+--
+
+LOCK TABLES `audit_log` WRITE;
+/*!40000 ALTER TABLE `audit_log` DISABLE KEYS */;
+/*!40000 ALTER TABLE `audit_log` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `blocks`
+--
+
+DROP TABLE IF EXISTS `blocks`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `blocks` (
+  `blocker_id` char(36) NOT NULL,
+  `blocked_id` char(36) NOT NULL,
+  `creation_time` datetime NOT NULL,
+  PRIMARY KEY (`blocker_id`,`blocked_id`),
+  KEY `blocked_id` (`blocked_id`),
+  CONSTRAINT `blocks_ibfk_1` FOREIGN KEY (`blocker_id`) REFERENCES `users` (`id`),
+  CONSTRAINT `blocks_ibfk_2` FOREIGN KEY (`blocked_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `blocks`
+--
+
+LOCK TABLES `blocks` WRITE;
+/*!40000 ALTER TABLE `blocks` DISABLE KEYS */;
+/*!40000 ALTER TABLE `blocks` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `classroom_members`
+--
+
+DROP TABLE IF EXISTS `classroom_members`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `classroom_members` (
+  `classroom_id` char(36) NOT NULL,
+  `user_id` char(36) NOT NULL,
+  `join_time` datetime NOT NULL,
+  PRIMARY KEY (`classroom_id`,`user_id`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `classroom_members_ibfk_1` FOREIGN KEY (`classroom_id`) REFERENCES `classrooms` (`id`),
+  CONSTRAINT `classroom_members_ibfk_2` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `classroom_members`
+--
+
+LOCK TABLES `classroom_members` WRITE;
+/*!40000 ALTER TABLE `classroom_members` DISABLE KEYS */;
+/*!40000 ALTER TABLE `classroom_members` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `classrooms`
+--
+
+DROP TABLE IF EXISTS `classrooms`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `classrooms` (
+  `id` char(36) NOT NULL,
+  `teacher_id` char(36) NOT NULL,
+  `name` varchar(128) NOT NULL,
+  `invite_code` varchar(16) NOT NULL,
+  `creation_time` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `invite_code` (`invite_code`),
+  KEY `teacher_id` (`teacher_id`),
+  CONSTRAINT `classrooms_ibfk_1` FOREIGN KEY (`teacher_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `classrooms`
+--
+
+LOCK TABLES `classrooms` WRITE;
+/*!40000 ALTER TABLE `classrooms` DISABLE KEYS */;
+/*!40000 ALTER TABLE `classrooms` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `event_outbox`
+--
+
+DROP TABLE IF EXISTS `event_outbox`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `event_outbox` (
+  `id` bigint unsigned NOT NULL AUTO_INCREMENT,
+  `event` json NOT NULL,
+  `creation_time` datetime NOT NULL,
+  `next_attempt_time` datetime NOT NULL,
+  `processed_time` datetime DEFAULT NULL,
+  `attempts` int NOT NULL,
+  `last_error` text,
+  PRIMARY KEY (`id`),
+  KEY `pending` (`processed_time`,`next_attempt_time`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `event_outbox`
+--
+
+LOCK TABLES `event_outbox` WRITE;
+/*!40000 ALTER TABLE `event_outbox` DISABLE KEYS */;
+/*!40000 ALTER TABLE `event_outbox` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `external_identities`
+--
+
+DROP TABLE IF EXISTS `external_identities`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `external_identities` (
+  `provider` varchar(64) NOT NULL,
+  `subject` varchar(255) NOT NULL,
+  `user_id` char(36) NOT NULL,
+  `email` varchar(128) DEFAULT NULL,
+  `creation_time` datetime NOT NULL,
+  `last_login_time` datetime NOT NULL,
+  PRIMARY KEY (`provider`,`subject`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `external_identities_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `external_identities`
+--
+
+LOCK TABLES `external_identities` WRITE;
+/*!40000 ALTER TABLE `external_identities` DISABLE KEYS */;
+/*!40000 ALTER TABLE `external_identities` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `friend_requests`
+--
+
+DROP TABLE IF EXISTS `friend_requests`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `friend_requests` (
+  `id` char(36) NOT NULL,
+  `sender_id` char(36) NOT NULL,
+  `receiver_id` char(36) NOT NULL,
+  `creation_time` datetime NOT NULL,
+  `user_id_low` char(36) GENERATED ALWAYS AS (least(`sender_id`,`receiver_id`)) STORED,
+  `user_id_high` char(36) GENERATED ALWAYS AS (greatest(`sender_id`,`receiver_id`)) STORED,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `sender_receiver` (`sender_id`,`receiver_id`),
+  UNIQUE KEY `user_pair` (`user_id_low`,`user_id_high`),
+  KEY `receiver_id` (`receiver_id`),
+  CONSTRAINT `friend_requests_ibfk_1` FOREIGN KEY (`sender_id`) REFERENCES `users` (`id`),
+  CONSTRAINT `friend_requests_ibfk_2` FOREIGN KEY (`receiver_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `friend_requests`
+--
+
+LOCK TABLES `friend_requests` WRITE;
+/*!40000 ALTER TABLE `friend_requests` DISABLE KEYS */;
+/*!40000 ALTER TABLE `friend_requests` ENABLE KEYS */;
+UNLOCK TABLES;
+
 --
 -- Table structure for table `friends`
 --
@@ -1090,6 +1436,223 @@ LOCK TABLES `friends` WRITE;
 /*!40000 ALTER TABLE `friends` ENABLE KEYS */;
 UNLOCK TABLES;
 
+--
+-- Table structure for table `grading_jobs`
+--
+
+DROP TABLE IF EXISTS `grading_jobs`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `grading_jobs` (
+  `answer_id` char(36) NOT NULL,
+  `status` varchar(16) NOT NULL,
+  `attempts` int NOT NULL,
+  `creation_time` datetime NOT NULL,
+  `next_attempt_time` datetime NOT NULL,
+  `lease_expiration_time` datetime DEFAULT NULL,
+  `completion_time` datetime DEFAULT NULL,
+  `last_error` text,
+  PRIMARY KEY (`answer_id`),
+  KEY `due` (`status`,`next_attempt_time`),
+  CONSTRAINT `grading_jobs_ibfk_1` FOREIGN KEY (`answer_id`) REFERENCES `answers` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `grading_jobs`
+--
+
+LOCK TABLES `grading_jobs` WRITE;
+/*!40000 ALTER TABLE `grading_jobs` DISABLE KEYS */;
+/*!40000 ALTER TABLE `grading_jobs` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `league_members`
+--
+
+DROP TABLE IF EXISTS `league_members`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `league_members` (
+  `user_id` char(36) NOT NULL,
+  `tier` int NOT NULL,
+  `week_start` date NOT NULL,
+  `weekly_xp` int NOT NULL,
+  PRIMARY KEY (`user_id`),
+  KEY `tier_week` (`tier`,`week_start`,`weekly_xp`),
+  CONSTRAINT `league_members_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `league_members`
+--
+
+LOCK TABLES `league_members` WRITE;
+/*!40000 ALTER TABLE `league_members` DISABLE KEYS */;
+/*!40000 ALTER TABLE `league_members` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `login_challenges`
+--
+
+DROP TABLE IF EXISTS `login_challenges`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `login_challenges` (
+  `token_hash` char(64) NOT NULL,
+  `user_id` char(36) NOT NULL,
+  `attempts` int NOT NULL,
+  `expiration_time` datetime NOT NULL,
+  PRIMARY KEY (`token_hash`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `login_challenges_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `login_challenges`
+--
+
+LOCK TABLES `login_challenges` WRITE;
+/*!40000 ALTER TABLE `login_challenges` DISABLE KEYS */;
+/*!40000 ALTER TABLE `login_challenges` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `login_failures`
+--
+
+DROP TABLE IF EXISTS `login_failures`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `login_failures` (
+  `scope` varchar(8) NOT NULL,
+  `subject` varchar(64) NOT NULL,
+  `failures` int NOT NULL,
+  `last_failure_time` datetime NOT NULL,
+  `locked_until` datetime DEFAULT NULL,
+  PRIMARY KEY (`scope`,`subject`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `login_failures`
+--
+
+LOCK TABLES `login_failures` WRITE;
+/*!40000 ALTER TABLE `login_failures` DISABLE KEYS */;
+/*!40000 ALTER TABLE `login_failures` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `oidc_logins`
+--
+
+DROP TABLE IF EXISTS `oidc_logins`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `oidc_logins` (
+  `state` char(43) NOT NULL,
+  `provider` varchar(64) NOT NULL,
+  `nonce` char(43) NOT NULL,
+  `code_verifier` char(43) NOT NULL,
+  `expiration_time` datetime NOT NULL,
+  PRIMARY KEY (`state`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `oidc_logins`
+--
+
+LOCK TABLES `oidc_logins` WRITE;
+/*!40000 ALTER TABLE `oidc_logins` DISABLE KEYS */;
+/*!40000 ALTER TABLE `oidc_logins` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `password_reset_tokens`
+--
+
+DROP TABLE IF EXISTS `password_reset_tokens`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `password_reset_tokens` (
+  `token_hash` char(64) NOT NULL,
+  `user_id` char(36) NOT NULL,
+  `creation_time` datetime NOT NULL,
+  `expiration_time` datetime NOT NULL,
+  `used_time` datetime DEFAULT NULL,
+  PRIMARY KEY (`token_hash`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `password_reset_tokens_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `password_reset_tokens`
+--
+
+LOCK TABLES `password_reset_tokens` WRITE;
+/*!40000 ALTER TABLE `password_reset_tokens` DISABLE KEYS */;
+/*!40000 ALTER TABLE `password_reset_tokens` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `profile_privacy`
+--
+
+DROP TABLE IF EXISTS `profile_privacy`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `profile_privacy` (
+  `user_id` char(36) NOT NULL,
+  `bio` varchar(16) NOT NULL DEFAULT 'Public',
+  `level` varchar(16) NOT NULL DEFAULT 'Public',
+  `streak` varchar(16) NOT NULL DEFAULT 'Public',
+  `achievements` varchar(16) NOT NULL DEFAULT 'Public',
+  `discoverable` tinyint(1) NOT NULL DEFAULT '1',
+  PRIMARY KEY (`user_id`),
+  CONSTRAINT `profile_privacy_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `profile_privacy`
+--
+
+LOCK TABLES `profile_privacy` WRITE;
+/*!40000 ALTER TABLE `profile_privacy` DISABLE KEYS */;
+/*!40000 ALTER TABLE `profile_privacy` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `recovery_codes`
+--
+
+DROP TABLE IF EXISTS `recovery_codes`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `recovery_codes` (
+  `user_id` char(36) NOT NULL,
+  `code_hash` char(64) NOT NULL,
+  PRIMARY KEY (`user_id`,`code_hash`),
+  CONSTRAINT `recovery_codes_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `recovery_codes`
+--
+
+LOCK TABLES `recovery_codes` WRITE;
+/*!40000 ALTER TABLE `recovery_codes` DISABLE KEYS */;
+/*!40000 ALTER TABLE `recovery_codes` ENABLE KEYS */;
+UNLOCK TABLES;
+
 --
 -- Table structure for table `sessions`
 --
@@ -1098,11 +1661,22 @@ DROP TABLE IF EXISTS `sessions`;
 /*!40101 SET @saved_cs_client     = @@character_set_client */;
 /*!50503 SET character_set_client = utf8mb4 */;
 CREATE TABLE `sessions` (
+  `id` char(36) NOT NULL,
   `user_id` char(36) NOT NULL,
-  `auth_token` char(36) NOT NULL,
-  `expiration_time` datetime NOT NULL,
+  `auth_token_hash` char(64) NOT NULL,
+  `refresh_token_hash` char(64) NOT NULL,
+  `previous_refresh_token_hash` char(64) DEFAULT NULL,
+  `user_agent` varchar(256) DEFAULT NULL,
+  `ip_address` varchar(45) DEFAULT NULL,
   `creation_time` datetime NOT NULL,
-  PRIMARY KEY (`user_id`,`auth_token`),
+  `last_used_time` datetime NOT NULL,
+  `expiration_time` datetime NOT NULL,
+  `max_expiration_time` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `auth_token_hash` (`auth_token_hash`),
+  UNIQUE KEY `refresh_token_hash` (`refresh_token_hash`),
+  KEY `previous_refresh_token_hash` (`previous_refresh_token_hash`),
+  KEY `user_id` (`user_id`),
   CONSTRAINT `sessions_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
 /*!40101 SET character_set_client = @saved_cs_client */;
@@ -1113,10 +1687,63 @@ CREATE TABLE `sessions` (
 
 LOCK TABLES `sessions` WRITE;
 /*!40000 ALTER TABLE `sessions` DISABLE KEYS */;
-INSERT INTO `sessions` VALUES ('95920d77-0cf1-4251-ad67-a1a48c548981','41e56d96-4526-4251-a2c3-50d5192d8606','2024-08-21 13:00:45','2024-08-07 13:00:45'),('95920d77-0cf1-4251-ad67-a1a48c548981','83f76d0e-143b-4a20-8deb-35386caf6148','2024-09-13 12:55:54','2024-08-30 12:55:54'),('95920d77-0cf1-4251-ad67-a1a48c548981','9ceb3d5c-738b-48f4-9283-d5d2cd357111','2024-09-09 20:04:40','2024-08-26 20:04:40'),('95920d77-0cf1-4251-ad67-a1a48c548981','a2e99bae-e4c4-4732-bcfd-2ec29976073f','2024-09-09 20:13:49','2024-08-26 20:13:49'),('95920d77-0cf1-4251-ad67-a1a48c548981','c0ab1782-47ef-4243-b973-8da70c4d2307','2024-09-03 16:55:42','2024-08-20 16:55:42');
 /*!40000 ALTER TABLE `sessions` ENABLE KEYS */;
 UNLOCK TABLES;
 
+--
+-- Table structure for table `solved_tasks`
+--
+
+DROP TABLE IF EXISTS `solved_tasks`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `solved_tasks` (
+  `user_id` char(36) NOT NULL,
+  `task_id` char(36) NOT NULL,
+  `solve_time` datetime NOT NULL,
+  PRIMARY KEY (`user_id`,`task_id`),
+  KEY `task_id` (`task_id`),
+  CONSTRAINT `solved_tasks_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`),
+  CONSTRAINT `solved_tasks_ibfk_2` FOREIGN KEY (`task_id`) REFERENCES `tasks` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `solved_tasks`
+--
+
+LOCK TABLES `solved_tasks` WRITE;
+/*!40000 ALTER TABLE `solved_tasks` DISABLE KEYS */;
+/*!40000 ALTER TABLE `solved_tasks` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `stream_tickets`
+--
+
+DROP TABLE IF EXISTS `stream_tickets`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `stream_tickets` (
+  `ticket_hash` char(64) NOT NULL,
+  `session_id` char(36) NOT NULL,
+  `user_id` char(36) NOT NULL,
+  `expiration_time` datetime NOT NULL,
+  PRIMARY KEY (`ticket_hash`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `stream_tickets_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `stream_tickets`
+--
+
+LOCK TABLES `stream_tickets` WRITE;
+/*!40000 ALTER TABLE `stream_tickets` DISABLE KEYS */;
+/*!40000 ALTER TABLE `stream_tickets` ENABLE KEYS */;
+UNLOCK TABLES;
+
 --
 -- Table structure for table `tags`
 --
@@ -1219,6 +1846,110 @@ INSERT INTO `tasks` VALUES ('1f5b9287-c0e5-4391-98b2-642c8c09dc69','Printing \"H
 /*!40000 ALTER TABLE `tasks` ENABLE KEYS */;
 UNLOCK TABLES;
 
+--
+-- Table structure for table `two_factor`
+--
+
+DROP TABLE IF EXISTS `two_factor`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `two_factor` (
+  `user_id` char(36) NOT NULL,
+  `secret` varchar(64) NOT NULL,
+  `enabled_time` datetime DEFAULT NULL,
+  `last_used_step` bigint DEFAULT NULL,
+  `creation_time` datetime NOT NULL,
+  PRIMARY KEY (`user_id`),
+  CONSTRAINT `two_factor_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `two_factor`
+--
+
+LOCK TABLES `two_factor` WRITE;
+/*!40000 ALTER TABLE `two_factor` DISABLE KEYS */;
+/*!40000 ALTER TABLE `two_factor` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `unlock_tokens`
+--
+
+DROP TABLE IF EXISTS `unlock_tokens`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `unlock_tokens` (
+  `token_hash` char(64) NOT NULL,
+  `user_id` char(36) NOT NULL,
+  `expiration_time` datetime NOT NULL,
+  PRIMARY KEY (`token_hash`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `unlock_tokens_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `unlock_tokens`
+--
+
+LOCK TABLES `unlock_tokens` WRITE;
+/*!40000 ALTER TABLE `unlock_tokens` DISABLE KEYS */;
+/*!40000 ALTER TABLE `unlock_tokens` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `user_achievements`
+--
+
+DROP TABLE IF EXISTS `user_achievements`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `user_achievements` (
+  `user_id` char(36) NOT NULL,
+  `achievement_id` varchar(64) NOT NULL,
+  `unlock_time` datetime NOT NULL,
+  `answer_id` char(36) DEFAULT NULL,
+  PRIMARY KEY (`user_id`,`achievement_id`),
+  KEY `answer_id` (`answer_id`),
+  CONSTRAINT `user_achievements_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `user_achievements`
+--
+
+LOCK TABLES `user_achievements` WRITE;
+/*!40000 ALTER TABLE `user_achievements` DISABLE KEYS */;
+/*!40000 ALTER TABLE `user_achievements` ENABLE KEYS */;
+UNLOCK TABLES;
+
+--
+-- Table structure for table `user_activity`
+--
+
+DROP TABLE IF EXISTS `user_activity`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `user_activity` (
+  `user_id` char(36) NOT NULL,
+  `day` date NOT NULL,
+  PRIMARY KEY (`user_id`,`day`),
+  CONSTRAINT `user_activity_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `user_activity`
+--
+
+LOCK TABLES `user_activity` WRITE;
+/*!40000 ALTER TABLE `user_activity` DISABLE KEYS */;
+/*!40000 ALTER TABLE `user_activity` ENABLE KEYS */;
+UNLOCK TABLES;
+
 --
 -- Table structure for table `user_progress`
 --
@@ -1248,6 +1979,30 @@ INSERT INTO `user_progress` VALUES ('95920d77-0cf1-4251-ad67-a1a48c548981',0,0,0
 /*!40000 ALTER TABLE `user_progress` ENABLE KEYS */;
 UNLOCK TABLES;
 
+--
+-- Table structure for table `user_roles`
+--
+
+DROP TABLE IF EXISTS `user_roles`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `user_roles` (
+  `user_id` char(36) NOT NULL,
+  `role` varchar(16) NOT NULL,
+  PRIMARY KEY (`user_id`,`role`),
+  CONSTRAINT `user_roles_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `user_roles`
+--
+
+LOCK TABLES `user_roles` WRITE;
+/*!40000 ALTER TABLE `user_roles` DISABLE KEYS */;
+/*!40000 ALTER TABLE `user_roles` ENABLE KEYS */;
+UNLOCK TABLES;
+
 --
 -- Table structure for table `users`
 --
@@ -1264,7 +2019,12 @@ CREATE TABLE `users` (
   `bio` varchar(256) DEFAULT NULL,
   `level` int NOT NULL,
   `xp` int NOT NULL,
-  PRIMARY KEY (`id`)
+  `email_verified_time` datetime DEFAULT NULL,
+  `phone_verified_time` datetime DEFAULT NULL,
+  `deletion_requested_time` datetime DEFAULT NULL,
+  `avatar` varchar(32) DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `username` (`username`)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
 /*!40101 SET character_set_client = @saved_cs_client */;
 
@@ -1274,9 +2034,38 @@ CREATE TABLE `users` (
 
 LOCK TABLES `users` WRITE;
 /*!40000 ALTER TABLE `users` DISABLE KEYS */;
-INSERT INTO `users` VALUES ('95920d77-0cf1-4251-ad67-a1a48c548981','$2a$12$6HmtNcdOckAlwW7s42CMke8/G47yIqTzivMOwWKfEwQtxbUfdZJRu','testuser','qwerty@gmail.com',NULL,NULL,0,0);
+INSERT INTO `users` VALUES ('95920d77-0cf1-4251-ad67-a1a48c548981','$2a$12$6HmtNcdOckAlwW7s42CMke8/G47yIqTzivMOwWKfEwQtxbUfdZJRu','testuser','qwerty@gmail.com',NULL,NULL,0,0,NULL,NULL,NULL,NULL);
 /*!40000 ALTER TABLE `users` ENABLE KEYS */;
 UNLOCK TABLES;
+
+--
+-- Table structure for table `verification_codes`
+--
+
+DROP TABLE IF EXISTS `verification_codes`;
+/*!40101 SET @saved_cs_client     = @@character_set_client */;
+/*!50503 SET character_set_client = utf8mb4 */;
+CREATE TABLE `verification_codes` (
+  `user_id` char(36) NOT NULL,
+  `channel` varchar(8) NOT NULL,
+  `destination` varchar(128) NOT NULL,
+  `code_hash` char(64) NOT NULL,
+  `attempts` int NOT NULL,
+  `creation_time` datetime NOT NULL,
+  `expiration_time` datetime NOT NULL,
+  PRIMARY KEY (`user_id`,`channel`),
+  CONSTRAINT `verification_codes_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+/*!40101 SET character_set_client = @saved_cs_client */;
+
+--
+-- Dumping data for table `verification_codes`
+--
+
+LOCK TABLES `verification_codes` WRITE;
+/*!40000 ALTER TABLE `verification_codes` DISABLE KEYS */;
+/*!40000 ALTER TABLE `verification_codes` ENABLE KEYS */;
+UNLOCK TABLES;
 /*!40103 SET TIME_ZONE=@OLD_TIME_ZONE */;
 /*!50606 SET GLOBAL INNODB_STATS_AUTO_RECALC=@OLD_INNODB_STATS_AUTO_RECALC */;
 
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use image::imageops::FilterType;
+use image::{ImageError, ImageFormat, ImageReader, Limits};
+use uuid::Uuid;
+use crate::config::AvatarConfig;
+use crate::storage::{Storage, StorageError};
+
+#[derive(Debug)]
+pub enum AvatarError {
+    TooLarge,
+    UnsupportedFormat,
+    BadImage(ImageError),
+    StorageError(StorageError)
+}
+
+/// Only trusts the content, whatever content type the client claims
+pub fn sniff(data: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(data) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP)) => Some(format),
+        _ => None
+    }
+}
+
+/// Crops the image to a square in the middle and returns it as png in each of the configured sizes
+pub fn resize(data: &[u8], config: &AvatarConfig) -> Result<Vec<(u32, Vec<u8>)>, AvatarError> {
+    let format = sniff(data).ok_or(AvatarError::UnsupportedFormat)?;
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.max_dimension);
+    limits.max_image_height = Some(config.max_dimension);
+
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| match e {
+        ImageError::Limits(_) => AvatarError::TooLarge,
+        e => AvatarError::BadImage(e)
+    })?;
+
+    config.sizes.iter().map(|&size| {
+        let mut png = Vec::new();
+        image.resize_to_fill(size, size, FilterType::Lanczos3)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .map_err(AvatarError::BadImage)?;
+        Ok((size, png))
+    }).collect()
+}
+
+/// Avatars of all users, every upload gets a new version so old urls can be cached forever
+pub struct Avatars {
+    storage: Arc<dyn Storage>,
+    config: AvatarConfig
+}
+
+impl Avatars {
+    pub fn new(storage: Arc<dyn Storage>, config: &AvatarConfig) -> Avatars {
+        Avatars { storage, config: config.clone() }
+    }
+
+    fn key(user_id: Uuid, version: &str, size: u32) -> String {
+        format!("{}/{}_{}.png", user_id.simple(), version, size)
+    }
+
+    /// Stores every size of the image, returns the new version
+    pub async fn save(&self, user_id: Uuid, data: Vec<u8>) -> Result<String, AvatarError> {
+        if data.len() > self.config.max_upload_bytes() {
+            return Err(AvatarError::TooLarge);
+        }
+
+        // Decoding and resizing is too slow for the async workers
+        let config = self.config.clone();
+        let images = tokio::task::spawn_blocking(move || resize(&data, &config)).await
+            .expect("Avatar resizing panicked")?;
+
+        let version = Uuid::new_v4().simple().to_string();
+        for (size, png) in images {
+            self.storage.put(&Avatars::key(user_id, &version, size), "image/png", png).await
+                .map_err(AvatarError::StorageError)?;
+        }
+        Ok(version)
+    }
+
+    pub async fn remove(&self, user_id: Uuid, version: &str) -> Result<(), StorageError> {
+        for size in &self.config.sizes {
+            self.storage.delete(&Avatars::key(user_id, version, *size)).await?;
+        }
+        Ok(())
+    }
+
+    /// Size in pixels to url
+    pub fn urls(&self, user_id: Uuid, version: &str) -> BTreeMap<String, String> {
+        self.config.sizes.iter()
+            .map(|size| (size.to_string(), self.storage.url(&Avatars::key(user_id, version, *size))))
+            .collect()
+    }
+
+    /// For serving files from the local storage
+    pub async fn read(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        self.storage.get(key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use crate::storage::LocalStorage;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        RgbImage::from_pixel(width, height, Rgb([200, 30, 30]))
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(&png(1, 1)), Some(ImageFormat::Png));
+        assert_eq!(sniff(b"GIF89a\x01\x00\x01\x00"), Some(ImageFormat::Gif));
+        assert_eq!(sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), None);
+        assert_eq!(sniff(b"just some text"), None);
+    }
+
+    #[test]
+    fn test_resize() {
+        let config = AvatarConfig { sizes: vec![16, 32], ..Default::default() };
+        let images = resize(&png(300, 100), &config).unwrap();
+
+        assert_eq!(images.len(), 2);
+        for (size, data) in images {
+            let image = image::load_from_memory(&data).unwrap();
+            assert_eq!((image.width(), image.height()), (size, size));
+        }
+
+        let config = AvatarConfig { max_dimension: 200, sizes: vec![16], ..Default::default() };
+        assert!(matches!(resize(&png(300, 100), &config), Err(AvatarError::TooLarge)));
+        assert!(matches!(resize(b"not an image", &config), Err(AvatarError::UnsupportedFormat)));
+        // A png header with nothing behind it
+        assert!(matches!(resize(&png(10, 10)[..40], &config), Err(AvatarError::BadImage(_))));
+    }
+
+    #[tokio::test]
+    async fn test_save_and_remove() {
+        let dir = std::env::temp_dir().join(format!("avatar_test_{}", Uuid::new_v4()));
+        let storage = Arc::new(LocalStorage::new(&dir, "/avatars"));
+        let avatars = Avatars::new(storage, &AvatarConfig { sizes: vec![16, 32], ..Default::default() });
+        let user_id = Uuid::new_v4();
+
+        let version = avatars.save(user_id, png(40, 40)).await.unwrap();
+        let urls = avatars.urls(user_id, &version);
+        assert_eq!(urls["16"], format!("/avatars/{}/{}_16.png", user_id.simple(), version));
+
+        let key = urls["32"].trim_start_matches("/avatars/");
+        assert!(avatars.read(key).await.unwrap().is_some());
+        avatars.remove(user_id, &version).await.unwrap();
+        assert!(avatars.read(key).await.unwrap().is_none());
+
+        let too_big = AvatarConfig { max_upload_kib: 1, ..Default::default() };
+        let avatars = Avatars::new(Arc::new(LocalStorage::new(&dir, "/avatars")), &too_big);
+        assert!(matches!(avatars.save(user_id, vec![0; 2048]).await, Err(AvatarError::TooLarge)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,18 +1,23 @@
 use dotenvy::dotenv;
 use std::env;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use sqlx::{MySqlPool, Error};
 use sqlx::mysql::MySqlPoolOptions;
-
-const DEFAULT_DATABASE_POOL_SIZE: u32 = 10;
+use crate::config::DatabaseConfig;
 
 fn load_env() -> Result<String, std::env::VarError> {
     dotenv().ok();
     env::var("DATABASE_URL")
 }
 
-pub async fn get_database_connection_pool(db_pool_size : Option<u32>) -> Result<Arc<Mutex<MySqlPool>>, Error> {
+pub async fn connect(config: &DatabaseConfig) -> Result<MySqlPool, Error> {
+    MySqlPoolOptions::new()
+        .max_connections(config.pool_size)
+        .connect(&config.url)
+        .await
+}
+
+// Used by the tests, which don't load the config
+pub async fn get_database_connection_pool(db_pool_size : Option<u32>) -> Result<MySqlPool, Error> {
     let db_url = match load_env() {
         Ok(url) => url,
         Err(e) => {
@@ -20,11 +25,11 @@ pub async fn get_database_connection_pool(db_pool_size : Option<u32>) -> Result<
         }
     };
 
-    let pool = MySqlPoolOptions::new()
-        .max_connections(db_pool_size.unwrap_or(DEFAULT_DATABASE_POOL_SIZE))
-        .connect(&db_url)
-        .await?;
-    Ok(Arc::new(Mutex::new(pool)))
+    let mut config = DatabaseConfig { url: db_url, ..Default::default() };
+    if let Some(pool_size) = db_pool_size {
+        config.pool_size = pool_size;
+    }
+    connect(&config).await
 }
 
 mod test {
@@ -34,7 +39,6 @@ mod test {
     #[tokio::test]
     async fn database_connection() {
         let pool = super::get_database_connection_pool(None).await.unwrap();
-        let pool = pool.lock().await;
         assert!(pool.acquire().await.is_ok());
         
     }
@@ -0,0 +1,364 @@
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, Executor, MySql, MySqlPool, Transaction};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use crate::models::leaderboard::LeagueTier;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Things that happened in the domain, other parts of the backend react to them through subscribers
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum DomainEvent {
+    AnswerSubmitted { answer_id: Uuid, user_id: Uuid, task_id: Uuid },
+    AnswerVerified { answer_id: Uuid, user_id: Uuid, task_id: Uuid, correct: bool, explanation: Option<String> },
+    UserRegistered { user_id: Uuid },
+    UserLoggedIn { user_id: Uuid },
+    TaskCreated { task_id: Uuid },
+    FriendRequestSent { request_id: Uuid, sender_id: Uuid, receiver_id: Uuid },
+    AchievementUnlocked { user_id: Uuid, achievement_id: String },
+    LeagueChanged { user_id: Uuid, previous_tier: LeagueTier, tier: LeagueTier, rank: u32 }
+}
+
+impl DomainEvent {
+    /// Stores the event in the outbox as a part of `transaction`, so it's delivered only if the transaction commits.
+    /// Returns the id of the outbox row
+    pub async fn record(&self, transaction: &mut Transaction<'static, MySql>) -> Result<u64, sqlx::Error> {
+        let json = serde_json::to_value(self).expect("Couldn't serialize domain event");
+
+        let result = query!(
+            "INSERT INTO event_outbox (event, creation_time, next_attempt_time, attempts) VALUES (?, ?, ?, 0)",
+            json,
+            Utc::now(),
+            Utc::now()
+        ).execute(transaction.as_mut()).await?;
+
+        Ok(result.last_insert_id())
+    }
+}
+
+#[async_trait]
+pub trait Subscriber: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Runs in the same transaction that marks the event as processed, an error rolls back all subscribers
+    async fn handle(&self, event: &DomainEvent, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error>;
+
+    /// Runs once the delivery of `event` is committed, for effects that can't be rolled back (like pushing to clients)
+    fn delivered(&self, _event: &DomainEvent) {}
+}
+
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Vec<Arc<dyn Subscriber>>
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus { subscribers: Vec::new() }
+    }
+
+    pub fn subscribe(mut self, subscriber: impl Subscriber + 'static) -> EventBus {
+        self.subscribers.push(Arc::new(subscriber));
+        self
+    }
+
+    /// Delivers events from the outbox to the subscribers, forever
+    pub async fn run(self, db_pool: MySqlPool) {
+        info!("Event bus started with {} subscribers", self.subscribers.len());
+
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            loop {
+                match self.dispatch_next(&db_pool).await {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(e) => {
+                        error!("Couldn't dispatch events!\nError: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn deliver(&self, event: &DomainEvent, transaction: &mut Transaction<'static, MySql>) -> Result<(), String> {
+        for subscriber in &self.subscribers {
+            subscriber.handle(event, transaction).await
+                .map_err(|e| format!("{}: {}", subscriber.name(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Returns `false` if there was nothing to dispatch
+    async fn dispatch_next(&self, db_pool: &MySqlPool) -> Result<bool, sqlx::Error> {
+        let mut tx = db_pool.begin().await?;
+
+        let row = query!(
+            "SELECT id FROM event_outbox
+            WHERE processed_time IS NULL AND attempts < ? AND next_attempt_time <= ?
+            ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED",
+            MAX_ATTEMPTS,
+            Utc::now()
+        ).fetch_optional(tx.as_mut()).await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(false)
+        };
+
+        let delivered = self.dispatch(row.id, &mut tx).await?;
+        tx.commit().await?;
+
+        if let Some(event) = delivered {
+            self.delivered(&event);
+        }
+        Ok(true)
+    }
+
+    /// Delivers the outbox row `id` as a part of `transaction`, which should hold its lock.
+    /// Returns the event if it was delivered, otherwise only the failed attempt is kept
+    async fn dispatch(&self, id: u64, transaction: &mut Transaction<'static, MySql>) -> Result<Option<DomainEvent>, sqlx::Error> {
+        let row = query!("SELECT event, attempts FROM event_outbox WHERE id = ? AND processed_time IS NULL FOR UPDATE", id)
+            .fetch_optional(transaction.as_mut()).await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None)
+        };
+
+        // Subscribers' changes are rolled back to here if one of them fails, the whole event is retried later
+        transaction.as_mut().execute("SAVEPOINT delivery").await?;
+
+        let result = match serde_json::from_value::<DomainEvent>(row.event) {
+            Ok(event) => self.deliver(&event, transaction).await.map(|_| event),
+            Err(e) => Err(format!("Couldn't deserialize event: {}", e))
+        };
+
+        match result {
+            Ok(event) => {
+                transaction.as_mut().execute("RELEASE SAVEPOINT delivery").await?;
+                query!("UPDATE event_outbox SET processed_time = ? WHERE id = ?", Utc::now(), id)
+                    .execute(transaction.as_mut()).await?;
+                Ok(Some(event))
+            },
+            Err(e) => {
+                transaction.as_mut().execute("ROLLBACK TO SAVEPOINT delivery").await?;
+
+                let attempts = row.attempts as u32 + 1;
+                warn!("Delivery of event {} failed (attempt {}/{})\nError: {}", id, attempts, MAX_ATTEMPTS, e);
+
+                query!(
+                    "UPDATE event_outbox SET attempts = ?, last_error = ?, next_attempt_time = ? WHERE id = ?",
+                    attempts,
+                    e,
+                    Utc::now() + retry_delay(attempts),
+                    id
+                ).execute(transaction.as_mut()).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn delivered(&self, event: &DomainEvent) {
+        for subscriber in &self.subscribers {
+            subscriber.delivered(event);
+        }
+    }
+}
+
+pub(crate) fn retry_delay(attempts: u32) -> chrono::Duration {
+    chrono::Duration::seconds(2_i64.pow(attempts.min(10)))
+}
+
+pub mod subscribers {
+    use super::*;
+    use crate::models::user::User;
+    use crate::models::leaderboard::XP_PER_CORRECT_ANSWER;
+    use crate::models::achievement::AchievementTrigger;
+
+    /// Awards XP and counts the day towards the streak for correct answers
+    pub struct ProgressSubscriber;
+
+    #[async_trait]
+    impl Subscriber for ProgressSubscriber {
+        fn name(&self) -> &'static str {
+            "progress"
+        }
+
+        async fn handle(&self, event: &DomainEvent, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            match event {
+                DomainEvent::AnswerVerified { user_id, task_id, correct: true, .. } => {
+                    // Solving the same task again (or grading it again) is still activity, but no more XP
+                    if User::record_solved_task(*user_id, *task_id, transaction).await? {
+                        User::award_xp(*user_id, XP_PER_CORRECT_ANSWER, transaction).await?;
+                    }
+                    User::record_activity(*user_id, transaction).await
+                },
+                DomainEvent::UserLoggedIn { user_id } => User::record_activity(*user_id, transaction).await,
+                _ => Ok(())
+            }
+        }
+    }
+
+    pub struct AchievementSubscriber;
+
+    #[async_trait]
+    impl Subscriber for AchievementSubscriber {
+        fn name(&self) -> &'static str {
+            "achievements"
+        }
+
+        async fn handle(&self, event: &DomainEvent, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            let (user_id, trigger) = match event {
+                DomainEvent::AnswerVerified { user_id, .. } => (*user_id, AchievementTrigger::AnswerVerified),
+                DomainEvent::UserLoggedIn { user_id } => (*user_id, AchievementTrigger::Login),
+                _ => return Ok(())
+            };
+
+            let unlocked = User::evaluate_achievements(user_id, trigger, transaction).await?;
+            // So the answer's result can show what it unlocked
+            if let DomainEvent::AnswerVerified { answer_id, .. } = event {
+                User::link_achievements_to_answer(user_id, *answer_id, &unlocked, transaction).await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_serialization() {
+        let event = DomainEvent::AnswerVerified {
+            answer_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
+            correct: true,
+            explanation: None
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert!(json.get("AnswerVerified").is_some());
+        assert_eq!(serde_json::from_value::<DomainEvent>(json).unwrap(), event);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert!(retry_delay(1) < retry_delay(2));
+        assert_eq!(retry_delay(20), retry_delay(10));
+    }
+
+    struct CountingSubscriber(Arc<std::sync::atomic::AtomicUsize>);
+
+    #[async_trait]
+    impl Subscriber for CountingSubscriber {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn handle(&self, event: &DomainEvent, _transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            if let DomainEvent::UserRegistered { .. } = event {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_retry() {
+        let db_pool = crate::database::get_database_connection_pool(None).await.unwrap();
+        let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let bus = EventBus::new()
+            .subscribe(CountingSubscriber(counter.clone()))
+            .subscribe(FailingOnce::default());
+
+        let mut tx = db_pool.begin().await.unwrap();
+        let event = DomainEvent::UserRegistered { user_id: Uuid::new_v4() };
+        let id = event.record(&mut tx).await.unwrap();
+
+        let row = query!("SELECT event, attempts, processed_time FROM event_outbox WHERE id = ?", id)
+            .fetch_one(tx.as_mut()).await.unwrap();
+        assert_eq!(serde_json::from_value::<DomainEvent>(row.event).unwrap(), event);
+        assert_eq!(row.attempts, 0);
+        assert!(row.processed_time.is_none());
+
+        // The failing subscriber comes after the counting one, the attempt is recorded and the next one put off
+        assert!(bus.dispatch(id, &mut tx).await.unwrap().is_none());
+        let row = query!("SELECT attempts, last_error, next_attempt_time, processed_time FROM event_outbox WHERE id = ?", id)
+            .fetch_one(tx.as_mut()).await.unwrap();
+        assert_eq!(row.attempts, 1);
+        assert!(row.last_error.is_some_and(|error| error.starts_with("failing once")));
+        assert!(row.next_attempt_time.and_utc() > Utc::now());
+        assert!(row.processed_time.is_none());
+
+        assert_eq!(bus.dispatch(id, &mut tx).await.unwrap(), Some(event));
+        let row = query!("SELECT attempts, processed_time FROM event_outbox WHERE id = ?", id)
+            .fetch_one(tx.as_mut()).await.unwrap();
+        assert_eq!(row.attempts, 1);
+        assert!(row.processed_time.is_some());
+        // Every attempt goes through all subscribers again
+        assert_eq!(counter.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // Processed events aren't delivered again
+        assert!(bus.dispatch(id, &mut tx).await.unwrap().is_none());
+
+        tx.rollback().await.unwrap();
+    }
+
+    /// Fails the first delivery it sees
+    #[derive(Default)]
+    struct FailingOnce(std::sync::atomic::AtomicBool);
+
+    #[async_trait]
+    impl Subscriber for FailingOnce {
+        fn name(&self) -> &'static str {
+            "failing once"
+        }
+
+        async fn handle(&self, _event: &DomainEvent, _transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            match self.0.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                false => Err(sqlx::Error::RowNotFound),
+                true => Ok(())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notify_after_retry() {
+        use crate::notifications::{Notification, NotificationHub, NotificationSubscriber};
+        use tokio_stream::StreamExt;
+
+        let db_pool = crate::database::get_database_connection_pool(None).await.unwrap();
+        let hub = NotificationHub::new();
+        let bus = EventBus::new()
+            .subscribe(NotificationSubscriber::new(hub.clone()))
+            .subscribe(FailingOnce::default());
+
+        let receiver_id = Uuid::new_v4();
+        let mut notifications = Box::pin(hub.subscribe(receiver_id));
+
+        let mut tx = db_pool.begin().await.unwrap();
+        let id = DomainEvent::FriendRequestSent { request_id: Uuid::new_v4(), sender_id: Uuid::new_v4(), receiver_id }
+            .record(&mut tx).await.unwrap();
+
+        // A later subscriber failing doesn't push anything, neither does the retry before it's committed
+        assert!(bus.dispatch(id, &mut tx).await.unwrap().is_none());
+        let event = bus.dispatch(id, &mut tx).await.unwrap().unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(50), notifications.next()).await.is_err());
+
+        bus.delivered(&event);
+        assert!(matches!(notifications.next().await, Some(Notification::FriendRequestReceived { .. })));
+        assert!(tokio::time::timeout(Duration::from_millis(50), notifications.next()).await.is_err());
+
+        tx.rollback().await.unwrap();
+    }
+}
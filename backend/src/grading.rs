@@ -0,0 +1,331 @@
+use std::time::Duration;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, MySqlPool, Transaction};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use crate::events::retry_delay;
+use crate::config::VerifierConfig;
+use crate::models::answer::{Answer, AnswerContent, VerificationError, VerifyResult};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum GradingStatus {
+    Pending,
+    Running,
+    Done,
+    Failed
+}
+
+impl GradingStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GradingStatus::Pending => "Pending",
+            GradingStatus::Running => "Running",
+            GradingStatus::Done => "Done",
+            GradingStatus::Failed => "Failed"
+        }
+    }
+
+    fn parse(status: &str) -> Result<GradingStatus, String> {
+        match status {
+            "Pending" => Ok(GradingStatus::Pending),
+            "Running" => Ok(GradingStatus::Running),
+            "Done" => Ok(GradingStatus::Done),
+            "Failed" => Ok(GradingStatus::Failed),
+            _ => Err(format!("Invalid grading status {}", status))
+        }
+    }
+}
+
+/// Grading of a single answer, identified by the answer's id
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct GradingJob {
+    pub answer_id: Uuid,
+    pub status: GradingStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>
+}
+
+impl GradingJob {
+    /// Queues grading of `answer_id`, workers pick it up once `transaction` commits
+    pub async fn enqueue(answer_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+        query!(
+            "INSERT INTO grading_jobs (answer_id, status, attempts, creation_time, next_attempt_time) VALUES (?, ?, 0, ?, ?)
+            ON DUPLICATE KEY UPDATE status = ?, attempts = 0, next_attempt_time = ?, last_error = NULL",
+            answer_id.to_string(),
+            GradingStatus::Pending.as_str(),
+            Utc::now(),
+            Utc::now(),
+            GradingStatus::Pending.as_str(),
+            Utc::now()
+        ).execute(transaction.as_mut()).await?;
+
+        Ok(())
+    }
+
+    pub async fn read(answer_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Option<GradingJob>, sqlx::Error> {
+        let row = query!(
+            "SELECT status, attempts, last_error FROM grading_jobs WHERE answer_id = ?",
+            answer_id.to_string()
+        ).fetch_optional(transaction.as_mut()).await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(GradingJob {
+            answer_id,
+            status: GradingStatus::parse(&row.status).map_err(|e| sqlx::Error::Decode(e.into()))?,
+            attempts: row.attempts as u32,
+            last_error: row.last_error
+        }))
+    }
+
+    pub async fn delete(answer_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+        query!("DELETE FROM grading_jobs WHERE answer_id = ?", answer_id.to_string())
+            .execute(transaction.as_mut()).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum GradingError {
+    NoSuchAnswer,
+    Timeout,
+    VerificationError(VerificationError),
+    DatabaseError(sqlx::Error)
+}
+
+impl From<sqlx::Error> for GradingError {
+    fn from(e: sqlx::Error) -> Self {
+        GradingError::DatabaseError(e)
+    }
+}
+
+impl GradingError {
+    // Retrying doesn't help with answers that can't be graded at all
+    fn is_permanent(&self) -> bool {
+        matches!(self, GradingError::NoSuchAnswer
+            | GradingError::VerificationError(VerificationError::BadAnswerFormat)
+            | GradingError::VerificationError(VerificationError::VerifierDisabled))
+    }
+}
+
+/// Runs the verifiers outside of request handling, so slow verifiers (like the AI one) don't block the submission
+#[derive(Clone, Default)]
+pub struct GradingQueue {
+    verifier: VerifierConfig
+}
+
+impl GradingQueue {
+    pub fn new(verifier: VerifierConfig) -> GradingQueue {
+        GradingQueue { verifier }
+    }
+
+    pub async fn run(self, db_pool: MySqlPool) {
+        info!("Grading queue started with {} workers", self.verifier.workers);
+
+        for worker in 0..self.verifier.workers.max(1) {
+            tokio::spawn(self.clone().work(worker, db_pool.clone()));
+        }
+    }
+
+    async fn work(self, worker: usize, db_pool: MySqlPool) {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            loop {
+                match self.grade_next(&db_pool).await {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(e) => {
+                        error!("Grading worker {} couldn't grade!\nError: {}", worker, e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Marks the next due job as running, jobs whose lease ran out (e.g. after a crash or restart) are due again
+    async fn claim(&self, db_pool: &MySqlPool) -> Result<Option<(Uuid, u32)>, sqlx::Error> {
+        let mut tx = db_pool.begin().await?;
+
+        let row = query!(
+            "SELECT answer_id, attempts FROM grading_jobs
+            WHERE (status = ? AND next_attempt_time <= ?) OR (status = ? AND lease_expiration_time <= ?)
+            ORDER BY creation_time LIMIT 1 FOR UPDATE SKIP LOCKED",
+            GradingStatus::Pending.as_str(),
+            Utc::now(),
+            GradingStatus::Running.as_str(),
+            Utc::now()
+        ).fetch_optional(tx.as_mut()).await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None)
+        };
+
+        let answer_id = Uuid::parse_str(&row.answer_id).expect("Couldn't parse string to Uuid");
+        let attempts = row.attempts as u32 + 1;
+
+        let lease = chrono::Duration::from_std(self.verifier.timeout() * 2).unwrap_or(chrono::Duration::minutes(5));
+        query!(
+            "UPDATE grading_jobs SET status = ?, attempts = ?, lease_expiration_time = ? WHERE answer_id = ?",
+            GradingStatus::Running.as_str(),
+            attempts,
+            Utc::now() + lease,
+            row.answer_id
+        ).execute(tx.as_mut()).await?;
+
+        tx.commit().await?;
+        Ok(Some((answer_id, attempts)))
+    }
+
+    // Open questions go out to the verifier, which can take a while, so it's asked with no transaction open
+    async fn verify_open(&self, answer: &Answer) -> Result<Option<VerifyResult>, GradingError> {
+        let open = match &answer.content {
+            Some(AnswerContent::OpenQuestion(open)) => open,
+            _ => return Ok(None)
+        };
+
+        let verify_result = tokio::time::timeout(self.verifier.timeout(), open.verify(&self.verifier)).await
+            .map_err(|_| GradingError::Timeout)?
+            .map_err(GradingError::VerificationError)?;
+        Ok(Some(verify_result))
+    }
+
+    // The other kinds are only compared with the task's answer, that's done in the transaction that saves the result.
+    // Returns `false` if the lease of `attempt` ran out and another worker has the job now, the result is dropped then
+    async fn finish(&self, answer: &Answer, attempt: u32, open_result: Option<VerifyResult>, transaction: &mut Transaction<'static, MySql>) -> Result<bool, GradingError> {
+        let claimed = query!(
+            "UPDATE grading_jobs SET status = ?, completion_time = ?, last_error = NULL WHERE answer_id = ? AND status = ? AND attempts = ?",
+            GradingStatus::Done.as_str(),
+            Utc::now(),
+            answer.id.to_string(),
+            GradingStatus::Running.as_str(),
+            attempt
+        ).execute(transaction.as_mut()).await?;
+
+        if claimed.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        let verify_result = match open_result {
+            Some(verify_result) => verify_result,
+            None => answer.verify(&self.verifier, transaction).await.map_err(GradingError::VerificationError)?
+        };
+
+        verify_result.save(answer, transaction).await?;
+        Ok(true)
+    }
+
+    async fn grade(&self, answer_id: Uuid, attempt: u32, db_pool: &MySqlPool) -> Result<bool, GradingError> {
+        let mut tx = db_pool.begin().await?;
+        let answer = Answer::read(answer_id, &mut tx).await?.ok_or(GradingError::NoSuchAnswer)?;
+        tx.commit().await?;
+
+        // The job row isn't locked here, the claim has already committed
+        let open_result = self.verify_open(&answer).await?;
+
+        let mut tx = db_pool.begin().await?;
+        let finished = self.finish(&answer, attempt, open_result, &mut tx).await?;
+        tx.commit().await?;
+        Ok(finished)
+    }
+
+    /// Returns `false` if there was nothing to grade
+    async fn grade_next(&self, db_pool: &MySqlPool) -> Result<bool, sqlx::Error> {
+        let (answer_id, attempts) = match self.claim(db_pool).await? {
+            Some(job) => job,
+            None => return Ok(false)
+        };
+
+        let result = if attempts > MAX_ATTEMPTS {
+            // Lease ran out on the last attempt
+            Err(GradingError::Timeout)
+        } else {
+            self.grade(answer_id, attempts, db_pool).await
+        };
+
+        match result {
+            Ok(true) => info!("Answer {} graded", answer_id),
+            Ok(false) => warn!("Grading of answer {} took longer than its lease (attempt {}), the result was dropped", answer_id, attempts),
+            Err(e) => {
+                let failed = e.is_permanent() || attempts >= MAX_ATTEMPTS;
+                warn!("Grading of answer {} failed (attempt {}/{})\nError: {:?}", answer_id, attempts, MAX_ATTEMPTS, e);
+
+                let mut tx = db_pool.begin().await?;
+                // Only if the job is still this attempt's, a later one may have finished it already
+                query!(
+                    "UPDATE grading_jobs SET status = ?, last_error = ?, next_attempt_time = ?
+                    WHERE answer_id = ? AND status = ? AND attempts = ?",
+                    if failed { GradingStatus::Failed.as_str() } else { GradingStatus::Pending.as_str() },
+                    format!("{:?}", e),
+                    Utc::now() + retry_delay(attempts),
+                    answer_id.to_string(),
+                    GradingStatus::Running.as_str(),
+                    attempts
+                ).execute(tx.as_mut()).await?;
+                tx.commit().await?;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::answer::PartsAnswer;
+
+    #[test]
+    fn test_status_roundtrip() {
+        for status in [GradingStatus::Pending, GradingStatus::Running, GradingStatus::Done, GradingStatus::Failed] {
+            assert_eq!(GradingStatus::parse(status.as_str()), Ok(status));
+        }
+        assert!(GradingStatus::parse("Graded").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_grade() {
+        let db_pool = crate::database::get_database_connection_pool(None).await.unwrap();
+        let mut tx = db_pool.begin().await.unwrap();
+
+        let task_id = query!("SELECT task_id FROM task_correct_answer LIMIT 1").fetch_one(tx.as_mut()).await.unwrap().task_id;
+        let answer = Answer::new(Uuid::new_v4(), Uuid::parse_str(&task_id).unwrap())
+            .solve(AnswerContent::FromParts(PartsAnswer { parts: vec!["AAAAAAAAAAA".to_string()] }));
+        answer.create(&mut tx).await.unwrap();
+        GradingJob::enqueue(answer.id, &mut tx).await.unwrap();
+        assert_eq!(GradingJob::read(answer.id, &mut tx).await.unwrap().unwrap().status, GradingStatus::Pending);
+
+        // Claimed twice, the first worker's lease ran out
+        query!("UPDATE grading_jobs SET status = ?, attempts = 2 WHERE answer_id = ?", GradingStatus::Running.as_str(), answer.id.to_string())
+            .execute(tx.as_mut()).await.unwrap();
+
+        // Only open questions go to the verifier
+        let queue = GradingQueue::default();
+        let open_result = queue.verify_open(&answer).await.unwrap();
+        assert!(open_result.is_none());
+
+        // The first worker's result is dropped
+        assert!(!queue.finish(&answer, 1, None, &mut tx).await.unwrap());
+        assert!(VerifyResult::read(answer.id, &mut tx).await.unwrap().is_none());
+        assert_eq!(GradingJob::read(answer.id, &mut tx).await.unwrap().unwrap().status, GradingStatus::Running);
+
+        assert!(queue.finish(&answer, 2, open_result, &mut tx).await.unwrap());
+        // Finished jobs aren't saved again
+        assert!(!queue.finish(&answer, 2, None, &mut tx).await.unwrap());
+
+        let job = GradingJob::read(answer.id, &mut tx).await.unwrap().unwrap();
+        assert_eq!(job.status, GradingStatus::Done);
+        assert!(VerifyResult::read(answer.id, &mut tx).await.unwrap().is_some());
+
+        tx.rollback().await.unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+use std::sync::OnceLock;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chrono::prelude::*;
+use hmac::{Hmac, Mac};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+use crate::config::{JwtAlgorithm, JwtConfig};
+
+static KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+/// Turns on JWT access tokens, returns `false` if they already were
+pub fn init(keys: JwtKeys) -> bool {
+    KEYS.set(keys).is_ok()
+}
+
+/// `None` unless the server runs with `session.mode = "jwt"`
+pub fn keys() -> Option<&'static JwtKeys> {
+    KEYS.get()
+}
+
+/// Contents of an access token, enough to authenticate a request without the database
+///
+/// Roles aren't in here, they're always read from the database so taking one away works right away
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Claims {
+    pub sub: Uuid, // User id
+    pub sid: Uuid, // Session the token was issued for
+    pub iss: String,
+    pub iat: i64,
+    pub exp: i64
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    alg: JwtAlgorithm,
+    typ: String
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JwtError {
+    Malformed,
+    WrongAlgorithm,
+    BadSignature,
+    WrongIssuer,
+    Expired,
+    BadKey(String)
+}
+
+enum Signer {
+    HS256(Vec<u8>),
+    EdDSA(Ed25519KeyPair)
+}
+
+pub struct JwtKeys {
+    signer: Signer,
+    issuer: String,
+    ttl: chrono::Duration
+}
+
+impl JwtKeys {
+    pub fn from_config(config: &JwtConfig) -> Result<JwtKeys, JwtError> {
+        let signer = match config.algorithm {
+            JwtAlgorithm::HS256 => {
+                let secret = config.secret.as_ref().ok_or(JwtError::BadKey("no secret for HS256".to_string()))?;
+                Signer::HS256(secret.as_bytes().to_vec())
+            },
+            JwtAlgorithm::EdDSA => {
+                let key = config.private_key.as_ref().ok_or(JwtError::BadKey("no private key for EdDSA".to_string()))?;
+                let der = STANDARD.decode(key.trim()).map_err(|e| JwtError::BadKey(e.to_string()))?;
+                // openssl writes PKCS#8 v1, without the public key
+                let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).map_err(|e| JwtError::BadKey(e.to_string()))?;
+                Signer::EdDSA(pair)
+            }
+        };
+
+        Ok(JwtKeys { signer, issuer: config.issuer.clone(), ttl: config.access_ttl() })
+    }
+
+    fn algorithm(&self) -> JwtAlgorithm {
+        match self.signer {
+            Signer::HS256(_) => JwtAlgorithm::HS256,
+            Signer::EdDSA(_) => JwtAlgorithm::EdDSA
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match &self.signer {
+            Signer::HS256(secret) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            },
+            Signer::EdDSA(pair) => pair.sign(message).as_ref().to_vec()
+        }
+    }
+
+    fn check_signature(&self, message: &[u8], signature: &[u8]) -> bool {
+        match &self.signer {
+            Signer::HS256(secret) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+                mac.update(message);
+                mac.verify_slice(signature).is_ok()
+            },
+            Signer::EdDSA(pair) => UnparsedPublicKey::new(&ED25519, pair.public_key().as_ref()).verify(message, signature).is_ok()
+        }
+    }
+
+    pub fn issue(&self, user_id: Uuid, session_id: Uuid) -> String {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: user_id,
+            sid: session_id,
+            iss: self.issuer.clone(),
+            iat: now.timestamp(),
+            exp: (now + self.ttl).timestamp()
+        };
+        let header = Header { alg: self.algorithm(), typ: "JWT".to_string() };
+
+        let message = format!("{}.{}", encode_part(&header), encode_part(&claims));
+        let signature = URL_SAFE_NO_PAD.encode(self.sign(message.as_bytes()));
+        format!("{}.{}", message, signature)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, JwtError> {
+        let (message, signature) = token.rsplit_once('.').ok_or(JwtError::Malformed)?;
+        let (header, claims) = message.split_once('.').ok_or(JwtError::Malformed)?;
+
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| JwtError::Malformed);
+        let header: Header = serde_json::from_slice(&decode(header)?).map_err(|_| JwtError::Malformed)?;
+
+        // Only the configured algorithm, a token can't pick a weaker one for itself
+        if header.alg != self.algorithm() {
+            return Err(JwtError::WrongAlgorithm);
+        }
+        if !self.check_signature(message.as_bytes(), &decode(signature)?) {
+            return Err(JwtError::BadSignature);
+        }
+
+        let claims: Claims = serde_json::from_slice(&decode(claims)?).map_err(|_| JwtError::Malformed)?;
+        if claims.iss != self.issuer {
+            return Err(JwtError::WrongIssuer);
+        }
+        if claims.exp <= Utc::now().timestamp() {
+            return Err(JwtError::Expired);
+        }
+
+        Ok(claims)
+    }
+}
+
+fn encode_part<T: Serialize>(value: &T) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(value).expect("Couldn't serialize token part"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Made up seed ("test key for the jwt module!!!!!") in the PKCS#8 v1 DER that `openssl genpkey -algorithm ed25519 -outform DER` writes, base64 encoded
+    const ED25519_KEY: &str = "MC4CAQAwBQYDK2VwBCIEIHRlc3Qga2V5IGZvciB0aGUgand0IG1vZHVsZSEhISEh";
+
+    fn hs256() -> JwtKeys {
+        JwtKeys::from_config(&JwtConfig { secret: Some("0123456789abcdef0123456789abcdef".to_string()), ..Default::default() }).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let eddsa = JwtKeys::from_config(&JwtConfig {
+            algorithm: JwtAlgorithm::EdDSA,
+            private_key: Some(ED25519_KEY.to_string()),
+            ..Default::default()
+        }).unwrap();
+
+        for keys in [hs256(), eddsa] {
+            let (user_id, session_id) = (Uuid::new_v4(), Uuid::new_v4());
+            let token = keys.issue(user_id, session_id);
+            assert_eq!(token.matches('.').count(), 2);
+
+            let claims = keys.verify(&token).unwrap();
+            assert_eq!((claims.sub, claims.sid), (user_id, session_id));
+        }
+    }
+
+    #[test]
+    fn test_rejections() {
+        let keys = hs256();
+        let token = keys.issue(Uuid::new_v4(), Uuid::new_v4());
+
+        let other = JwtKeys::from_config(&JwtConfig { secret: Some("another secret, just as long as the first".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(other.verify(&token), Err(JwtError::BadSignature));
+
+        let (message, _) = token.rsplit_once('.').unwrap();
+        assert_eq!(keys.verify(&format!("{}.", message)), Err(JwtError::BadSignature));
+        assert_eq!(keys.verify("not a token"), Err(JwtError::Malformed));
+
+        // Tampered claims break the signature
+        let mut parts: Vec<String> = token.split('.').map(String::from).collect();
+        parts[1] = URL_SAFE_NO_PAD.encode(br#"{"sub":"00000000-0000-0000-0000-000000000000"}"#);
+        assert_eq!(keys.verify(&parts.join(".")), Err(JwtError::BadSignature));
+
+        let expired = JwtKeys { ttl: chrono::Duration::seconds(-1), ..hs256() };
+        assert_eq!(keys.verify(&expired.issue(Uuid::new_v4(), Uuid::new_v4())), Err(JwtError::Expired));
+
+        let foreign = JwtKeys { issuer: "someone else".to_string(), ..hs256() };
+        assert_eq!(keys.verify(&foreign.issue(Uuid::new_v4(), Uuid::new_v4())), Err(JwtError::WrongIssuer));
+    }
+}
@@ -4,22 +4,42 @@
 use pico_args::{Arguments, Error};
 use tracing::info;
 use tracing_subscriber::fmt::format::FmtSpan;
+use std::path::Path;
+use config::{Config, ConfigOverrides};
 
 mod models;
 mod server;
 mod database;
+mod events;
+mod notifications;
+mod grading;
+mod config;
+mod notifier;
+mod jwt;
+mod oidc;
+mod totp;
+mod rate_limit;
+mod password;
+mod storage;
+mod avatar;
 
 
 const HELP_MESSAGE : &str = r#"
 FLAGS:
--h, --help : Print this help message
--l, --logs : Enable logging into stdout
+-h, --help :                    Print this help message
+-l, --logs :                    Enable logging into stdout
+--print-config :                Print the resolved configuration and exit
 
 ARGUMENTS:
---key <[]> :                    The API key for the AI service (REQUIRED)
+--config <[]> :                 Path to the TOML config file. Default is config.toml (or DUOLINGO_CONFIG)
+--key <[]> :                    The API key for the AI service (REQUIRED unless the verifier is disabled)
 --ip-address <[].[].[].[]> :    The ip address to bind the server to. Default is 127.0.0.1
 --port <[]> :                   The port to bind the server to. Default is 8080
 --db-pool-size <[]> :           The size of the database connection pool. Default is 10
+--grading-workers <[]> :        How many answers can be graded at once. Default is 4
+
+Settings are taken from the config file, then environment variables, then these arguments,
+later ones take precedence. See config.example.toml for all settings and their variables.
 "#;
 
 #[tokio::main]
@@ -33,30 +53,98 @@ async fn main() {
         }
     };
     
-    if let Some(key) = args.api_key.as_deref() {
-        dbg!(&key);
-        std::env::set_var("DUOLINGO_APP_API_KEY", key);
-        dbg!(std::env::var("DUOLINGO_APP_API_KEY"));
+    // DATABASE_URL used to live in .env, it's still read from there
+    dotenvy::dotenv().ok();
+    
+    let env_overrides = match ConfigOverrides::from_env(|key| std::env::var(key).ok()) {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            eprintln!("Error reading environment: {}", e);
+            return;
+        }
+    };
+    
+    let config_path = args.config_path.clone()
+        .or_else(|| std::env::var("DUOLINGO_CONFIG").ok())
+        .unwrap_or(config::DEFAULT_CONFIG_PATH.to_string());
+    
+    let config = match Config::load(Path::new(&config_path), env_overrides, args.overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error loading configuration from {}: {}", config_path, e);
+            return;
+        }
+    };
+    
+    if args.print_config {
+        print!("{}", config.to_redacted_toml());
+        if let Err(e) = config.validate() {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+    
+    if let Err(e) = config.validate() {
+        eprintln!("Error: {}", e);
+        return;
     }
     
-    let db_pool = match database::get_database_connection_pool(args.db_pool_size).await {
+    if config.logging.enabled {
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(config.logging.level().unwrap_or(tracing::Level::INFO))
+            .with_span_events(FmtSpan::ENTER | FmtSpan::EXIT)
+            .finish();
+        tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+        
+        info!("logging enabled");
+    }
+    
+    match &config.session.token_key {
+        Some(key) => { models::session::init_token_key(key); },
+        None => eprintln!("Warning: no session.token_key (DUOLINGO_TOKEN_KEY) set, everyone will be logged out on restart"),
+    }
+    
+    match password::PasswordHasher::new(&config.password_hashing) {
+        Ok(hasher) => { password::init_hasher(hasher); },
+        Err(e) => {
+            eprintln!("Error setting up password hashing: {}", e);
+            return;
+        }
+    }
+    
+    if config.session.mode == config::AuthMode::Jwt {
+        match jwt::JwtKeys::from_config(&config.session.jwt) {
+            Ok(keys) => { jwt::init(keys); },
+            Err(e) => {
+                eprintln!("Error loading JWT keys: {:?}", e);
+                return;
+            }
+        }
+    }
+    
+    let db_pool = match database::connect(&config.database).await {
         Ok(pool) => pool,
         Err(e) => {
             eprintln!("Error connecting to database: {}", e);
             return;
         }
     };
-    if let Err(e) = server::start(db_pool, args.ip_address.as_deref(), args.port).await {
+    
+    if let Err(e) = models::session::Session::migrate_plaintext_tokens(&db_pool).await {
+        eprintln!("Error hashing stored session tokens: {}", e);
+        return;
+    }
+    
+    if let Err(e) = server::start(db_pool, config).await {
         eprintln!("Error starting server: {}", e);
     }
 }
 
 #[derive(Debug)]
 struct AppArgs {
-    ip_address : Option<String>,
-    port : Option<u32>,
-    db_pool_size : Option<u32>,
-    api_key: Option<String>
+    config_path : Option<String>,
+    print_config : bool,
+    overrides : ConfigOverrides
 }
 
 fn parse_args() -> Result<Option<AppArgs>, Error> {
@@ -67,20 +155,20 @@ fn parse_args() -> Result<Option<AppArgs>, Error> {
         return Ok(None);
     }
     
-    if p_args.contains(["-l", "--logs"]) {
-        let subscriber = tracing_subscriber::fmt()
-            .with_span_events(FmtSpan::ENTER | FmtSpan::EXIT)
-            .finish();
-        tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
-        
-        info!("logging enabled");
-    }
+    let logs = p_args.contains(["-l", "--logs"]);
     
     let args = AppArgs {
-        ip_address : p_args.opt_value_from_str("--ip-address")?,
-        port : p_args.opt_value_from_str("--port")?,
-        db_pool_size : p_args.opt_value_from_str("--db-pool-size")?,
-        api_key : p_args.opt_value_from_str("--key")?
+        config_path : p_args.opt_value_from_str("--config")?,
+        print_config : p_args.contains("--print-config"),
+        overrides : ConfigOverrides {
+            ip_address : p_args.opt_value_from_str("--ip-address")?,
+            port : p_args.opt_value_from_str("--port")?,
+            db_pool_size : p_args.opt_value_from_str("--db-pool-size")?,
+            grading_workers : p_args.opt_value_from_str("--grading-workers")?,
+            api_key : p_args.opt_value_from_str("--key")?,
+            logs : logs.then_some(true),
+            ..Default::default()
+        }
     };
     
     let remaining = p_args.finish();
@@ -1,6 +1,22 @@
 pub mod user;
 pub mod task;
 pub mod answer;
+pub mod friend;
+pub mod leaderboard;
+pub mod achievement;
+pub mod password_reset;
+pub mod verification;
+pub mod session;
+pub mod role;
+pub mod identity;
+pub mod two_factor;
+pub mod audit;
+pub mod lockout;
+pub mod account;
+pub mod profile;
+pub mod discovery;
+pub mod classroom;
+pub mod gradebook;
 
 pub mod serde_uuid_vec {
     use serde::{self, Serializer, Deserializer, Serialize, Deserialize};
@@ -0,0 +1,187 @@
+use serde::Serialize;
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::info;
+use super::achievement::UnlockedAchievement;
+use super::audit::AuditEntry;
+use super::identity::ExternalIdentity;
+use super::role::Role;
+use super::session::Session;
+use super::user::User;
+use crate::config::AccountDeletionConfig;
+
+/// An answer as the user sent it, with the grade if it got one
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ExportedAnswer {
+    pub id: Uuid,
+    pub task_id: Uuid,
+    pub content: Option<serde_json::Value>,
+    pub correct: Option<bool>,
+    pub explanation: Option<String>,
+    pub graded_time: Option<DateTime<Utc>>
+}
+
+/// Everything stored about a user, for `GET /user/:id/export`
+#[derive(Debug, Serialize)]
+pub struct AccountExport {
+    pub exported_time: DateTime<Utc>,
+    pub id: Uuid,
+    pub profile: User,
+    pub roles: Vec<Role>,
+    pub deletion_requested_time: Option<DateTime<Utc>>,
+    pub sessions: Vec<Session>,
+    pub identities: Vec<ExternalIdentity>,
+    pub blocked: Vec<Uuid>,
+    pub achievements: Vec<UnlockedAchievement>,
+    pub answers: Vec<ExportedAnswer>,
+    pub audit_log: Vec<AuditEntry>
+}
+
+pub mod database {
+    use super::*;
+
+    impl User {
+        /// When the user asked for the account to be deleted, `None` if they didn't
+        pub async fn deletion_requested(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+            let row = query!("SELECT deletion_requested_time FROM users WHERE id = ?", id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+
+            Ok(row.and_then(|row| row.deletion_requested_time).map(|time| time.and_utc()))
+        }
+
+        /// Signs the user out everywhere, the account is purged after the grace period unless they log in again.
+        /// Returns when it will be purged
+        pub async fn request_deletion(id: Uuid, config: &AccountDeletionConfig, transaction: &mut Transaction<'static, MySql>) -> Result<DateTime<Utc>, sqlx::Error> {
+            // Asking twice doesn't push the purge back
+            query!(
+                "UPDATE users SET deletion_requested_time = ? WHERE id = ? AND deletion_requested_time IS NULL",
+                Utc::now(),
+                id.to_string()
+            ).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM sessions WHERE user_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+
+            let requested = User::deletion_requested(id, transaction).await?.unwrap_or_else(Utc::now);
+            info!("User {} asked for their account to be deleted", id);
+            Ok(requested + config.grace_period())
+        }
+
+        /// Returns `true` if a deletion was pending
+        pub async fn cancel_deletion(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            let result = query!(
+                "UPDATE users SET deletion_requested_time = NULL WHERE id = ? AND deletion_requested_time IS NOT NULL",
+                id.to_string()
+            ).execute(transaction.as_mut()).await?;
+
+            Ok(result.rows_affected() > 0)
+        }
+
+        /// Deletes the accounts whose grace period is over.
+        /// Returns the purged users with their avatar version, the files have to be removed once this commits
+        pub async fn purge_deleted(config: &AccountDeletionConfig, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<(Uuid, Option<String>)>, sqlx::Error> {
+            let rows = query!(
+                "SELECT id, avatar FROM users WHERE deletion_requested_time < ?",
+                Utc::now() - config.grace_period()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            let mut purged = Vec::new();
+            for row in rows {
+                let id = Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid");
+                User::delete(id, transaction).await?;
+                info!("Account of user {} purged", id);
+                purged.push((id, row.avatar));
+            }
+
+            Ok(purged)
+        }
+
+        pub async fn export(id: Uuid, current_session: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<AccountExport, sqlx::Error> {
+            let answers = query!(
+                "SELECT a.id, a.task_id, a.content, r.correct AS `correct?`, r.explanation, r.creation_time AS `graded_time?`
+                FROM answers a LEFT JOIN answer_results r ON r.answer_id = a.id WHERE a.user_id = ?",
+                id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(AccountExport {
+                exported_time: Utc::now(),
+                id,
+                profile: User::read(id, transaction).await?,
+                roles: User::read_roles(id, transaction).await?,
+                deletion_requested_time: User::deletion_requested(id, transaction).await?,
+                sessions: Session::list(id, current_session, transaction).await?,
+                identities: User::read_identities(id, transaction).await?,
+                blocked: User::read_blocked(id, transaction).await?,
+                achievements: User::read_achievements(id, transaction).await?,
+                answers: answers.into_iter().map(|row| ExportedAnswer {
+                    id: Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid"),
+                    task_id: Uuid::parse_str(&row.task_id).expect("Couldn't parse string to Uuid"),
+                    content: row.content,
+                    correct: row.correct.map(|correct| correct != 0),
+                    explanation: row.explanation,
+                    graded_time: row.graded_time.map(|time| time.and_utc())
+                }).collect(),
+                audit_log: AuditEntry::read_for_user(id, u32::MAX, transaction).await?
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+
+        #[tokio::test]
+        async fn test_deletion_and_purge() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let config = AccountDeletionConfig::default();
+
+            let user = User::new("deletion_test".to_string(), "aaaaa".to_string(), Some("deletion@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+
+            let purge_time = User::request_deletion(user.id, &config, &mut tx).await.unwrap();
+            assert!(purge_time > Utc::now() + chrono::Duration::days(29));
+            assert!(User::deletion_requested(user.id, &mut tx).await.unwrap().is_some());
+
+            // Still in the grace period
+            User::purge_deleted(&config, &mut tx).await.unwrap();
+            assert!(User::read(user.id, &mut tx).await.is_ok());
+
+            assert!(User::cancel_deletion(user.id, &mut tx).await.unwrap());
+            assert!(!User::cancel_deletion(user.id, &mut tx).await.unwrap());
+
+            User::request_deletion(user.id, &config, &mut tx).await.unwrap();
+            let expired = AccountDeletionConfig { grace_days: 0, ..Default::default() };
+            query!("UPDATE users SET deletion_requested_time = ?, avatar = 'v1' WHERE id = ?", Utc::now() - chrono::Duration::seconds(1), user.id.to_string())
+                .execute(tx.as_mut()).await.unwrap();
+            // The avatar comes back so its files can be removed too
+            let purged = User::purge_deleted(&expired, &mut tx).await.unwrap();
+            assert!(purged.contains(&(user.id, Some("v1".to_string()))));
+            assert!(User::read(user.id, &mut tx).await.is_err());
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_export() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("export_test".to_string(), "aaaaa".to_string(), Some("export@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+
+            let export = User::export(user.id, Uuid::new_v4(), &mut tx).await.unwrap();
+            assert_eq!(export.id, user.id);
+            assert_eq!(export.profile.email.as_deref(), Some("export@test.com"));
+            assert!(export.answers.is_empty());
+
+            // The password hash never leaves the server
+            let json = serde_json::to_string(&export).unwrap();
+            assert!(!json.contains(&user.password_hash));
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
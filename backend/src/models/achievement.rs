@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::info;
+use super::user::User;
+use crate::events::DomainEvent;
+
+/// What has to happen for an achievement to unlock
+#[derive(Debug, PartialEq)]
+pub enum AchievementRule {
+    CorrectAnswers(u32),
+    Streak(u32),
+    AllTasksTagged(&'static str)
+}
+
+/// Events on which achievements get evaluated
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AchievementTrigger {
+    AnswerVerified,
+    Login
+}
+
+impl AchievementRule {
+    fn is_triggered_by(&self, trigger: AchievementTrigger) -> bool {
+        match self {
+            AchievementRule::CorrectAnswers(_) | AchievementRule::AllTasksTagged(_) => trigger == AchievementTrigger::AnswerVerified,
+            AchievementRule::Streak(_) => true
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AchievementDefinition {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub rule: AchievementRule
+}
+
+pub const ACHIEVEMENTS: &[AchievementDefinition] = &[
+    AchievementDefinition {
+        id: "first_correct_answer",
+        name: "Hello world",
+        description: "Answer a task correctly",
+        rule: AchievementRule::CorrectAnswers(1)
+    },
+    AchievementDefinition {
+        id: "correct_answers_100",
+        name: "Centurion",
+        description: "Answer 100 tasks correctly",
+        rule: AchievementRule::CorrectAnswers(100)
+    },
+    AchievementDefinition {
+        id: "streak_3",
+        name: "Warming up",
+        description: "Keep a 3-day streak",
+        rule: AchievementRule::Streak(3)
+    },
+    AchievementDefinition {
+        id: "streak_10",
+        name: "On fire",
+        description: "Keep a 10-day streak",
+        rule: AchievementRule::Streak(10)
+    },
+    AchievementDefinition {
+        id: "basics_solved",
+        name: "Back to basics",
+        description: "Solve all tasks tagged `basics`",
+        rule: AchievementRule::AllTasksTagged("basics")
+    },
+    AchievementDefinition {
+        id: "loops_solved",
+        name: "Loop de loop",
+        description: "Solve all tasks tagged `loops`",
+        rule: AchievementRule::AllTasksTagged("loops")
+    },
+];
+
+impl AchievementDefinition {
+    pub fn find(id: &str) -> Option<&'static AchievementDefinition> {
+        ACHIEVEMENTS.iter().find(|definition| definition.id == id)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct UnlockedAchievement {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub unlock_time: DateTime<Utc>
+}
+
+impl UnlockedAchievement {
+    fn new(definition: &AchievementDefinition, unlock_time: DateTime<Utc>) -> UnlockedAchievement {
+        UnlockedAchievement {
+            id: definition.id.to_string(),
+            name: definition.name.to_string(),
+            description: definition.description.to_string(),
+            unlock_time
+        }
+    }
+}
+
+pub mod database {
+    use super::*;
+
+    impl AchievementRule {
+        async fn is_satisfied(&self, user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            match self {
+                AchievementRule::CorrectAnswers(count) => {
+                    let record = query!(
+                        "SELECT COUNT(*) AS count FROM answers
+                        JOIN answer_results ON answer_results.answer_id = answers.id
+                        WHERE answers.user_id = ? AND answer_results.correct",
+                        user_id.to_string()
+                    ).fetch_one(transaction.as_mut()).await?;
+                    Ok(record.count >= *count as i64)
+                },
+                AchievementRule::Streak(days) => {
+                    Ok(User::read_streak(user_id, transaction).await? >= *days)
+                },
+                AchievementRule::AllTasksTagged(tag) => {
+                    let record = query!(
+                        "SELECT COUNT(*) AS total,
+                        COUNT(solved.task_id) AS solved
+                        FROM task_tags
+                        JOIN tags ON tags.id = task_tags.tag_id
+                        LEFT JOIN (
+                            SELECT DISTINCT answers.task_id FROM answers
+                            JOIN answer_results ON answer_results.answer_id = answers.id
+                            WHERE answers.user_id = ? AND answer_results.correct
+                        ) AS solved ON solved.task_id = task_tags.task_id
+                        WHERE tags.name = ?",
+                        user_id.to_string(),
+                        tag
+                    ).fetch_one(transaction.as_mut()).await?;
+                    Ok(record.total > 0 && record.total == record.solved)
+                }
+            }
+        }
+    }
+
+    impl User {
+        pub async fn read_achievements(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<UnlockedAchievement>, sqlx::Error> {
+            let rows = query!("SELECT achievement_id, unlock_time FROM user_achievements WHERE user_id = ? ORDER BY unlock_time", id.to_string())
+                .fetch_all(transaction.as_mut()).await?;
+
+            // Achievements removed from the definitions are skipped
+            Ok(rows.iter()
+                .filter_map(|row| AchievementDefinition::find(&row.achievement_id)
+                    .map(|definition| UnlockedAchievement::new(definition, row.unlock_time.and_utc())))
+                .collect())
+        }
+
+        /// Achievements unlocked by grading this answer, for the answer's result
+        pub async fn read_answer_achievements(answer_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<UnlockedAchievement>, sqlx::Error> {
+            let rows = query!("SELECT achievement_id, unlock_time FROM user_achievements WHERE answer_id = ? ORDER BY unlock_time", answer_id.to_string())
+                .fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.iter()
+                .filter_map(|row| AchievementDefinition::find(&row.achievement_id)
+                    .map(|definition| UnlockedAchievement::new(definition, row.unlock_time.and_utc())))
+                .collect())
+        }
+
+        pub async fn link_achievements_to_answer(id: Uuid, answer_id: Uuid, achievements: &[UnlockedAchievement], transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            for achievement in achievements {
+                query!(
+                    "UPDATE user_achievements SET answer_id = ? WHERE user_id = ? AND achievement_id = ?",
+                    answer_id.to_string(),
+                    id.to_string(),
+                    achievement.id
+                ).execute(transaction.as_mut()).await?;
+            }
+            Ok(())
+        }
+
+        /// Checks the rules relevant to `trigger` and persists newly unlocked achievements
+        pub async fn evaluate_achievements(id: Uuid, trigger: AchievementTrigger, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<UnlockedAchievement>, sqlx::Error> {
+            let unlocked_ids = query!("SELECT achievement_id FROM user_achievements WHERE user_id = ?", id.to_string())
+                .fetch_all(transaction.as_mut()).await?
+                .into_iter()
+                .map(|row| row.achievement_id)
+                .collect::<Vec<String>>();
+
+            let mut newly_unlocked = Vec::new();
+            for definition in ACHIEVEMENTS {
+                if unlocked_ids.iter().any(|unlocked_id| unlocked_id == definition.id) || !definition.rule.is_triggered_by(trigger) {
+                    continue;
+                }
+
+                if !definition.rule.is_satisfied(id, transaction).await? {
+                    continue;
+                }
+
+                let unlock_time = Utc::now().with_nanosecond(0).unwrap_or_else(Utc::now);
+                query!(
+                    "INSERT INTO user_achievements (user_id, achievement_id, unlock_time) VALUES (?, ?, ?)",
+                    id.to_string(),
+                    definition.id,
+                    unlock_time
+                ).execute(transaction.as_mut()).await?;
+
+                DomainEvent::AchievementUnlocked { user_id: id, achievement_id: definition.id.to_string() }
+                    .record(transaction).await?;
+
+                info!("User {} unlocked achievement {}", id, definition.id);
+                newly_unlocked.push(UnlockedAchievement::new(definition, unlock_time));
+            }
+
+            Ok(newly_unlocked)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+        use crate::models::answer::{Answer, AnswerContent, OpenQuestionAnswer, VerifyResult};
+
+        #[tokio::test]
+        async fn test_first_correct_answer() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("achievement_test".to_string(), "aaaaa".to_string(), Some("test@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+
+            let task_id = query!("SELECT id FROM tasks LIMIT 1").fetch_one(tx.as_mut()).await.unwrap().id;
+            let answer = Answer::new(user.id, Uuid::parse_str(&task_id).unwrap())
+                .solve(AnswerContent::OpenQuestion(OpenQuestionAnswer { content: "AAAAAAAAAAA".to_string() }));
+            answer.create(&mut tx).await.unwrap();
+
+            let unlocked = User::evaluate_achievements(user.id, AchievementTrigger::AnswerVerified, &mut tx).await.unwrap();
+            assert!(unlocked.iter().all(|achievement| achievement.id != "first_correct_answer"));
+
+            VerifyResult { correct: true, explanation: None }.save(&answer, &mut tx).await.unwrap();
+
+            let unlocked = User::evaluate_achievements(user.id, AchievementTrigger::AnswerVerified, &mut tx).await.unwrap();
+            assert!(unlocked.iter().any(|achievement| achievement.id == "first_correct_answer"));
+
+            User::link_achievements_to_answer(user.id, answer.id, &unlocked, &mut tx).await.unwrap();
+            let answer_achievements = User::read_answer_achievements(answer.id, &mut tx).await.unwrap();
+            assert_eq!(answer_achievements.len(), unlocked.len());
+            assert!(answer_achievements.iter().any(|achievement| achievement.id == "first_correct_answer"));
+
+            // Already unlocked achievements aren't reported again
+            let unlocked = User::evaluate_achievements(user.id, AchievementTrigger::AnswerVerified, &mut tx).await.unwrap();
+            assert!(unlocked.is_empty());
+
+            let achievements = User::read_achievements(user.id, &mut tx).await.unwrap();
+            assert!(achievements.iter().any(|achievement| achievement.id == "first_correct_answer"));
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_ids() {
+        for (i, definition) in ACHIEVEMENTS.iter().enumerate() {
+            assert!(ACHIEVEMENTS[i + 1..].iter().all(|other| other.id != definition.id), "Duplicate achievement id {}", definition.id);
+        }
+    }
+
+    #[test]
+    fn test_triggers() {
+        assert!(AchievementRule::Streak(3).is_triggered_by(AchievementTrigger::Login));
+        assert!(!AchievementRule::CorrectAnswers(1).is_triggered_by(AchievementTrigger::Login));
+        assert!(AchievementRule::AllTasksTagged("loops").is_triggered_by(AchievementTrigger::AnswerVerified));
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+
+/// Security relevant things that happened to an account
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum AuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    LoginThrottled,
+    AccountLocked,
+    AccountUnlocked,
+    PasswordChanged
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "LoginSucceeded",
+            AuditAction::LoginFailed => "LoginFailed",
+            AuditAction::LoginThrottled => "LoginThrottled",
+            AuditAction::AccountLocked => "AccountLocked",
+            AuditAction::AccountUnlocked => "AccountUnlocked",
+            AuditAction::PasswordChanged => "PasswordChanged"
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<AuditAction> {
+        match action {
+            "LoginSucceeded" => Some(AuditAction::LoginSucceeded),
+            "LoginFailed" => Some(AuditAction::LoginFailed),
+            "LoginThrottled" => Some(AuditAction::LoginThrottled),
+            "AccountLocked" => Some(AuditAction::AccountLocked),
+            "AccountUnlocked" => Some(AuditAction::AccountUnlocked),
+            "PasswordChanged" => Some(AuditAction::PasswordChanged),
+            _ => None
+        }
+    }
+}
+
+/// Kept when the user is deleted, `user_id` is empty for logins to accounts that don't exist
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub action: AuditAction,
+    pub ip_address: Option<String>,
+    pub details: Option<String>,
+    pub creation_time: DateTime<Utc>
+}
+
+pub mod database {
+    use super::*;
+
+    impl AuditEntry {
+        pub async fn record(user_id: Option<Uuid>, action: AuditAction, ip_address: Option<&str>, details: Option<String>, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!(
+                "INSERT INTO audit_log (id, user_id, action, ip_address, details, creation_time) VALUES (?, ?, ?, ?, ?, ?)",
+                Uuid::new_v4().to_string(),
+                user_id.map(|id| id.to_string()),
+                action.as_str(),
+                ip_address,
+                details,
+                Utc::now()
+            ).execute(transaction.as_mut()).await?;
+
+            Ok(())
+        }
+
+        /// Newest first
+        pub async fn read_for_user(user_id: Uuid, limit: u32, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<AuditEntry>, sqlx::Error> {
+            let rows = query!(
+                "SELECT id, action, ip_address, details, creation_time FROM audit_log WHERE user_id = ? ORDER BY creation_time DESC LIMIT ?",
+                user_id.to_string(),
+                limit
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.into_iter().map(|row| AuditEntry {
+                id: Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid"),
+                user_id: Some(user_id),
+                action: AuditAction::parse(&row.action).unwrap_or_else(|| panic!("Invalid audit action {} in the database", row.action)),
+                ip_address: row.ip_address,
+                details: row.details,
+                creation_time: row.creation_time.and_utc()
+            }).collect())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+
+        #[tokio::test]
+        async fn test_record_and_read() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user_id = Uuid::new_v4();
+            AuditEntry::record(Some(user_id), AuditAction::LoginFailed, Some("10.0.0.1"), None, &mut tx).await.unwrap();
+            AuditEntry::record(None, AuditAction::LoginFailed, Some("10.0.0.1"), Some("unknown".to_string()), &mut tx).await.unwrap();
+
+            let entries = AuditEntry::read_for_user(user_id, 10, &mut tx).await.unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].action, AuditAction::LoginFailed);
+            assert_eq!(entries[0].ip_address.as_deref(), Some("10.0.0.1"));
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,940 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use rand::Rng;
+use tracing::info;
+use super::user::User;
+
+/// A teacher's group of students, joined with the invite code
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Classroom {
+    pub id: Uuid,
+    pub teacher_id: Uuid,
+    pub name: String,
+    /// Only shown to the teacher
+    pub invite_code: Option<String>,
+    pub creation_time: DateTime<Utc>
+}
+
+/// A spot in the curriculum, the same numbers as in the user's progress
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct CurriculumLevel {
+    pub course: u32,
+    pub unit: u32,
+    pub sector: u32,
+    pub level: u32
+}
+
+impl CurriculumLevel {
+    /// Levels in other courses are never past this one
+    pub fn is_past(&self, other: &CurriculumLevel) -> bool {
+        self.course == other.course && (self.unit, self.sector, self.level) > (other.unit, other.sector, other.level)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AssignmentTarget {
+    /// Done once the student gets past the level
+    Level(CurriculumLevel),
+    /// Done once every task has a correct answer
+    Tasks(#[serde(with = "super::serde_uuid_vec")] Vec<Uuid>)
+}
+
+/// What happens to correct answers submitted after the due time but before the close time
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum LatePolicy {
+    #[default]
+    Accept,
+    /// Takes `percent_per_day` off the task's credit for every started day after the due time
+    Penalty { percent_per_day: u32 },
+    NoCredit
+}
+
+impl LatePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LatePolicy::Accept => "Accept",
+            LatePolicy::Penalty { .. } => "Penalty",
+            LatePolicy::NoCredit => "NoCredit"
+        }
+    }
+
+    /// Share of the full credit for a correct answer submitted at `submission_time`
+    pub fn credit(&self, due_time: DateTime<Utc>, submission_time: DateTime<Utc>) -> f64 {
+        if submission_time <= due_time {
+            return 1.0;
+        }
+
+        match self {
+            LatePolicy::Accept => 1.0,
+            LatePolicy::Penalty { percent_per_day } => {
+                let late = submission_time - due_time;
+                let days = (late.num_seconds() + 86_399) / 86_400;
+                (100 - (*percent_per_day as i64 * days).min(100)) as f64 / 100.0
+            },
+            LatePolicy::NoCredit => 0.0
+        }
+    }
+}
+
+/// Who the assignment is for and which answers count, everything is optional
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AssignmentRules {
+    /// Every student of the classroom if left out
+    pub assignees: Option<Vec<Uuid>>,
+    /// Answers submitted before are ignored
+    pub open_time: Option<DateTime<Utc>>,
+    /// Answers submitted after are ignored, until then the late policy applies
+    pub close_time: Option<DateTime<Utc>>,
+    /// Answers to a task after this many are ignored
+    pub max_attempts: Option<u32>,
+    pub late_policy: LatePolicy
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Assignment {
+    pub id: Uuid,
+    pub classroom_id: Uuid,
+    pub title: String,
+    pub target: AssignmentTarget,
+    pub due_time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub rules: AssignmentRules,
+    pub creation_time: DateTime<Utc>
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum AssignmentStatus {
+    Pending,
+    Completed,
+    CompletedLate,
+    Overdue
+}
+
+/// One answer of a student to a task of an assignment, `correct` is `None` until it's graded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    pub answer_id: Uuid,
+    pub task_id: Uuid,
+    pub correct: Option<bool>,
+    pub submission_time: DateTime<Utc>
+}
+
+/// The task counts are only there for task set assignments
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize, PartialEq)]
+pub struct AssignmentProgress {
+    pub assignment_id: Uuid,
+    pub status: AssignmentStatus,
+    pub tasks_total: Option<u32>,
+    pub tasks_answered: Option<u32>,
+    pub tasks_correct: Option<u32>
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RosterEntry {
+    pub user_id: Uuid,
+    pub username: String,
+    pub join_time: DateTime<Utc>,
+    pub position: CurriculumLevel,
+    pub assignments: Vec<AssignmentProgress>,
+    pub assignments_completed: u32,
+    /// All of the student's graded answers, not just the ones for assignments
+    pub answers_graded: u32,
+    pub answers_correct: u32,
+    /// Share of the graded answers that were correct, `None` without any
+    pub correctness: Option<f64>
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Roster {
+    pub classroom_id: Uuid,
+    pub assignments: Vec<Assignment>,
+    pub students: Vec<RosterEntry>
+}
+
+#[derive(Debug)]
+pub enum ClassroomError {
+    NoSuchMember,
+    BadInviteCode,
+    AlreadyMember,
+    InvalidName,
+    NoTasks,
+    NoSuchTask,
+    NoAssignees,
+    InvalidSchedule,
+    InvalidAttemptLimit,
+    InvalidLatePolicy,
+    NotGradable,
+    DatabaseError(sqlx::Error)
+}
+
+impl From<sqlx::Error> for ClassroomError {
+    fn from(e: sqlx::Error) -> Self {
+        ClassroomError::DatabaseError(e)
+    }
+}
+
+pub const MAX_NAME_LENGTH: usize = 128;
+
+const INVITE_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const INVITE_CODE_LENGTH: usize = 8;
+
+/// Without the characters that are easy to mix up when read off a whiteboard
+pub fn generate_invite_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..INVITE_CODE_LENGTH)
+        .map(|_| INVITE_CODE_ALPHABET[rng.gen_range(0..INVITE_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Students type the code however they like
+pub fn normalize_invite_code(code: &str) -> String {
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_uppercase()
+}
+
+fn check_name(name: &str) -> Result<String, ClassroomError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ClassroomError::InvalidName);
+    }
+    Ok(name.to_string())
+}
+
+// MySQL DATETIME has no sub-second precision
+fn now() -> DateTime<Utc> {
+    Utc::now().with_nanosecond(0).unwrap_or_else(Utc::now)
+}
+
+fn trim_nanos(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.with_nanosecond(0).unwrap_or(time)
+}
+
+fn unique(ids: Vec<Uuid>) -> Vec<Uuid> {
+    let mut unique = Vec::with_capacity(ids.len());
+    for id in ids {
+        if !unique.contains(&id) {
+            unique.push(id);
+        }
+    }
+    unique
+}
+
+impl Classroom {
+    pub fn new(teacher_id: Uuid, name: &str) -> Result<Classroom, ClassroomError> {
+        Ok(Classroom {
+            id: Uuid::new_v4(),
+            teacher_id,
+            name: check_name(name)?,
+            invite_code: Some(generate_invite_code()),
+            creation_time: now()
+        })
+    }
+}
+
+impl Assignment {
+    pub fn new(classroom_id: Uuid, title: &str, target: AssignmentTarget, due_time: DateTime<Utc>, rules: AssignmentRules) -> Result<Assignment, ClassroomError> {
+        let target = match target {
+            AssignmentTarget::Tasks(tasks) if tasks.is_empty() => return Err(ClassroomError::NoTasks),
+            // The same task twice is only counted once
+            AssignmentTarget::Tasks(tasks) => AssignmentTarget::Tasks(unique(tasks)),
+            target => target
+        };
+
+        let due_time = trim_nanos(due_time);
+        let open_time = rules.open_time.map(trim_nanos);
+        let close_time = rules.close_time.map(trim_nanos);
+        if open_time.is_some_and(|open_time| open_time >= due_time) || close_time.is_some_and(|close_time| close_time < due_time) {
+            return Err(ClassroomError::InvalidSchedule);
+        }
+        if rules.max_attempts == Some(0) {
+            return Err(ClassroomError::InvalidAttemptLimit);
+        }
+        if let LatePolicy::Penalty { percent_per_day: 0 | 101.. } = rules.late_policy {
+            return Err(ClassroomError::InvalidLatePolicy);
+        }
+
+        let assignees = match rules.assignees {
+            Some(assignees) if assignees.is_empty() => return Err(ClassroomError::NoAssignees),
+            assignees => assignees.map(unique)
+        };
+
+        Ok(Assignment {
+            id: Uuid::new_v4(),
+            classroom_id,
+            title: check_name(title)?,
+            target,
+            due_time,
+            rules: AssignmentRules { assignees, open_time, close_time, ..rules },
+            creation_time: now()
+        })
+    }
+
+    pub fn is_assigned(&self, user_id: Uuid) -> bool {
+        self.rules.assignees.as_ref().is_none_or(|assignees| assignees.contains(&user_id))
+    }
+
+    /// The answers to `task_id` that count, oldest first: the ones in the open to close window, up to the attempt limit
+    pub fn counted_attempts<'a>(&self, task_id: Uuid, attempts: &'a [Attempt]) -> Vec<&'a Attempt> {
+        let mut counted: Vec<&Attempt> = attempts.iter()
+            .filter(|attempt| attempt.task_id == task_id)
+            .filter(|attempt| self.rules.open_time.is_none_or(|open_time| attempt.submission_time >= open_time))
+            .filter(|attempt| self.rules.close_time.is_none_or(|close_time| attempt.submission_time <= close_time))
+            .collect();
+        counted.sort_by_key(|attempt| attempt.submission_time);
+        if let Some(max_attempts) = self.rules.max_attempts {
+            counted.truncate(max_attempts as usize);
+        }
+        counted
+    }
+
+    /// Where a student with `position` and `attempts` on the assignment's tasks stands at `now`
+    pub fn progress(&self, position: &CurriculumLevel, attempts: &[Attempt], now: DateTime<Utc>) -> AssignmentProgress {
+        let unfinished = if now > self.due_time { AssignmentStatus::Overdue } else { AssignmentStatus::Pending };
+
+        let tasks = match &self.target {
+            AssignmentTarget::Level(level) => {
+                return AssignmentProgress {
+                    assignment_id: self.id,
+                    status: if position.is_past(level) { AssignmentStatus::Completed } else { unfinished },
+                    tasks_total: None,
+                    tasks_answered: None,
+                    tasks_correct: None
+                };
+            },
+            AssignmentTarget::Tasks(tasks) => tasks
+        };
+
+        let mut answered = 0;
+        // The assignment is done when the last task got its first correct answer
+        let mut first_correct = Vec::new();
+        for task_id in tasks {
+            let task_attempts = self.counted_attempts(*task_id, attempts);
+            if !task_attempts.is_empty() {
+                answered += 1;
+            }
+            if let Some(time) = task_attempts.iter().filter(|attempt| attempt.correct == Some(true)).map(|attempt| attempt.submission_time).min() {
+                first_correct.push(time);
+            }
+        }
+
+        let status = match first_correct.iter().max() {
+            Some(done) if first_correct.len() == tasks.len() && *done <= self.due_time => AssignmentStatus::Completed,
+            Some(_) if first_correct.len() == tasks.len() => AssignmentStatus::CompletedLate,
+            _ => unfinished
+        };
+
+        AssignmentProgress {
+            assignment_id: self.id,
+            status,
+            tasks_total: Some(tasks.len() as u32),
+            tasks_answered: Some(answered),
+            tasks_correct: Some(first_correct.len() as u32)
+        }
+    }
+}
+
+pub mod database {
+    use super::*;
+
+    fn parse_classroom(id: &str, teacher_id: &str, name: String, invite_code: Option<String>, creation_time: NaiveDateTime) -> Classroom {
+        Classroom {
+            id: Uuid::parse_str(id).expect("Couldn't parse string to Uuid"),
+            teacher_id: Uuid::parse_str(teacher_id).expect("Couldn't parse string to Uuid"),
+            name,
+            invite_code,
+            creation_time: creation_time.and_utc()
+        }
+    }
+
+    async fn unused_invite_code(transaction: &mut Transaction<'static, MySql>) -> Result<String, sqlx::Error> {
+        loop {
+            let code = generate_invite_code();
+            let taken = query!("SELECT id FROM classrooms WHERE invite_code = ?", code)
+                .fetch_optional(transaction.as_mut()).await?;
+            if taken.is_none() {
+                return Ok(code);
+            }
+        }
+    }
+
+    impl Classroom {
+        pub async fn create(&mut self, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            let code = unused_invite_code(transaction).await?;
+            query!(
+                "INSERT INTO classrooms (id, teacher_id, name, invite_code, creation_time) VALUES (?, ?, ?, ?, ?)",
+                self.id.to_string(),
+                self.teacher_id.to_string(),
+                self.name,
+                code,
+                self.creation_time
+            ).execute(transaction.as_mut()).await?;
+            self.invite_code = Some(code);
+
+            info!("User {} created classroom {}", self.teacher_id, self.id);
+            Ok(())
+        }
+
+        /// With the invite code, it's up to the caller to hide it from students
+        pub async fn read(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Option<Classroom>, sqlx::Error> {
+            let row = query!("SELECT * FROM classrooms WHERE id = ?", id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+
+            Ok(row.map(|row| parse_classroom(&row.id, &row.teacher_id, row.name, Some(row.invite_code), row.creation_time)))
+        }
+
+        /// Classrooms the user teaches or is a student in, invite codes only for the former
+        pub async fn read_for_user(user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<Classroom>, sqlx::Error> {
+            let rows = query!(
+                "SELECT c.id, c.teacher_id, c.name, c.invite_code, c.creation_time FROM classrooms c
+                WHERE c.teacher_id = ? OR c.id IN (SELECT classroom_id FROM classroom_members WHERE user_id = ?)
+                ORDER BY c.creation_time, c.name",
+                user_id.to_string(),
+                user_id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.into_iter().map(|row| {
+                let invite_code = (row.teacher_id == user_id.to_string()).then_some(row.invite_code);
+                parse_classroom(&row.id, &row.teacher_id, row.name, invite_code, row.creation_time)
+            }).collect())
+        }
+
+        pub async fn is_member(&self, user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            let row = query!("SELECT user_id FROM classroom_members WHERE classroom_id = ? AND user_id = ?", self.id.to_string(), user_id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+            Ok(row.is_some())
+        }
+
+        /// Returns the classroom without its invite code
+        pub async fn join(invite_code: &str, user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Classroom, ClassroomError> {
+            let row = query!("SELECT * FROM classrooms WHERE invite_code = ?", normalize_invite_code(invite_code))
+                .fetch_optional(transaction.as_mut()).await?
+                .ok_or(ClassroomError::BadInviteCode)?;
+            let classroom = parse_classroom(&row.id, &row.teacher_id, row.name, None, row.creation_time);
+
+            if classroom.teacher_id == user_id || classroom.is_member(user_id, transaction).await? {
+                return Err(ClassroomError::AlreadyMember);
+            }
+
+            query!(
+                "INSERT INTO classroom_members (classroom_id, user_id, join_time) VALUES (?, ?, ?)",
+                classroom.id.to_string(),
+                user_id.to_string(),
+                now()
+            ).execute(transaction.as_mut()).await?;
+
+            info!("User {} joined classroom {}", user_id, classroom.id);
+            Ok(classroom)
+        }
+
+        /// The old code stops working, students who already joined stay
+        pub async fn regenerate_invite_code(&mut self, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            let code = unused_invite_code(transaction).await?;
+            query!("UPDATE classrooms SET invite_code = ? WHERE id = ?", code, self.id.to_string())
+                .execute(transaction.as_mut()).await?;
+            self.invite_code = Some(code);
+            Ok(())
+        }
+
+        pub async fn remove_member(&self, user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), ClassroomError> {
+            let result = query!("DELETE FROM classroom_members WHERE classroom_id = ? AND user_id = ?", self.id.to_string(), user_id.to_string())
+                .execute(transaction.as_mut()).await?;
+            if result.rows_affected() == 0 {
+                return Err(ClassroomError::NoSuchMember);
+            }
+
+            info!("User {} left classroom {}", user_id, self.id);
+            Ok(())
+        }
+
+        /// Along with its members and assignments
+        pub async fn delete(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!("DELETE FROM assignment_tasks WHERE assignment_id IN (SELECT id FROM assignments WHERE classroom_id = ?)", id.to_string())
+                .execute(transaction.as_mut()).await?;
+            query!("DELETE FROM assignment_assignees WHERE assignment_id IN (SELECT id FROM assignments WHERE classroom_id = ?)", id.to_string())
+                .execute(transaction.as_mut()).await?;
+            query!("DELETE FROM assignments WHERE classroom_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+            query!("DELETE FROM classroom_members WHERE classroom_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+            query!("DELETE FROM classrooms WHERE id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+
+            info!("Classroom {} deleted", id);
+            Ok(())
+        }
+
+        /// Every student with their progress on every assignment, computed from their answers up to `now`
+        pub async fn read_roster(&self, now: DateTime<Utc>, transaction: &mut Transaction<'static, MySql>) -> Result<Roster, sqlx::Error> {
+            let assignments = Assignment::read_all(self.id, transaction).await?;
+
+            let members = query!(
+                "SELECT m.user_id, m.join_time, u.username, p.course, p.unit, p.sector, p.level FROM classroom_members m
+                JOIN users u ON u.id = m.user_id JOIN user_progress p ON p.user_id = m.user_id
+                WHERE m.classroom_id = ? ORDER BY u.username",
+                self.id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            let attempt_rows = query!(
+                "SELECT a.id, a.user_id, a.task_id, a.creation_time, r.correct FROM answers a
+                JOIN classroom_members m ON m.user_id = a.user_id AND m.classroom_id = ?
+                LEFT JOIN answer_results r ON r.answer_id = a.id
+                WHERE a.task_id IN (SELECT t.task_id FROM assignment_tasks t JOIN assignments s ON s.id = t.assignment_id WHERE s.classroom_id = ?)",
+                self.id.to_string(),
+                self.id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            let mut attempts: HashMap<String, Vec<Attempt>> = HashMap::new();
+            for row in attempt_rows {
+                attempts.entry(row.user_id).or_default().push(Attempt {
+                    answer_id: Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid"),
+                    task_id: Uuid::parse_str(&row.task_id).expect("Couldn't parse string to Uuid"),
+                    correct: row.correct.map(|correct| correct != 0),
+                    submission_time: row.creation_time.and_utc()
+                });
+            }
+
+            let counts = query!(
+                "SELECT a.user_id, COUNT(*) AS `graded!`, CAST(COALESCE(SUM(r.correct), 0) AS SIGNED) AS `correct!` FROM answers a
+                JOIN classroom_members m ON m.user_id = a.user_id AND m.classroom_id = ?
+                JOIN answer_results r ON r.answer_id = a.id
+                GROUP BY a.user_id",
+                self.id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+            let counts: HashMap<String, (u32, u32)> = counts.into_iter()
+                .map(|row| (row.user_id, (row.graded as u32, row.correct as u32)))
+                .collect();
+
+            let students = members.into_iter().map(|row| {
+                let user_id = Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid");
+                let position = CurriculumLevel { course: row.course as u32, unit: row.unit as u32, sector: row.sector as u32, level: row.level as u32 };
+                let student_attempts = attempts.get(&row.user_id).map(Vec::as_slice).unwrap_or_default();
+                let progress: Vec<AssignmentProgress> = assignments.iter()
+                    .filter(|assignment| assignment.is_assigned(user_id))
+                    .map(|assignment| assignment.progress(&position, student_attempts, now))
+                    .collect();
+                let (graded, correct) = counts.get(&row.user_id).copied().unwrap_or_default();
+
+                RosterEntry {
+                    user_id,
+                    username: row.username,
+                    join_time: row.join_time.and_utc(),
+                    position,
+                    assignments_completed: progress.iter()
+                        .filter(|progress| matches!(progress.status, AssignmentStatus::Completed | AssignmentStatus::CompletedLate))
+                        .count() as u32,
+                    assignments: progress,
+                    answers_graded: graded,
+                    answers_correct: correct,
+                    correctness: if graded > 0 { Some(correct as f64 / graded as f64) } else { None }
+                }
+            }).collect();
+
+            Ok(Roster { classroom_id: self.id, assignments, students })
+        }
+    }
+
+    impl Assignment {
+        pub async fn create(&self, transaction: &mut Transaction<'static, MySql>) -> Result<(), ClassroomError> {
+            let level = match &self.target {
+                AssignmentTarget::Level(level) => Some(level),
+                AssignmentTarget::Tasks(_) => None
+            };
+
+            let late_penalty = match self.rules.late_policy {
+                LatePolicy::Penalty { percent_per_day } => Some(percent_per_day),
+                _ => None
+            };
+
+            query!(
+                "INSERT INTO assignments (id, classroom_id, title, course, unit, sector, level, open_time, due_time, close_time, max_attempts, late_policy, late_penalty, creation_time)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                self.id.to_string(),
+                self.classroom_id.to_string(),
+                self.title,
+                level.map(|level| level.course),
+                level.map(|level| level.unit),
+                level.map(|level| level.sector),
+                level.map(|level| level.level),
+                self.rules.open_time,
+                self.due_time,
+                self.rules.close_time,
+                self.rules.max_attempts,
+                self.rules.late_policy.as_str(),
+                late_penalty,
+                self.creation_time
+            ).execute(transaction.as_mut()).await?;
+
+            for user_id in self.rules.assignees.iter().flatten() {
+                let member = query!("SELECT user_id FROM classroom_members WHERE classroom_id = ? AND user_id = ?", self.classroom_id.to_string(), user_id.to_string())
+                    .fetch_optional(transaction.as_mut()).await?;
+                if member.is_none() {
+                    return Err(ClassroomError::NoSuchMember);
+                }
+
+                query!("INSERT INTO assignment_assignees (assignment_id, user_id) VALUES (?, ?)", self.id.to_string(), user_id.to_string())
+                    .execute(transaction.as_mut()).await?;
+            }
+
+            if let AssignmentTarget::Tasks(tasks) = &self.target {
+                for (position, task_id) in tasks.iter().enumerate() {
+                    let task = query!("SELECT id FROM tasks WHERE id = ?", task_id.to_string())
+                        .fetch_optional(transaction.as_mut()).await?;
+                    if task.is_none() {
+                        return Err(ClassroomError::NoSuchTask);
+                    }
+
+                    query!(
+                        "INSERT INTO assignment_tasks (assignment_id, task_id, position) VALUES (?, ?, ?)",
+                        self.id.to_string(),
+                        task_id.to_string(),
+                        position as u32
+                    ).execute(transaction.as_mut()).await?;
+                }
+            }
+
+            info!("Assignment {} created in classroom {}", self.id, self.classroom_id);
+            Ok(())
+        }
+
+        /// Earliest due first
+        pub async fn read_all(classroom_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<Assignment>, sqlx::Error> {
+            let rows = query!("SELECT * FROM assignments WHERE classroom_id = ? ORDER BY due_time, title", classroom_id.to_string())
+                .fetch_all(transaction.as_mut()).await?;
+
+            let task_rows = query!(
+                "SELECT t.assignment_id, t.task_id FROM assignment_tasks t JOIN assignments s ON s.id = t.assignment_id
+                WHERE s.classroom_id = ? ORDER BY t.position",
+                classroom_id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            let mut tasks: HashMap<String, Vec<Uuid>> = HashMap::new();
+            for row in task_rows {
+                tasks.entry(row.assignment_id).or_default()
+                    .push(Uuid::parse_str(&row.task_id).expect("Couldn't parse string to Uuid"));
+            }
+
+            let assignee_rows = query!(
+                "SELECT a.assignment_id, a.user_id FROM assignment_assignees a JOIN assignments s ON s.id = a.assignment_id
+                WHERE s.classroom_id = ?",
+                classroom_id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            let mut assignees: HashMap<String, Vec<Uuid>> = HashMap::new();
+            for row in assignee_rows {
+                assignees.entry(row.assignment_id).or_default()
+                    .push(Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid"));
+            }
+
+            Ok(rows.into_iter().map(|row| {
+                let target = match (row.course, row.unit, row.sector, row.level) {
+                    (Some(course), Some(unit), Some(sector), Some(level)) => AssignmentTarget::Level(CurriculumLevel {
+                        course: course as u32,
+                        unit: unit as u32,
+                        sector: sector as u32,
+                        level: level as u32
+                    }),
+                    _ => AssignmentTarget::Tasks(tasks.remove(&row.id).unwrap_or_default())
+                };
+
+                let late_policy = match (row.late_policy.as_str(), row.late_penalty) {
+                    ("Accept", _) => LatePolicy::Accept,
+                    ("Penalty", Some(percent_per_day)) => LatePolicy::Penalty { percent_per_day: percent_per_day as u32 },
+                    ("NoCredit", _) => LatePolicy::NoCredit,
+                    (policy, _) => panic!("Invalid late policy {} in the database", policy)
+                };
+
+                Assignment {
+                    id: Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid"),
+                    classroom_id,
+                    title: row.title,
+                    target,
+                    due_time: row.due_time.and_utc(),
+                    rules: AssignmentRules {
+                        assignees: assignees.remove(&row.id),
+                        open_time: row.open_time.map(|time| time.and_utc()),
+                        close_time: row.close_time.map(|time| time.and_utc()),
+                        max_attempts: row.max_attempts.map(|max_attempts| max_attempts as u32),
+                        late_policy
+                    },
+                    creation_time: row.creation_time.and_utc()
+                }
+            }).collect())
+        }
+
+        pub async fn read(classroom_id: Uuid, id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Option<Assignment>, sqlx::Error> {
+            Ok(Assignment::read_all(classroom_id, transaction).await?
+                .into_iter()
+                .find(|assignment| assignment.id == id))
+        }
+
+        /// `false` if the classroom has no such assignment
+        pub async fn delete(classroom_id: Uuid, id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            let row = query!("SELECT id FROM assignments WHERE id = ? AND classroom_id = ?", id.to_string(), classroom_id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+            if row.is_none() {
+                return Ok(false);
+            }
+
+            query!("DELETE FROM assignment_tasks WHERE assignment_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+            query!("DELETE FROM assignment_assignees WHERE assignment_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+            query!("DELETE FROM assignments WHERE id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+
+            info!("Assignment {} deleted", id);
+            Ok(true)
+        }
+    }
+
+    impl User {
+        /// Memberships and every classroom the user teaches, for when the account is deleted
+        pub async fn delete_classrooms(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!("DELETE FROM classroom_members WHERE user_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+            query!("DELETE FROM assignment_assignees WHERE user_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+
+            let taught = query!("SELECT id FROM classrooms WHERE teacher_id = ?", id.to_string())
+                .fetch_all(transaction.as_mut()).await?;
+            for row in taught {
+                Classroom::delete(Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid"), transaction).await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+        use crate::models::answer::{Answer, AnswerContent, PartsAnswer, VerifyResult};
+
+        async fn create_user(username: &str, tx: &mut Transaction<'static, MySql>) -> User {
+            let user = User::new(username.to_string(), "aaaaa".to_string(), Some(format!("{}@test.com", username)), None, tx).await.unwrap();
+            user.create(tx).await.unwrap();
+            user
+        }
+
+        #[tokio::test]
+        async fn test_membership() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let teacher = create_user("class_teacher", &mut tx).await;
+            let student = create_user("class_student", &mut tx).await;
+
+            let mut classroom = Classroom::new(teacher.id, "  Java 101 ").unwrap();
+            classroom.create(&mut tx).await.unwrap();
+            assert_eq!(classroom.name, "Java 101");
+            let code = classroom.invite_code.clone().unwrap();
+
+            assert!(matches!(Classroom::join("nope", student.id, &mut tx).await, Err(ClassroomError::BadInviteCode)));
+            assert!(matches!(Classroom::join(&code, teacher.id, &mut tx).await, Err(ClassroomError::AlreadyMember)));
+            let joined = Classroom::join(&code.to_lowercase(), student.id, &mut tx).await.unwrap();
+            assert_eq!(joined.id, classroom.id);
+            assert_eq!(joined.invite_code, None);
+            assert!(matches!(Classroom::join(&code, student.id, &mut tx).await, Err(ClassroomError::AlreadyMember)));
+
+            let classrooms = Classroom::read_for_user(student.id, &mut tx).await.unwrap();
+            assert_eq!(classrooms.len(), 1);
+            assert_eq!(classrooms[0].invite_code, None);
+            assert_eq!(Classroom::read_for_user(teacher.id, &mut tx).await.unwrap()[0].invite_code, Some(code.clone()));
+
+            classroom.regenerate_invite_code(&mut tx).await.unwrap();
+            assert_ne!(classroom.invite_code, Some(code.clone()));
+            assert!(classroom.is_member(student.id, &mut tx).await.unwrap());
+
+            classroom.remove_member(student.id, &mut tx).await.unwrap();
+            assert!(matches!(classroom.remove_member(student.id, &mut tx).await, Err(ClassroomError::NoSuchMember)));
+
+            User::delete_classrooms(teacher.id, &mut tx).await.unwrap();
+            assert_eq!(Classroom::read(classroom.id, &mut tx).await.unwrap(), None);
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_roster() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let teacher = create_user("roster_teacher", &mut tx).await;
+            let student = create_user("roster_student", &mut tx).await;
+            let mut classroom = Classroom::new(teacher.id, "Roster").unwrap();
+            classroom.create(&mut tx).await.unwrap();
+            Classroom::join(classroom.invite_code.as_deref().unwrap(), student.id, &mut tx).await.unwrap();
+
+            let task_id = query!("SELECT task_id FROM task_correct_answer LIMIT 1").fetch_one(tx.as_mut()).await.unwrap().task_id;
+            let task_id = Uuid::parse_str(&task_id).unwrap();
+
+            let due_time = Utc::now() + chrono::Duration::days(7);
+            let tasks = Assignment::new(classroom.id, "Homework", AssignmentTarget::Tasks(vec![task_id]), due_time, AssignmentRules::default()).unwrap();
+            tasks.create(&mut tx).await.unwrap();
+            let level = CurriculumLevel { course: 0, unit: 0, sector: 0, level: 0 };
+            Assignment::new(classroom.id, "Level", AssignmentTarget::Level(level), due_time, AssignmentRules::default()).unwrap().create(&mut tx).await.unwrap();
+
+            let missing = Assignment::new(classroom.id, "Missing", AssignmentTarget::Tasks(vec![Uuid::new_v4()]), due_time, AssignmentRules::default()).unwrap();
+            assert!(matches!(missing.create(&mut tx).await, Err(ClassroomError::NoSuchTask)));
+
+            let answer = Answer::new(student.id, task_id).solve(AnswerContent::FromParts(PartsAnswer { parts: vec![] }));
+            answer.create(&mut tx).await.unwrap();
+            VerifyResult { correct: true, explanation: None }.save(&answer, &mut tx).await.unwrap();
+
+            let roster = classroom.read_roster(Utc::now(), &mut tx).await.unwrap();
+            assert_eq!(roster.assignments.len(), 2);
+            assert_eq!(roster.students.len(), 1);
+            let entry = &roster.students[0];
+            assert_eq!(entry.user_id, student.id);
+            assert_eq!((entry.answers_graded, entry.answers_correct, entry.correctness), (1, 1, Some(1.0)));
+
+            let homework = entry.assignments.iter().find(|progress| progress.assignment_id == tasks.id).unwrap();
+            assert_eq!(homework.status, AssignmentStatus::Completed);
+            assert_eq!(entry.assignments_completed, 1);
+
+            assert!(Assignment::delete(classroom.id, tasks.id, &mut tx).await.unwrap());
+            assert!(!Assignment::delete(classroom.id, tasks.id, &mut tx).await.unwrap());
+            assert_eq!(Assignment::read_all(classroom.id, &mut tx).await.unwrap().len(), 1);
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn attempt(task_id: Uuid, correct: Option<bool>, submission_time: DateTime<Utc>) -> Attempt {
+        Attempt { answer_id: Uuid::new_v4(), task_id, correct, submission_time }
+    }
+
+    #[test]
+    fn test_invite_code() {
+        let code = generate_invite_code();
+        assert_eq!(code.len(), INVITE_CODE_LENGTH);
+        assert!(code.bytes().all(|c| INVITE_CODE_ALPHABET.contains(&c)));
+        assert_eq!(normalize_invite_code(" abcd-2345 "), "ABCD2345");
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(matches!(Classroom::new(Uuid::new_v4(), "   "), Err(ClassroomError::InvalidName)));
+        assert!(matches!(Classroom::new(Uuid::new_v4(), &"a".repeat(MAX_NAME_LENGTH + 1)), Err(ClassroomError::InvalidName)));
+        assert!(matches!(Assignment::new(Uuid::new_v4(), "Empty", AssignmentTarget::Tasks(vec![]), Utc::now(), AssignmentRules::default()), Err(ClassroomError::NoTasks)));
+
+        let task_id = Uuid::new_v4();
+        let assignment = Assignment::new(Uuid::new_v4(), "Twice", AssignmentTarget::Tasks(vec![task_id, task_id]), Utc::now(), AssignmentRules::default()).unwrap();
+        assert_eq!(assignment.target, AssignmentTarget::Tasks(vec![task_id]));
+    }
+
+    #[test]
+    fn test_rules() {
+        let due = Utc::now().with_nanosecond(0).unwrap();
+        let new = |rules: AssignmentRules| Assignment::new(Uuid::new_v4(), "Rules", AssignmentTarget::Tasks(vec![Uuid::new_v4()]), due, rules);
+
+        assert!(matches!(new(AssignmentRules { open_time: Some(due), ..Default::default() }), Err(ClassroomError::InvalidSchedule)));
+        assert!(matches!(new(AssignmentRules { close_time: Some(due - Duration::seconds(1)), ..Default::default() }), Err(ClassroomError::InvalidSchedule)));
+        assert!(matches!(new(AssignmentRules { max_attempts: Some(0), ..Default::default() }), Err(ClassroomError::InvalidAttemptLimit)));
+        assert!(matches!(new(AssignmentRules { late_policy: LatePolicy::Penalty { percent_per_day: 101 }, ..Default::default() }), Err(ClassroomError::InvalidLatePolicy)));
+        assert!(matches!(new(AssignmentRules { assignees: Some(vec![]), ..Default::default() }), Err(ClassroomError::NoAssignees)));
+
+        let student = Uuid::new_v4();
+        let assignment = new(AssignmentRules { assignees: Some(vec![student, student]), ..Default::default() }).unwrap();
+        assert_eq!(assignment.rules.assignees, Some(vec![student]));
+        assert!(assignment.is_assigned(student));
+        assert!(!assignment.is_assigned(Uuid::new_v4()));
+        assert!(new(AssignmentRules::default()).unwrap().is_assigned(student));
+    }
+
+    #[test]
+    fn test_counted_attempts() {
+        let due = Utc::now().with_nanosecond(0).unwrap();
+        let task_id = Uuid::new_v4();
+        let rules = AssignmentRules {
+            open_time: Some(due - Duration::days(1)),
+            close_time: Some(due + Duration::days(1)),
+            max_attempts: Some(2),
+            ..Default::default()
+        };
+        let assignment = Assignment::new(Uuid::new_v4(), "Window", AssignmentTarget::Tasks(vec![task_id]), due, rules).unwrap();
+
+        let attempts = [
+            attempt(task_id, Some(true), due + Duration::hours(12)),
+            attempt(task_id, Some(true), due - Duration::days(2)),
+            attempt(task_id, Some(false), due - Duration::hours(1)),
+            attempt(task_id, Some(true), due + Duration::hours(20)),
+            attempt(task_id, Some(true), due + Duration::days(2)),
+            attempt(Uuid::new_v4(), Some(true), due)
+        ];
+        let counted: Vec<DateTime<Utc>> = assignment.counted_attempts(task_id, &attempts).iter().map(|attempt| attempt.submission_time).collect();
+        assert_eq!(counted, vec![due - Duration::hours(1), due + Duration::hours(12)]);
+    }
+
+    #[test]
+    fn test_late_policy() {
+        let due = Utc::now();
+        assert_eq!(LatePolicy::NoCredit.credit(due, due), 1.0);
+        assert_eq!(LatePolicy::NoCredit.credit(due, due + Duration::seconds(1)), 0.0);
+        assert_eq!(LatePolicy::Accept.credit(due, due + Duration::days(30)), 1.0);
+
+        let penalty = LatePolicy::Penalty { percent_per_day: 25 };
+        assert_eq!(penalty.credit(due, due + Duration::minutes(1)), 0.75);
+        assert_eq!(penalty.credit(due, due + Duration::days(1)), 0.75);
+        assert_eq!(penalty.credit(due, due + Duration::days(1) + Duration::seconds(1)), 0.5);
+        assert_eq!(penalty.credit(due, due + Duration::days(10)), 0.0);
+    }
+
+    #[test]
+    fn test_level_progress() {
+        let now = Utc::now().with_nanosecond(0).unwrap();
+        let target = CurriculumLevel { course: 1, unit: 2, sector: 0, level: 3 };
+        let assignment = Assignment::new(Uuid::new_v4(), "Loops", AssignmentTarget::Level(target), now, AssignmentRules::default()).unwrap();
+
+        let behind = CurriculumLevel { course: 1, unit: 1, sector: 5, level: 9 };
+        let past = CurriculumLevel { course: 1, unit: 2, sector: 1, level: 0 };
+        let other_course = CurriculumLevel { course: 2, unit: 9, sector: 9, level: 9 };
+
+        assert_eq!(assignment.progress(&past, &[], now).status, AssignmentStatus::Completed);
+        assert_eq!(assignment.progress(&target, &[], now).status, AssignmentStatus::Pending);
+        assert_eq!(assignment.progress(&behind, &[], now + Duration::hours(1)).status, AssignmentStatus::Overdue);
+        assert_eq!(assignment.progress(&other_course, &[], now).status, AssignmentStatus::Pending);
+        assert_eq!(assignment.progress(&past, &[], now).tasks_total, None);
+    }
+
+    #[test]
+    fn test_task_progress() {
+        let due = Utc::now().with_nanosecond(0).unwrap();
+        let (task_1, task_2) = (Uuid::new_v4(), Uuid::new_v4());
+        let assignment = Assignment::new(Uuid::new_v4(), "Homework", AssignmentTarget::Tasks(vec![task_1, task_2]), due, AssignmentRules::default()).unwrap();
+        let position = CurriculumLevel { course: 0, unit: 0, sector: 0, level: 0 };
+        let before = due - Duration::hours(1);
+
+        let progress = assignment.progress(&position, &[attempt(task_1, Some(false), before), attempt(task_2, None, before)], before);
+        assert_eq!(progress.status, AssignmentStatus::Pending);
+        assert_eq!((progress.tasks_total, progress.tasks_answered, progress.tasks_correct), (Some(2), Some(2), Some(0)));
+
+        let attempts = [attempt(task_1, Some(true), before), attempt(task_2, Some(true), before)];
+        assert_eq!(assignment.progress(&position, &attempts, due + Duration::days(1)).status, AssignmentStatus::Completed);
+
+        // Only the first correct answer counts, a later one doesn't make it late
+        let attempts = [attempt(task_1, Some(true), before), attempt(task_2, Some(true), due + Duration::hours(1)), attempt(task_2, Some(true), before)];
+        assert_eq!(assignment.progress(&position, &attempts, due + Duration::days(1)).status, AssignmentStatus::Completed);
+
+        let attempts = [attempt(task_1, Some(true), before), attempt(task_2, Some(true), due + Duration::hours(1))];
+        assert_eq!(assignment.progress(&position, &attempts, due + Duration::days(1)).status, AssignmentStatus::CompletedLate);
+
+        let attempts = [attempt(task_1, Some(true), before), attempt(Uuid::new_v4(), Some(true), before)];
+        let progress = assignment.progress(&position, &attempts, due + Duration::days(1));
+        assert_eq!(progress.status, AssignmentStatus::Overdue);
+        assert_eq!(progress.tasks_correct, Some(1));
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use super::user::User;
+
+/// Just enough to show a user in a list
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize, PartialEq)]
+pub struct UserSummary {
+    pub id: Uuid,
+    pub username: String,
+    #[serde(skip)]
+    pub avatar_version: Option<String>,
+    /// Size to url, filled in by the server
+    pub avatar: Option<BTreeMap<String, String>>
+}
+
+impl UserSummary {
+    fn new(id: &str, username: String, avatar_version: Option<String>) -> UserSummary {
+        UserSummary {
+            id: Uuid::parse_str(id).expect("Couldn't parse string to Uuid"),
+            username,
+            avatar_version,
+            avatar: None
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct SearchResults {
+    pub page: u32,
+    pub page_size: u32,
+    pub results: Vec<UserSummary>
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct FriendSuggestion {
+    #[serde(flatten)]
+    pub user: UserSummary,
+    pub mutual_friends: u32,
+    pub same_course: bool
+}
+
+/// So `%` and `_` typed by the user are matched literally
+pub fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+pub mod database {
+    use super::*;
+
+    impl User {
+        /// Exact matches first, then usernames starting with `text`, containing it and finally ones that only sound alike.
+        /// Users who are blocked either way, hidden themselves or are being deleted are left out
+        pub async fn search(viewer: Uuid, text: &str, page: u32, page_size: u32, transaction: &mut Transaction<'static, MySql>) -> Result<SearchResults, sqlx::Error> {
+            let prefix = format!("{}%", escape_like(text));
+            let contains = format!("%{}%", escape_like(text));
+
+            let rows = query!(
+                "SELECT u.id, u.username, u.avatar FROM users u LEFT JOIN profile_privacy p ON p.user_id = u.id
+                WHERE (u.username LIKE ? OR SOUNDEX(u.username) = SOUNDEX(?))
+                AND u.id != ? AND u.deletion_requested_time IS NULL AND COALESCE(p.discoverable, 1) = 1
+                AND NOT EXISTS (SELECT 1 FROM blocks b WHERE (b.blocker_id = ? AND b.blocked_id = u.id) OR (b.blocker_id = u.id AND b.blocked_id = ?))
+                ORDER BY CASE WHEN u.username = ? THEN 0 WHEN u.username LIKE ? THEN 1 WHEN u.username LIKE ? THEN 2 ELSE 3 END,
+                CHAR_LENGTH(u.username), u.username
+                LIMIT ? OFFSET ?",
+                contains,
+                text,
+                viewer.to_string(),
+                viewer.to_string(),
+                viewer.to_string(),
+                text,
+                prefix,
+                contains,
+                page_size,
+                page.saturating_mul(page_size)
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(SearchResults {
+                page,
+                page_size,
+                results: rows.into_iter().map(|row| UserSummary::new(&row.id, row.username, row.avatar)).collect()
+            })
+        }
+
+        /// Friends of friends and users in the same course, the ones with the most mutual friends first.
+        /// Friends, users with a pending request either way and the same users as in `search` are left out
+        pub async fn suggest_friends(id: Uuid, limit: u32, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<FriendSuggestion>, sqlx::Error> {
+            let rows = query!(
+                "SELECT u.id, u.username, u.avatar,
+                (SELECT COUNT(*) FROM friends mine JOIN friends theirs ON theirs.user_id_1 = mine.user_id_2
+                    WHERE mine.user_id_1 = ? AND theirs.user_id_2 = u.id) AS `mutual_friends!`,
+                EXISTS (SELECT 1 FROM user_progress mine JOIN user_progress theirs ON theirs.course = mine.course
+                    WHERE mine.user_id = ? AND theirs.user_id = u.id) AS `same_course!`
+                FROM users u LEFT JOIN profile_privacy p ON p.user_id = u.id
+                WHERE (u.id IN (SELECT theirs.user_id_2 FROM friends mine JOIN friends theirs ON theirs.user_id_1 = mine.user_id_2 WHERE mine.user_id_1 = ?)
+                    OR u.id IN (SELECT theirs.user_id FROM user_progress mine JOIN user_progress theirs ON theirs.course = mine.course WHERE mine.user_id = ?))
+                AND u.id != ? AND u.deletion_requested_time IS NULL AND COALESCE(p.discoverable, 1) = 1
+                AND u.id NOT IN (SELECT user_id_2 FROM friends WHERE user_id_1 = ?)
+                AND NOT EXISTS (SELECT 1 FROM friend_requests r WHERE (r.sender_id = ? AND r.receiver_id = u.id) OR (r.sender_id = u.id AND r.receiver_id = ?))
+                AND NOT EXISTS (SELECT 1 FROM blocks b WHERE (b.blocker_id = ? AND b.blocked_id = u.id) OR (b.blocker_id = u.id AND b.blocked_id = ?))
+                ORDER BY `mutual_friends!` DESC, `same_course!` DESC, u.username
+                LIMIT ?",
+                id.to_string(),
+                id.to_string(),
+                id.to_string(),
+                id.to_string(),
+                id.to_string(),
+                id.to_string(),
+                id.to_string(),
+                id.to_string(),
+                id.to_string(),
+                id.to_string(),
+                limit
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.into_iter().map(|row| FriendSuggestion {
+                user: UserSummary::new(&row.id, row.username, row.avatar),
+                mutual_friends: row.mutual_friends as u32,
+                same_course: row.same_course != 0
+            }).collect())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+        use crate::models::profile::PrivacySettings;
+
+        async fn create_user(username: &str, tx: &mut Transaction<'static, MySql>) -> User {
+            let user = User::new(username.to_string(), "aaaaa".to_string(), Some(format!("{}@test.com", username)), None, tx).await.unwrap();
+            user.create(tx).await.unwrap();
+            user
+        }
+
+        async fn make_friends(id_1: Uuid, id_2: Uuid, tx: &mut Transaction<'static, MySql>) {
+            query!("INSERT INTO friends (user_id_1, user_id_2) VALUES (?, ?), (?, ?)", id_1.to_string(), id_2.to_string(), id_2.to_string(), id_1.to_string())
+                .execute(tx.as_mut()).await.unwrap();
+        }
+
+        fn usernames(results: &SearchResults) -> Vec<&str> {
+            results.results.iter().map(|user| user.username.as_str()).collect()
+        }
+
+        #[tokio::test]
+        async fn test_search() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let viewer = create_user("searcher", &mut tx).await;
+            let exact = create_user("findme", &mut tx).await;
+            create_user("findme_too", &mut tx).await;
+            create_user("xfindmex", &mut tx).await;
+            let hidden = create_user("findme_hidden", &mut tx).await;
+            User::update_privacy(hidden.id, &PrivacySettings { discoverable: false, ..Default::default() }, &mut tx).await.unwrap();
+
+            let results = User::search(viewer.id, "findme", 0, 10, &mut tx).await.unwrap();
+            assert_eq!(usernames(&results), vec!["findme", "findme_too", "xfindmex"]);
+
+            // `_` is matched literally, "findme" only sounds the same
+            let results = User::search(viewer.id, "findme_", 0, 10, &mut tx).await.unwrap();
+            assert_eq!(usernames(&results), vec!["findme_too", "findme"]);
+
+            let results = User::search(viewer.id, "findme", 1, 2, &mut tx).await.unwrap();
+            assert_eq!(usernames(&results), vec!["xfindmex"]);
+
+            User::block(exact.id, viewer.id, &mut tx).await.unwrap();
+            let results = User::search(viewer.id, "findme", 0, 10, &mut tx).await.unwrap();
+            assert!(!usernames(&results).contains(&"findme"));
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_suggest_friends() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = create_user("suggest_me", &mut tx).await;
+            let friend = create_user("suggest_friend", &mut tx).await;
+            let friend_of_friend = create_user("suggest_fof", &mut tx).await;
+            make_friends(user.id, friend.id, &mut tx).await;
+            make_friends(friend.id, friend_of_friend.id, &mut tx).await;
+
+            let suggestions = User::suggest_friends(user.id, 100, &mut tx).await.unwrap();
+            assert_eq!(suggestions[0].user.id, friend_of_friend.id);
+            assert_eq!(suggestions[0].mutual_friends, 1);
+            assert!(suggestions[0].same_course);
+            assert!(suggestions.iter().all(|suggestion| suggestion.user.id != friend.id && suggestion.user.id != user.id));
+
+            User::block(user.id, friend_of_friend.id, &mut tx).await.unwrap();
+            let suggestions = User::suggest_friends(user.id, 100, &mut tx).await.unwrap();
+            assert!(suggestions.iter().all(|suggestion| suggestion.user.id != friend_of_friend.id));
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("plain"), "plain");
+        assert_eq!(escape_like("50%_off\\"), "50\\%\\_off\\\\");
+    }
+}
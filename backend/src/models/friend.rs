@@ -0,0 +1,394 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::info;
+use super::user::User;
+use crate::events::DomainEvent;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct FriendRequest {
+    pub id: Uuid,
+    pub sender_id: Uuid,
+    pub receiver_id: Uuid,
+    pub creation_time: DateTime<Utc>
+}
+
+#[derive(Debug)]
+pub enum FriendError {
+    SelfReference,
+    NoSuchUser,
+    NoSuchRequest,
+    AlreadyFriends,
+    RequestExists,
+    Blocked,
+    NotAuthorized,
+    DatabaseError(sqlx::Error)
+}
+
+impl FriendRequest {
+    pub fn new(sender_id: Uuid, receiver_id: Uuid) -> FriendRequest {
+        FriendRequest {
+            id: Uuid::new_v4(),
+            sender_id,
+            receiver_id,
+            // MySQL DATETIME has no sub-second precision
+            creation_time: Utc::now().with_nanosecond(0).unwrap_or_else(Utc::now)
+        }
+    }
+}
+
+pub mod database {
+    use super::*;
+
+    fn parse_request(id: &str, sender_id: &str, receiver_id: &str, creation_time: NaiveDateTime) -> FriendRequest {
+        FriendRequest {
+            id: Uuid::parse_str(id).expect("Couldn't parse string to Uuid"),
+            sender_id: Uuid::parse_str(sender_id).expect("Couldn't parse string to Uuid"),
+            receiver_id: Uuid::parse_str(receiver_id).expect("Couldn't parse string to Uuid"),
+            creation_time: creation_time.and_utc()
+        }
+    }
+
+    impl FriendRequest {
+        pub async fn send(&self, transaction: &mut Transaction<'static, MySql>) -> Result<(), FriendError> {
+            if self.sender_id == self.receiver_id {
+                return Err(FriendError::SelfReference);
+            }
+
+            let receiver = query!("SELECT id FROM users WHERE id = ?", self.receiver_id.to_string())
+                .fetch_optional(transaction.as_mut()).await.map_err(FriendError::DatabaseError)?;
+            if receiver.is_none() {
+                return Err(FriendError::NoSuchUser);
+            }
+
+            if User::is_blocked_between(&self.sender_id, &self.receiver_id, transaction).await.map_err(FriendError::DatabaseError)? {
+                return Err(FriendError::Blocked);
+            }
+
+            if User::are_friends(&self.sender_id, &self.receiver_id, transaction).await.map_err(FriendError::DatabaseError)? {
+                return Err(FriendError::AlreadyFriends);
+            }
+
+            // A request in either direction is enough, the receiver should answer the existing one.
+            // Two sent at the same time both get past this, the unique key on the pair stops the second
+            let existing = query!(
+                "SELECT id FROM friend_requests WHERE (sender_id = ? AND receiver_id = ?) OR (sender_id = ? AND receiver_id = ?)",
+                self.sender_id.to_string(),
+                self.receiver_id.to_string(),
+                self.receiver_id.to_string(),
+                self.sender_id.to_string()
+            ).fetch_optional(transaction.as_mut()).await.map_err(FriendError::DatabaseError)?;
+            if existing.is_some() {
+                return Err(FriendError::RequestExists);
+            }
+
+            query!(
+                "INSERT INTO friend_requests (id, sender_id, receiver_id, creation_time) VALUES (?, ?, ?, ?)",
+                self.id.to_string(),
+                self.sender_id.to_string(),
+                self.receiver_id.to_string(),
+                self.creation_time
+            ).execute(transaction.as_mut()).await.map_err(|e| match e {
+                sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => FriendError::RequestExists,
+                e => FriendError::DatabaseError(e)
+            })?;
+
+            DomainEvent::FriendRequestSent { request_id: self.id, sender_id: self.sender_id, receiver_id: self.receiver_id }
+                .record(transaction).await.map_err(FriendError::DatabaseError)?;
+
+            info!("User {} sent a friend request to {}", self.sender_id, self.receiver_id);
+            Ok(())
+        }
+
+        pub async fn read(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Option<FriendRequest>, sqlx::Error> {
+            let row = query!("SELECT * FROM friend_requests WHERE id = ?", id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+
+            Ok(row.map(|row| parse_request(&row.id, &row.sender_id, &row.receiver_id, row.creation_time)))
+        }
+
+        pub async fn read_incoming(user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<FriendRequest>, sqlx::Error> {
+            let rows = query!("SELECT * FROM friend_requests WHERE receiver_id = ? ORDER BY creation_time", user_id.to_string())
+                .fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.iter()
+                .map(|row| parse_request(&row.id, &row.sender_id, &row.receiver_id, row.creation_time))
+                .collect())
+        }
+
+        pub async fn read_outgoing(user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<FriendRequest>, sqlx::Error> {
+            let rows = query!("SELECT * FROM friend_requests WHERE sender_id = ? ORDER BY creation_time", user_id.to_string())
+                .fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.iter()
+                .map(|row| parse_request(&row.id, &row.sender_id, &row.receiver_id, row.creation_time))
+                .collect())
+        }
+
+        async fn remove(&self, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!("DELETE FROM friend_requests WHERE id = ?", self.id.to_string())
+                .execute(transaction.as_mut()).await?;
+            Ok(())
+        }
+
+        pub async fn accept(id: Uuid, user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), FriendError> {
+            let request = FriendRequest::read(id, transaction).await
+                .map_err(FriendError::DatabaseError)?
+                .ok_or(FriendError::NoSuchRequest)?;
+
+            if request.receiver_id != user_id {
+                return Err(FriendError::NotAuthorized);
+            }
+
+            request.remove(transaction).await.map_err(FriendError::DatabaseError)?;
+
+            // Friendship is mutual, so it's stored in both directions
+            query!(
+                "INSERT INTO friends (user_id_1, user_id_2) VALUES (?, ?), (?, ?)",
+                request.sender_id.to_string(),
+                request.receiver_id.to_string(),
+                request.receiver_id.to_string(),
+                request.sender_id.to_string()
+            ).execute(transaction.as_mut()).await.map_err(FriendError::DatabaseError)?;
+
+            info!("User {} accepted friend request from {}", request.receiver_id, request.sender_id);
+            Ok(())
+        }
+
+        pub async fn decline(id: Uuid, user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), FriendError> {
+            let request = FriendRequest::read(id, transaction).await
+                .map_err(FriendError::DatabaseError)?
+                .ok_or(FriendError::NoSuchRequest)?;
+
+            if request.receiver_id != user_id {
+                return Err(FriendError::NotAuthorized);
+            }
+
+            request.remove(transaction).await.map_err(FriendError::DatabaseError)
+        }
+
+        pub async fn cancel(id: Uuid, user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), FriendError> {
+            let request = FriendRequest::read(id, transaction).await
+                .map_err(FriendError::DatabaseError)?
+                .ok_or(FriendError::NoSuchRequest)?;
+
+            if request.sender_id != user_id {
+                return Err(FriendError::NotAuthorized);
+            }
+
+            request.remove(transaction).await.map_err(FriendError::DatabaseError)
+        }
+    }
+
+    impl User {
+        pub async fn read_friends(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<Uuid>, sqlx::Error> {
+            let rows = query!("SELECT user_id_2 FROM friends WHERE user_id_1 = ?", id.to_string())
+                .fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.iter()
+                .map(|row| Uuid::parse_str(&row.user_id_2).expect("Couldn't parse string to Uuid"))
+                .collect())
+        }
+
+        pub async fn are_friends(id_1: &Uuid, id_2: &Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            let row = query!("SELECT user_id_1 FROM friends WHERE user_id_1 = ? AND user_id_2 = ?", id_1.to_string(), id_2.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+            Ok(row.is_some())
+        }
+
+        pub async fn unfriend(id: Uuid, friend_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), FriendError> {
+            if !User::are_friends(&id, &friend_id, transaction).await.map_err(FriendError::DatabaseError)? {
+                return Err(FriendError::NoSuchUser);
+            }
+
+            query!(
+                "DELETE FROM friends WHERE (user_id_1 = ? AND user_id_2 = ?) OR (user_id_1 = ? AND user_id_2 = ?)",
+                id.to_string(),
+                friend_id.to_string(),
+                friend_id.to_string(),
+                id.to_string()
+            ).execute(transaction.as_mut()).await.map_err(FriendError::DatabaseError)?;
+
+            Ok(())
+        }
+
+        pub async fn is_blocked_between(id_1: &Uuid, id_2: &Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            let row = query!(
+                "SELECT blocker_id FROM blocks WHERE (blocker_id = ? AND blocked_id = ?) OR (blocker_id = ? AND blocked_id = ?)",
+                id_1.to_string(),
+                id_2.to_string(),
+                id_2.to_string(),
+                id_1.to_string()
+            ).fetch_optional(transaction.as_mut()).await?;
+            Ok(row.is_some())
+        }
+
+        pub async fn read_blocked(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<Uuid>, sqlx::Error> {
+            let rows = query!("SELECT blocked_id FROM blocks WHERE blocker_id = ?", id.to_string())
+                .fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.iter()
+                .map(|row| Uuid::parse_str(&row.blocked_id).expect("Couldn't parse string to Uuid"))
+                .collect())
+        }
+
+        pub async fn block(id: Uuid, blocked_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), FriendError> {
+            if id == blocked_id {
+                return Err(FriendError::SelfReference);
+            }
+
+            let blocked = query!("SELECT id FROM users WHERE id = ?", blocked_id.to_string())
+                .fetch_optional(transaction.as_mut()).await.map_err(FriendError::DatabaseError)?;
+            if blocked.is_none() {
+                return Err(FriendError::NoSuchUser);
+            }
+
+            // Blocking ends the friendship and drops pending requests both ways
+            query!(
+                "DELETE FROM friends WHERE (user_id_1 = ? AND user_id_2 = ?) OR (user_id_1 = ? AND user_id_2 = ?)",
+                id.to_string(),
+                blocked_id.to_string(),
+                blocked_id.to_string(),
+                id.to_string()
+            ).execute(transaction.as_mut()).await.map_err(FriendError::DatabaseError)?;
+
+            query!(
+                "DELETE FROM friend_requests WHERE (sender_id = ? AND receiver_id = ?) OR (sender_id = ? AND receiver_id = ?)",
+                id.to_string(),
+                blocked_id.to_string(),
+                blocked_id.to_string(),
+                id.to_string()
+            ).execute(transaction.as_mut()).await.map_err(FriendError::DatabaseError)?;
+
+            query!(
+                "INSERT IGNORE INTO blocks (blocker_id, blocked_id, creation_time) VALUES (?, ?, ?)",
+                id.to_string(),
+                blocked_id.to_string(),
+                Utc::now()
+            ).execute(transaction.as_mut()).await.map_err(FriendError::DatabaseError)?;
+
+            info!("User {} blocked {}", id, blocked_id);
+            Ok(())
+        }
+
+        pub async fn unblock(id: Uuid, blocked_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!("DELETE FROM blocks WHERE blocker_id = ? AND blocked_id = ?", id.to_string(), blocked_id.to_string())
+                .execute(transaction.as_mut()).await?;
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+
+        async fn create_user(username: &str, tx: &mut Transaction<'static, MySql>) -> User {
+            let user = User::new(username.to_string(), "aaaaa".to_string(), Some("test@test.com".to_string()), None, tx).await.unwrap();
+            user.create(tx).await.unwrap();
+            user
+        }
+
+        #[tokio::test]
+        async fn test_send_and_accept() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let sender = create_user("friend_test_1", &mut tx).await;
+            let receiver = create_user("friend_test_2", &mut tx).await;
+
+            let request = FriendRequest::new(sender.id, receiver.id);
+            request.send(&mut tx).await.unwrap();
+
+            let incoming = FriendRequest::read_incoming(receiver.id, &mut tx).await.unwrap();
+            assert_eq!(incoming, vec![request]);
+
+            let id = incoming[0].id;
+            assert!(matches!(FriendRequest::accept(id, sender.id, &mut tx).await, Err(FriendError::NotAuthorized)));
+            FriendRequest::accept(id, receiver.id, &mut tx).await.unwrap();
+
+            assert_eq!(User::read_friends(sender.id, &mut tx).await.unwrap(), vec![receiver.id]);
+            assert_eq!(User::read_friends(receiver.id, &mut tx).await.unwrap(), vec![sender.id]);
+
+            let again = FriendRequest::new(receiver.id, sender.id);
+            assert!(matches!(again.send(&mut tx).await, Err(FriendError::AlreadyFriends)));
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_crossed_requests() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let sender = create_user("friend_test_1", &mut tx).await;
+            let receiver = create_user("friend_test_2", &mut tx).await;
+            FriendRequest::new(sender.id, receiver.id).send(&mut tx).await.unwrap();
+
+            // Like a request sent back at the same time, that didn't see this one yet
+            let crossed = FriendRequest::new(receiver.id, sender.id);
+            let inserted = query!(
+                "INSERT INTO friend_requests (id, sender_id, receiver_id, creation_time) VALUES (?, ?, ?, ?)",
+                crossed.id.to_string(),
+                crossed.sender_id.to_string(),
+                crossed.receiver_id.to_string(),
+                crossed.creation_time
+            ).execute(tx.as_mut()).await;
+            assert!(matches!(inserted, Err(sqlx::Error::Database(e)) if e.is_unique_violation()));
+
+            assert!(matches!(crossed.send(&mut tx).await, Err(FriendError::RequestExists)));
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_decline_and_cancel() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let sender = create_user("friend_test_1", &mut tx).await;
+            let receiver = create_user("friend_test_2", &mut tx).await;
+
+            let request = FriendRequest::new(sender.id, receiver.id);
+            request.send(&mut tx).await.unwrap();
+            assert!(matches!(FriendRequest::new(receiver.id, sender.id).send(&mut tx).await, Err(FriendError::RequestExists)));
+
+            FriendRequest::decline(request.id, receiver.id, &mut tx).await.unwrap();
+            assert!(FriendRequest::read(request.id, &mut tx).await.unwrap().is_none());
+
+            let request = FriendRequest::new(sender.id, receiver.id);
+            request.send(&mut tx).await.unwrap();
+            assert!(matches!(FriendRequest::cancel(request.id, receiver.id, &mut tx).await, Err(FriendError::NotAuthorized)));
+            FriendRequest::cancel(request.id, sender.id, &mut tx).await.unwrap();
+            assert!(FriendRequest::read_outgoing(sender.id, &mut tx).await.unwrap().is_empty());
+
+            assert!(User::read_friends(sender.id, &mut tx).await.unwrap().is_empty());
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_block() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = create_user("friend_test_1", &mut tx).await;
+            let other = create_user("friend_test_2", &mut tx).await;
+
+            let request = FriendRequest::new(user.id, other.id);
+            request.send(&mut tx).await.unwrap();
+            FriendRequest::accept(request.id, other.id, &mut tx).await.unwrap();
+
+            User::block(other.id, user.id, &mut tx).await.unwrap();
+            assert!(User::read_friends(user.id, &mut tx).await.unwrap().is_empty());
+            assert_eq!(User::read_blocked(other.id, &mut tx).await.unwrap(), vec![user.id]);
+            assert!(matches!(FriendRequest::new(user.id, other.id).send(&mut tx).await, Err(FriendError::Blocked)));
+
+            User::unblock(other.id, user.id, &mut tx).await.unwrap();
+            assert!(FriendRequest::new(user.id, other.id).send(&mut tx).await.is_ok());
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
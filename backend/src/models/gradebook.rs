@@ -0,0 +1,353 @@
+use serde::Serialize;
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::warn;
+use super::answer::{Answer, AnswerContent, VerificationError};
+use super::classroom::{Assignment, AssignmentTarget, Attempt, ClassroomError};
+use crate::config::VerifierConfig;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub enum TaskStatus {
+    Unanswered,
+    /// Answered, but nothing that counts is graded as correct and some of it isn't graded yet
+    Pending,
+    Incorrect,
+    Correct
+}
+
+impl TaskStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TaskStatus::Unanswered => "Unanswered",
+            TaskStatus::Pending => "Pending",
+            TaskStatus::Incorrect => "Incorrect",
+            TaskStatus::Correct => "Correct"
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct TaskGrade {
+    pub task_id: Uuid,
+    pub status: TaskStatus,
+    /// Only the answers that count
+    pub attempts: u32,
+    /// The first correct answer came after the due time
+    pub late: bool,
+    /// 0 to 1, after the late policy
+    pub credit: f64
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct GradeSummary {
+    pub user_id: Uuid,
+    pub username: String,
+    /// 0 to 100, every task is worth the same
+    pub score: f64,
+    pub tasks_total: u32,
+    pub tasks_correct: u32,
+    pub tasks_pending: u32,
+    pub attempts: u32,
+    pub late: bool,
+    pub tasks: Vec<TaskGrade>
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Gradebook {
+    pub assignment: Assignment,
+    pub generation_time: DateTime<Utc>,
+    pub students: Vec<GradeSummary>
+}
+
+impl Assignment {
+    /// `None` for curriculum level assignments, they have no tasks to grade
+    pub fn grade(&self, user_id: Uuid, username: String, attempts: &[Attempt]) -> Option<GradeSummary> {
+        let tasks = match &self.target {
+            AssignmentTarget::Tasks(tasks) => tasks,
+            AssignmentTarget::Level(_) => return None
+        };
+
+        let grades: Vec<TaskGrade> = tasks.iter().map(|task_id| {
+            let counted = self.counted_attempts(*task_id, attempts);
+            let first_correct = counted.iter().find(|attempt| attempt.correct == Some(true));
+
+            let status = match first_correct {
+                Some(_) => TaskStatus::Correct,
+                None if counted.iter().any(|attempt| attempt.correct.is_none()) => TaskStatus::Pending,
+                None if !counted.is_empty() => TaskStatus::Incorrect,
+                None => TaskStatus::Unanswered
+            };
+
+            TaskGrade {
+                task_id: *task_id,
+                status,
+                attempts: counted.len() as u32,
+                late: first_correct.is_some_and(|attempt| attempt.submission_time > self.due_time),
+                credit: first_correct.map_or(0.0, |attempt| self.rules.late_policy.credit(self.due_time, attempt.submission_time))
+            }
+        }).collect();
+
+        let count = |status: TaskStatus| grades.iter().filter(|grade| grade.status == status).count() as u32;
+
+        Some(GradeSummary {
+            user_id,
+            username,
+            score: grades.iter().map(|grade| grade.credit).sum::<f64>() / tasks.len() as f64 * 100.0,
+            tasks_total: tasks.len() as u32,
+            tasks_correct: count(TaskStatus::Correct),
+            tasks_pending: count(TaskStatus::Pending),
+            attempts: grades.iter().map(|grade| grade.attempts).sum(),
+            late: grades.iter().any(|grade| grade.late),
+            tasks: grades
+        })
+    }
+}
+
+/// Quoted when needed, cells that would start a formula in a spreadsheet are kept as text
+pub fn csv_field(value: &str) -> String {
+    let value = match value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        true => format!("'{}", value),
+        false => value.to_string()
+    };
+
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value
+    }
+}
+
+impl Gradebook {
+    /// One row per student, a status and credit column per task in the assignment's order
+    pub fn to_csv(&self) -> String {
+        let tasks = match &self.assignment.target {
+            AssignmentTarget::Tasks(tasks) => tasks.as_slice(),
+            AssignmentTarget::Level(_) => &[]
+        };
+
+        let mut header = vec!["user_id".to_string(), "username".to_string(), "score".to_string(), "tasks_correct".to_string(),
+            "tasks_pending".to_string(), "tasks_total".to_string(), "attempts".to_string(), "late".to_string()];
+        for task_id in tasks {
+            header.push(format!("{} status", task_id));
+            header.push(format!("{} credit", task_id));
+        }
+
+        let mut csv = header.join(",") + "\r\n";
+        for student in &self.students {
+            let mut row = vec![
+                student.user_id.to_string(),
+                csv_field(&student.username),
+                format!("{:.2}", student.score),
+                student.tasks_correct.to_string(),
+                student.tasks_pending.to_string(),
+                student.tasks_total.to_string(),
+                student.attempts.to_string(),
+                student.late.to_string()
+            ];
+            for grade in &student.tasks {
+                row.push(grade.status.as_str().to_string());
+                row.push(format!("{:.2}", grade.credit));
+            }
+            csv += &(row.join(",") + "\r\n");
+        }
+        csv
+    }
+}
+
+pub mod database {
+    use super::*;
+
+    // Answers still waiting in the grading queue are verified here, without saving the result so the queue doesn't
+    // award XP twice. Open questions are left to the queue, the AI verifier is too slow to wait for
+    async fn verify_pending(attempt: &mut Attempt, verifier: &VerifierConfig, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+        let answer = match Answer::read(attempt.answer_id, transaction).await? {
+            Some(answer) => answer,
+            None => return Ok(())
+        };
+        if matches!(answer.content, None | Some(AnswerContent::OpenQuestion(_))) {
+            return Ok(());
+        }
+
+        match answer.verify(verifier, transaction).await {
+            Ok(result) => attempt.correct = Some(result.correct),
+            Err(VerificationError::DatabaseError(e)) => return Err(e),
+            Err(e) => warn!("Couldn't verify answer {}: {:?}", attempt.answer_id, e)
+        }
+        Ok(())
+    }
+
+    impl Gradebook {
+        /// Grades of every current student the assignment is for, `only` narrows it down to one of them
+        pub async fn read(assignment: Assignment, only: Option<Uuid>, verifier: &VerifierConfig, transaction: &mut Transaction<'static, MySql>) -> Result<Gradebook, ClassroomError> {
+            let tasks = match &assignment.target {
+                AssignmentTarget::Tasks(tasks) => tasks.clone(),
+                AssignmentTarget::Level(_) => return Err(ClassroomError::NotGradable)
+            };
+
+            let members = query!(
+                "SELECT m.user_id, u.username FROM classroom_members m JOIN users u ON u.id = m.user_id
+                WHERE m.classroom_id = ? ORDER BY u.username",
+                assignment.classroom_id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            let attempt_rows = query!(
+                "SELECT a.id, a.user_id, a.task_id, a.creation_time, r.correct FROM answers a
+                JOIN classroom_members m ON m.user_id = a.user_id AND m.classroom_id = ?
+                LEFT JOIN answer_results r ON r.answer_id = a.id
+                WHERE a.task_id IN (SELECT task_id FROM assignment_tasks WHERE assignment_id = ?)",
+                assignment.classroom_id.to_string(),
+                assignment.id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            let mut students = Vec::new();
+            for member in members {
+                let user_id = Uuid::parse_str(&member.user_id).expect("Couldn't parse string to Uuid");
+                if !assignment.is_assigned(user_id) || only.is_some_and(|only| only != user_id) {
+                    continue;
+                }
+
+                let mut attempts: Vec<Attempt> = attempt_rows.iter()
+                    .filter(|row| row.user_id == member.user_id)
+                    .map(|row| Attempt {
+                        answer_id: Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid"),
+                        task_id: Uuid::parse_str(&row.task_id).expect("Couldn't parse string to Uuid"),
+                        correct: row.correct.map(|correct| correct != 0),
+                        submission_time: row.creation_time.and_utc()
+                    })
+                    .collect();
+
+                // Only the ones that count, answers past the attempt limit aren't worth verifying
+                let counted: Vec<Uuid> = tasks.iter()
+                    .flat_map(|task_id| assignment.counted_attempts(*task_id, &attempts))
+                    .filter(|attempt| attempt.correct.is_none())
+                    .map(|attempt| attempt.answer_id)
+                    .collect();
+                for attempt in attempts.iter_mut().filter(|attempt| counted.contains(&attempt.answer_id)) {
+                    verify_pending(attempt, verifier, transaction).await?;
+                }
+
+                if let Some(summary) = assignment.grade(user_id, member.username, &attempts) {
+                    students.push(summary);
+                }
+            }
+
+            Ok(Gradebook { assignment, generation_time: Utc::now(), students })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+        use crate::models::answer::{PartsAnswer, VerifyResult};
+        use crate::models::classroom::{AssignmentRules, Classroom};
+        use crate::models::user::User;
+
+        async fn create_user(username: &str, tx: &mut Transaction<'static, MySql>) -> User {
+            let user = User::new(username.to_string(), "aaaaa".to_string(), Some(format!("{}@test.com", username)), None, tx).await.unwrap();
+            user.create(tx).await.unwrap();
+            user
+        }
+
+        #[tokio::test]
+        async fn test_read_gradebook() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let teacher = create_user("grade_teacher", &mut tx).await;
+            let student = create_user("grade_student", &mut tx).await;
+            let other = create_user("grade_other", &mut tx).await;
+            let mut classroom = Classroom::new(teacher.id, "Grades").unwrap();
+            classroom.create(&mut tx).await.unwrap();
+            Classroom::join(classroom.invite_code.as_deref().unwrap(), student.id, &mut tx).await.unwrap();
+            Classroom::join(classroom.invite_code.as_deref().unwrap(), other.id, &mut tx).await.unwrap();
+
+            let task_id = query!("SELECT task_id FROM task_correct_answer LIMIT 1").fetch_one(tx.as_mut()).await.unwrap().task_id;
+            let task_id = Uuid::parse_str(&task_id).unwrap();
+
+            let rules = AssignmentRules { assignees: Some(vec![student.id]), max_attempts: Some(3), ..Default::default() };
+            let assignment = Assignment::new(classroom.id, "Graded", AssignmentTarget::Tasks(vec![task_id]), Utc::now() + chrono::Duration::days(1), rules).unwrap();
+            assignment.create(&mut tx).await.unwrap();
+
+            // Still waiting for the grading queue
+            let answer = Answer::new(student.id, task_id).solve(AnswerContent::FromParts(PartsAnswer { parts: vec!["nope".to_string()] }));
+            answer.create(&mut tx).await.unwrap();
+
+            let gradebook = Gradebook::read(assignment.clone(), None, &VerifierConfig::default(), &mut tx).await.unwrap();
+            assert_eq!(gradebook.students.len(), 1);
+            let summary = &gradebook.students[0];
+            assert_eq!(summary.user_id, student.id);
+            assert_eq!(summary.tasks[0].status, TaskStatus::Incorrect);
+            assert_eq!((summary.attempts, summary.score), (1, 0.0));
+            // The queue still saves the result
+            assert_eq!(VerifyResult::read(answer.id, &mut tx).await.unwrap(), None);
+
+            let gradebook = Gradebook::read(assignment, Some(other.id), &VerifierConfig::default(), &mut tx).await.unwrap();
+            assert!(gradebook.students.is_empty());
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use crate::models::classroom::{AssignmentRules, LatePolicy};
+
+    fn attempt(task_id: Uuid, correct: Option<bool>, submission_time: DateTime<Utc>) -> Attempt {
+        Attempt { answer_id: Uuid::new_v4(), task_id, correct, submission_time }
+    }
+
+    #[test]
+    fn test_grade() {
+        let due = Utc::now().with_nanosecond(0).unwrap();
+        let tasks: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let rules = AssignmentRules { max_attempts: Some(2), late_policy: LatePolicy::Penalty { percent_per_day: 30 }, ..Default::default() };
+        let assignment = Assignment::new(Uuid::new_v4(), "Quiz", AssignmentTarget::Tasks(tasks.clone()), due, rules).unwrap();
+        let before = due - Duration::hours(1);
+
+        let attempts = [
+            attempt(tasks[0], Some(true), before),
+            // Two days late
+            attempt(tasks[1], Some(false), before),
+            attempt(tasks[1], Some(true), due + Duration::hours(30)),
+            // The third answer is past the limit
+            attempt(tasks[2], Some(false), before),
+            attempt(tasks[2], None, before + Duration::minutes(1)),
+            attempt(tasks[2], Some(true), before + Duration::minutes(2))
+        ];
+
+        let summary = assignment.grade(Uuid::new_v4(), "student".to_string(), &attempts).unwrap();
+        let statuses: Vec<TaskStatus> = summary.tasks.iter().map(|grade| grade.status).collect();
+        assert_eq!(statuses, vec![TaskStatus::Correct, TaskStatus::Correct, TaskStatus::Pending, TaskStatus::Unanswered]);
+        assert!((summary.tasks[1].credit - 0.4).abs() < 1e-9);
+        assert!(summary.tasks[1].late && summary.late);
+        assert_eq!((summary.tasks_correct, summary.tasks_pending, summary.tasks_total, summary.attempts), (2, 1, 4, 5));
+        assert!((summary.score - 35.0).abs() < 1e-9);
+
+        let level = crate::models::classroom::CurriculumLevel { course: 0, unit: 0, sector: 0, level: 0 };
+        let assignment = Assignment::new(Uuid::new_v4(), "Level", AssignmentTarget::Level(level), due, AssignmentRules::default()).unwrap();
+        assert_eq!(assignment.grade(Uuid::new_v4(), "student".to_string(), &[]), None);
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("=1+1"), "'=1+1");
+
+        let task_id = Uuid::new_v4();
+        let assignment = Assignment::new(Uuid::new_v4(), "Quiz", AssignmentTarget::Tasks(vec![task_id]), Utc::now(), AssignmentRules::default()).unwrap();
+        let user_id = Uuid::new_v4();
+        let student = assignment.grade(user_id, "a,b".to_string(), &[attempt(task_id, Some(true), Utc::now() - Duration::hours(1))]).unwrap();
+        let gradebook = Gradebook { assignment, generation_time: Utc::now(), students: vec![student] };
+
+        let csv = gradebook.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], format!("user_id,username,score,tasks_correct,tasks_pending,tasks_total,attempts,late,{0} status,{0} credit", task_id));
+        assert_eq!(lines[1], format!("{},\"a,b\",100.00,1,0,1,1,false,Correct,1.00", user_id));
+    }
+}
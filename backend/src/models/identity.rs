@@ -0,0 +1,294 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::info;
+use super::password_reset::generate_token;
+use super::session::ClientInfo;
+use super::two_factor::LoginStep;
+use super::user::{valid_email, User, UserError};
+use crate::config::{SessionConfig, TwoFactorConfig};
+use crate::oidc::{IdTokenClaims, LoginRequest};
+
+/// Account of a user at an identity provider
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub user_id: Uuid,
+    pub email: Option<String>,
+    pub creation_time: DateTime<Utc>,
+    pub last_login_time: DateTime<Utc>
+}
+
+#[derive(Debug)]
+pub enum IdentityError {
+    /// A new account needs an email and the provider didn't share one
+    MissingEmail,
+    UserError(UserError),
+    DatabaseError(sqlx::Error)
+}
+
+impl From<sqlx::Error> for IdentityError {
+    fn from(e: sqlx::Error) -> Self {
+        IdentityError::DatabaseError(e)
+    }
+}
+
+impl From<UserError> for IdentityError {
+    fn from(e: UserError) -> Self {
+        IdentityError::UserError(e)
+    }
+}
+
+/// Username for a new account, from what the provider calls the user
+pub fn username_base(claims: &IdTokenClaims) -> String {
+    let name = claims.preferred_username.as_deref()
+        .or(claims.email.as_deref().and_then(|email| email.split('@').next()))
+        .unwrap_or("");
+
+    let name: String = name.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        .take(48)
+        .collect();
+
+    if name.is_empty() { "user".to_string() } else { name }
+}
+
+pub mod database {
+    use super::*;
+
+    impl LoginRequest {
+        pub async fn save(&self, ttl: chrono::Duration, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            // Logins that were never finished, nothing else cleans them up
+            query!("DELETE FROM oidc_logins WHERE expiration_time < ?", Utc::now())
+                .execute(transaction.as_mut()).await?;
+
+            query!(
+                "INSERT INTO oidc_logins (state, provider, nonce, code_verifier, expiration_time) VALUES (?, ?, ?, ?, ?)",
+                self.state,
+                self.provider,
+                self.nonce,
+                self.code_verifier,
+                Utc::now() + ttl
+            ).execute(transaction.as_mut()).await?;
+
+            Ok(())
+        }
+
+        /// The login `state` belongs to, it can only be taken once
+        pub async fn take(provider: &str, state: &str, transaction: &mut Transaction<'static, MySql>) -> Result<Option<LoginRequest>, sqlx::Error> {
+            let row = query!(
+                "SELECT state, provider, nonce, code_verifier FROM oidc_logins WHERE state = ? AND provider = ? AND expiration_time > ? FOR UPDATE",
+                state,
+                provider,
+                Utc::now()
+            ).fetch_optional(transaction.as_mut()).await?;
+
+            query!("DELETE FROM oidc_logins WHERE state = ?", state)
+                .execute(transaction.as_mut()).await?;
+
+            Ok(row.map(|row| LoginRequest {
+                provider: row.provider,
+                state: row.state,
+                nonce: row.nonce,
+                code_verifier: row.code_verifier
+            }))
+        }
+    }
+
+    impl User {
+        pub async fn read_identities(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<ExternalIdentity>, sqlx::Error> {
+            let rows = query!(
+                "SELECT provider, subject, email, creation_time, last_login_time FROM external_identities WHERE user_id = ? ORDER BY provider",
+                id.to_string()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.into_iter().map(|row| ExternalIdentity {
+                provider: row.provider,
+                subject: row.subject,
+                user_id: id,
+                email: row.email,
+                creation_time: row.creation_time.and_utc(),
+                last_login_time: row.last_login_time.and_utc()
+            }).collect())
+        }
+
+        /// Logs in the user linked to the identity, creating an account on the first login. The provider stands in for the password, 2FA is still asked for.
+        /// Accounts are never linked by email, an unverified address at the provider could take over someone else's
+        pub async fn login_external(provider: &str, claims: &IdTokenClaims, client: &ClientInfo, session_config: &SessionConfig, two_factor_config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<LoginStep, IdentityError> {
+            let linked = query!(
+                "SELECT user_id FROM external_identities WHERE provider = ? AND subject = ? FOR UPDATE",
+                provider,
+                claims.sub
+            ).fetch_optional(transaction.as_mut()).await?;
+
+            let user_id = match linked {
+                Some(row) => {
+                    query!(
+                        "UPDATE external_identities SET email = ?, last_login_time = ? WHERE provider = ? AND subject = ?",
+                        claims.email,
+                        Utc::now(),
+                        provider,
+                        claims.sub
+                    ).execute(transaction.as_mut()).await?;
+
+                    Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid")
+                },
+                None => {
+                    let user = User::provision(claims, transaction).await?;
+                    query!(
+                        "INSERT INTO external_identities (provider, subject, user_id, email, creation_time, last_login_time) VALUES (?, ?, ?, ?, ?, ?)",
+                        provider,
+                        claims.sub,
+                        user.id.to_string(),
+                        claims.email,
+                        Utc::now(),
+                        Utc::now()
+                    ).execute(transaction.as_mut()).await?;
+
+                    info!("User {} created from a {} login", user.id, provider);
+                    user.id
+                }
+            };
+
+            info!("User {} logging in with {}", user_id, provider);
+            if let Some(challenge) = User::two_factor_challenge(user_id, two_factor_config, transaction).await? {
+                info!("User {} needs a second factor to log in", user_id);
+                return Ok(LoginStep::TwoFactor(challenge));
+            }
+
+            let (user, tokens) = User::start_session(user_id, client, session_config, transaction).await?;
+            Ok(LoginStep::Done(user, tokens))
+        }
+
+        // The password is random and never shown, a reset sets a real one if the user wants it
+        async fn provision(claims: &IdTokenClaims, transaction: &mut Transaction<'static, MySql>) -> Result<User, IdentityError> {
+            let email = match &claims.email {
+                Some(email) if valid_email(email) => email.clone(),
+                _ => return Err(IdentityError::MissingEmail)
+            };
+
+            let base = username_base(claims);
+            let mut username = base.clone();
+            for _ in 0..10 {
+                let user = match User::new(username.clone(), generate_token(), Some(email.clone()), None, transaction).await {
+                    Ok(user) => user,
+                    Err(UserError::UsernameExists) => {
+                        username = format!("{}{}", base, rand::random::<u16>() % 10000);
+                        continue;
+                    },
+                    Err(e) => return Err(e.into())
+                };
+                user.create(transaction).await?;
+
+                if claims.email_verified {
+                    query!("UPDATE users SET email_verified_time = ? WHERE id = ?", Utc::now(), user.id.to_string())
+                        .execute(transaction.as_mut()).await?;
+                }
+
+                return Ok(user);
+            }
+
+            Err(UserError::UsernameExists.into())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+        use crate::oidc::{mock::MockIdp, OidcClient};
+        use crate::config::OidcConfig;
+        use crate::totp;
+
+        fn client_info() -> ClientInfo {
+            ClientInfo { user_agent: None, ip_address: None }
+        }
+
+        async fn login(claims: &IdTokenClaims, tx: &mut Transaction<'static, MySql>) -> Result<LoginStep, IdentityError> {
+            User::login_external("mock", claims, &client_info(), &SessionConfig::default(), &TwoFactorConfig::default(), tx).await
+        }
+
+        #[tokio::test]
+        async fn test_provisioning() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let (idp, provider) = MockIdp::start().await;
+            let mut config = OidcConfig::default();
+            config.providers.insert("mock".to_string(), provider.clone());
+            let oidc = OidcClient::new(&config);
+
+            let (request, url) = oidc.start_login("mock").unwrap();
+            request.save(config.login_ttl(), &mut tx).await.unwrap();
+
+            let request = LoginRequest::take("mock", &request.state, &mut tx).await.unwrap().unwrap();
+            assert_eq!(LoginRequest::take("mock", &request.state, &mut tx).await.unwrap(), None);
+
+            let subject = Uuid::new_v4().to_string();
+            let code = idp.authorize(&provider, &url, &subject, Some("oidc@school.edu"));
+            let claims = oidc.finish_login(&request, &code).await.unwrap();
+
+            let Ok(LoginStep::Done(user, _)) = login(&claims, &mut tx).await else { panic!("Expected a session") };
+            assert!(user.email_verified);
+            assert_eq!(user.email.as_deref(), Some("oidc@school.edu"));
+
+            // The second login finds the same account
+            let Ok(LoginStep::Done(again, _)) = login(&claims, &mut tx).await else { panic!("Expected a session") };
+            assert_eq!(again.id, user.id);
+
+            // The provider doesn't replace the second factor
+            let enrollment = User::enroll_two_factor(user.id, &TwoFactorConfig::default(), &mut tx).await.unwrap();
+            let code = totp::code(&totp::decode_secret(&enrollment.secret).unwrap(), totp::step(Utc::now()));
+            User::enable_two_factor(user.id, &code, &TwoFactorConfig::default(), &mut tx).await.unwrap();
+            assert!(matches!(login(&claims, &mut tx).await, Ok(LoginStep::TwoFactor(_))));
+
+            let identities = User::read_identities(user.id, &mut tx).await.unwrap();
+            assert_eq!(identities.len(), 1);
+            assert_eq!(identities[0].subject, subject);
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_missing_email() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let (idp, provider) = MockIdp::start().await;
+            let mut config = OidcConfig::default();
+            config.providers.insert("mock".to_string(), provider.clone());
+            let oidc = OidcClient::new(&config);
+
+            let (request, url) = oidc.start_login("mock").unwrap();
+            let code = idp.authorize(&provider, &url, "no-email", None);
+            let claims = oidc.finish_login(&request, &code).await.unwrap();
+
+            assert!(matches!(login(&claims, &mut tx).await, Err(IdentityError::MissingEmail)));
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_username_base() {
+        let claims = |username: Option<&str>, email: Option<&str>| -> IdTokenClaims {
+            serde_json::from_value(serde_json::json!({
+                "iss": "", "sub": "", "aud": "", "exp": 0,
+                "preferred_username": username,
+                "email": email
+            })).unwrap()
+        };
+
+        assert_eq!(username_base(&claims(Some("jan.kowalski"), None)), "jan.kowalski");
+        assert_eq!(username_base(&claims(None, Some("anna+x@school.edu"))), "annax");
+        assert_eq!(username_base(&claims(Some("łódź"), None)), "d");
+        assert_eq!(username_base(&claims(None, None)), "user");
+    }
+}
@@ -0,0 +1,418 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::info;
+use super::user::User;
+use crate::events::DomainEvent;
+
+pub const XP_PER_CORRECT_ANSWER: u32 = 10;
+pub const XP_PER_LEVEL: u32 = 100;
+
+// Fraction of a league promoted/demoted at the end of the week
+const PROMOTED_FRACTION: f64 = 0.2;
+const DEMOTED_FRACTION: f64 = 0.2;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+pub enum LeagueTier {
+    Bronze,
+    Silver,
+    Gold,
+    Sapphire,
+    Ruby,
+    Diamond
+}
+
+impl LeagueTier {
+    const ALL: [LeagueTier; 6] = [
+        LeagueTier::Bronze,
+        LeagueTier::Silver,
+        LeagueTier::Gold,
+        LeagueTier::Sapphire,
+        LeagueTier::Ruby,
+        LeagueTier::Diamond
+    ];
+
+    pub fn from_index(index: i32) -> LeagueTier {
+        let index = index.clamp(0, LeagueTier::ALL.len() as i32 - 1);
+        LeagueTier::ALL[index as usize]
+    }
+
+    pub fn index(&self) -> i32 {
+        *self as i32
+    }
+
+    pub fn promoted(&self) -> LeagueTier {
+        LeagueTier::from_index(self.index() + 1)
+    }
+
+    pub fn demoted(&self) -> LeagueTier {
+        LeagueTier::from_index(self.index() - 1)
+    }
+
+    /// Tier for the next week, given the 0-based rank among `league_size` members
+    pub fn next(&self, rank: usize, league_size: usize, weekly_xp: u32) -> LeagueTier {
+        if league_size == 0 {
+            return *self;
+        }
+
+        let promoted_count = (league_size as f64 * PROMOTED_FRACTION).ceil() as usize;
+        let demoted_count = (league_size as f64 * DEMOTED_FRACTION).floor() as usize;
+
+        // Nobody gets promoted for doing nothing
+        if rank < promoted_count && weekly_xp > 0 {
+            self.promoted()
+        } else if rank >= league_size - demoted_count {
+            self.demoted()
+        } else {
+            *self
+        }
+    }
+}
+
+/// Monday of the current (UTC) week, leagues are reset on it
+pub fn current_week_start() -> NaiveDate {
+    let today = Utc::now().date_naive();
+    today - chrono::Days::new(today.weekday().num_days_from_monday() as u64)
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct LeaderboardEntry {
+    pub rank: u32,
+    pub user_id: Uuid,
+    pub username: String,
+    pub xp: u32
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Leaderboard {
+    pub page: u32,
+    pub page_size: u32,
+    pub tier: Option<LeagueTier>, // Only for league leaderboards
+    pub entries: Vec<LeaderboardEntry>
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct LeagueMembership {
+    pub user_id: Uuid,
+    pub tier: LeagueTier,
+    pub week_start: NaiveDate,
+    pub weekly_xp: u32
+}
+
+// Saturates instead of wrapping, a page that far back is just empty
+fn offset(page: u32, page_size: u32) -> u32 {
+    page.saturating_mul(page_size)
+}
+
+fn to_entries(offset: u32, rows: Vec<(String, String, i64)>) -> Vec<LeaderboardEntry> {
+    rows.into_iter()
+        .enumerate()
+        .map(|(i, (id, username, xp))| LeaderboardEntry {
+            rank: offset + i as u32 + 1,
+            user_id: Uuid::parse_str(&id).expect("Couldn't parse string to Uuid"),
+            username,
+            xp: xp as u32
+        })
+        .collect()
+}
+
+pub mod database {
+    use super::*;
+
+    impl User {
+        pub async fn award_xp(id: Uuid, amount: u32, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            // MySQL evaluates the assignments in order, so `level` sees the updated `xp`
+            query!(
+                "UPDATE users SET xp = xp + ?, level = xp DIV ? WHERE id = ?",
+                amount,
+                XP_PER_LEVEL,
+                id.to_string()
+            ).execute(transaction.as_mut()).await?;
+
+            // XP from before the rollover got to last week would be mixed into it (or replace it and skip
+            // the promotion), so that week is settled first
+            let membership = query!("SELECT week_start FROM league_members WHERE user_id = ? FOR UPDATE", id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+            if membership.is_some_and(|row| row.week_start < current_week_start()) {
+                LeagueMembership::rollover(transaction).await?;
+            }
+
+            query!(
+                "INSERT INTO league_members (user_id, tier, week_start, weekly_xp) VALUES (?, ?, ?, ?)
+                ON DUPLICATE KEY UPDATE weekly_xp = weekly_xp + VALUES(weekly_xp)",
+                id.to_string(),
+                LeagueTier::Bronze.index(),
+                current_week_start(),
+                amount
+            ).execute(transaction.as_mut()).await?;
+
+            info!("User {} was awarded {} XP", id, amount);
+            Ok(())
+        }
+
+        /// `false` if the user already solved the task, XP is only awarded for the first correct answer
+        pub async fn record_solved_task(id: Uuid, task_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            let inserted = query!(
+                "INSERT IGNORE INTO solved_tasks (user_id, task_id, solve_time) VALUES (?, ?, ?)",
+                id.to_string(),
+                task_id.to_string(),
+                Utc::now()
+            ).execute(transaction.as_mut()).await?;
+
+            Ok(inserted.rows_affected() > 0)
+        }
+    }
+
+    impl LeagueMembership {
+        pub async fn read(user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<LeagueMembership, sqlx::Error> {
+            let row = query!("SELECT * FROM league_members WHERE user_id = ?", user_id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+
+            Ok(match row {
+                Some(row) => LeagueMembership {
+                    user_id,
+                    tier: LeagueTier::from_index(row.tier),
+                    week_start: row.week_start,
+                    weekly_xp: row.weekly_xp as u32
+                },
+                // Users who haven't earned anything yet start in the lowest league
+                None => LeagueMembership {
+                    user_id,
+                    tier: LeagueTier::Bronze,
+                    week_start: current_week_start(),
+                    weekly_xp: 0
+                }
+            })
+        }
+
+        /// Promotes and demotes members of every league that finished before the current week.
+        /// Returns the number of members moved into the new week.
+        ///
+        /// Also run by `award_xp`, the rows are locked so a league isn't settled twice
+        pub async fn rollover(transaction: &mut Transaction<'static, MySql>) -> Result<usize, sqlx::Error> {
+            let week_start = current_week_start();
+
+            let rows = query!(
+                "SELECT user_id, tier, week_start, weekly_xp FROM league_members WHERE week_start < ?
+                ORDER BY week_start, tier, weekly_xp DESC FOR UPDATE",
+                week_start
+            ).fetch_all(transaction.as_mut()).await?;
+
+            // Rows are sorted, so every league is a contiguous chunk
+            let mut processed = 0;
+            for league in rows.chunk_by(|a, b| a.week_start == b.week_start && a.tier == b.tier) {
+                for (rank, row) in league.iter().enumerate() {
+                    let tier = LeagueTier::from_index(row.tier);
+                    let next_tier = tier.next(rank, league.len(), row.weekly_xp as u32);
+
+                    query!(
+                        "UPDATE league_members SET tier = ?, week_start = ?, weekly_xp = 0 WHERE user_id = ?",
+                        next_tier.index(),
+                        week_start,
+                        row.user_id
+                    ).execute(transaction.as_mut()).await?;
+
+                    if next_tier != tier {
+                        DomainEvent::LeagueChanged {
+                            user_id: Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid"),
+                            previous_tier: tier,
+                            tier: next_tier,
+                            rank: rank as u32 + 1
+                        }.record(transaction).await?;
+                    }
+                }
+                processed += league.len();
+            }
+
+            if processed > 0 {
+                info!("League rollover moved {} members into the week of {}", processed, week_start);
+            }
+            Ok(processed)
+        }
+    }
+
+    impl Leaderboard {
+        /// With `verified_only` users without a verified email or phone are left out,
+        /// accounts waiting to be purged never show up
+        pub async fn global(page: u32, page_size: u32, verified_only: bool, transaction: &mut Transaction<'static, MySql>) -> Result<Leaderboard, sqlx::Error> {
+            let offset = offset(page, page_size);
+            let rows = query!(
+                "SELECT id, username, xp FROM users
+                WHERE (? = FALSE OR email_verified_time IS NOT NULL OR phone_verified_time IS NOT NULL)
+                AND deletion_requested_time IS NULL
+                ORDER BY xp DESC, username LIMIT ? OFFSET ?",
+                verified_only,
+                page_size,
+                offset
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(Leaderboard {
+                page,
+                page_size,
+                tier: None,
+                entries: to_entries(offset, rows.into_iter().map(|row| (row.id, row.username, row.xp as i64)).collect())
+            })
+        }
+
+        pub async fn friends(user_id: Uuid, page: u32, page_size: u32, verified_only: bool, transaction: &mut Transaction<'static, MySql>) -> Result<Leaderboard, sqlx::Error> {
+            let offset = offset(page, page_size);
+            let rows = query!(
+                "SELECT id, username, xp FROM users
+                WHERE (id = ? OR id IN (SELECT user_id_2 FROM friends WHERE user_id_1 = ?))
+                AND (? = FALSE OR email_verified_time IS NOT NULL OR phone_verified_time IS NOT NULL)
+                AND deletion_requested_time IS NULL
+                ORDER BY xp DESC, username LIMIT ? OFFSET ?",
+                user_id.to_string(),
+                user_id.to_string(),
+                verified_only,
+                page_size,
+                offset
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(Leaderboard {
+                page,
+                page_size,
+                tier: None,
+                entries: to_entries(offset, rows.into_iter().map(|row| (row.id, row.username, row.xp as i64)).collect())
+            })
+        }
+
+        pub async fn league(user_id: Uuid, page: u32, page_size: u32, verified_only: bool, transaction: &mut Transaction<'static, MySql>) -> Result<Leaderboard, sqlx::Error> {
+            let membership = LeagueMembership::read(user_id, transaction).await?;
+            let offset = offset(page, page_size);
+            let rows = query!(
+                "SELECT users.id, users.username, league_members.weekly_xp FROM league_members
+                JOIN users ON users.id = league_members.user_id
+                WHERE league_members.tier = ? AND league_members.week_start = ?
+                AND (? = FALSE OR users.email_verified_time IS NOT NULL OR users.phone_verified_time IS NOT NULL)
+                AND users.deletion_requested_time IS NULL
+                ORDER BY league_members.weekly_xp DESC, users.username LIMIT ? OFFSET ?",
+                membership.tier.index(),
+                membership.week_start,
+                verified_only,
+                page_size,
+                offset
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(Leaderboard {
+                page,
+                page_size,
+                tier: Some(membership.tier),
+                entries: to_entries(offset, rows.into_iter().map(|row| (row.id, row.username, row.weekly_xp as i64)).collect())
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+
+        #[tokio::test]
+        async fn test_award_xp() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("leaderboard_test".to_string(), "aaaaa".to_string(), Some("test@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+
+            User::award_xp(user.id, XP_PER_LEVEL + 5, &mut tx).await.unwrap();
+
+            let read_user = User::read(user.id, &mut tx).await.unwrap();
+            assert_eq!(read_user.level, crate::models::user::UserLevel { level: 1, xp: XP_PER_LEVEL + 5 });
+
+            let membership = LeagueMembership::read(user.id, &mut tx).await.unwrap();
+            assert_eq!(membership.weekly_xp, XP_PER_LEVEL + 5);
+            assert_eq!(membership.tier, LeagueTier::Bronze);
+
+            let leaderboard = Leaderboard::league(user.id, 0, 100, false, &mut tx).await.unwrap();
+            assert!(leaderboard.entries.iter().any(|entry| entry.user_id == user.id));
+
+            let leaderboard = Leaderboard::league(user.id, 0, 100, true, &mut tx).await.unwrap();
+            assert!(!leaderboard.entries.iter().any(|entry| entry.user_id == user.id));
+
+            // Neither are accounts about to be deleted
+            User::request_deletion(user.id, &crate::config::AccountDeletionConfig::default(), &mut tx).await.unwrap();
+            let leaderboard = Leaderboard::league(user.id, 0, 100, false, &mut tx).await.unwrap();
+            assert!(!leaderboard.entries.iter().any(|entry| entry.user_id == user.id));
+            let leaderboard = Leaderboard::friends(user.id, 0, 100, false, &mut tx).await.unwrap();
+            assert!(leaderboard.entries.is_empty());
+            User::cancel_deletion(user.id, &mut tx).await.unwrap();
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_award_xp_before_rollover() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("leaderboard_test".to_string(), "aaaaa".to_string(), Some("test@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+
+            // The week ended with the user alone on top of their league, but the rollover didn't run yet
+            User::award_xp(user.id, 50, &mut tx).await.unwrap();
+            query!("UPDATE league_members SET week_start = ? WHERE user_id = ?", current_week_start() - chrono::Days::new(7), user.id.to_string())
+                .execute(tx.as_mut()).await.unwrap();
+
+            User::award_xp(user.id, 5, &mut tx).await.unwrap();
+            let membership = LeagueMembership::read(user.id, &mut tx).await.unwrap();
+            assert_eq!(membership.tier, LeagueTier::Silver);
+            assert_eq!((membership.week_start, membership.weekly_xp), (current_week_start(), 5));
+
+            // Nothing left for the rollover to do with them
+            LeagueMembership::rollover(&mut tx).await.unwrap();
+            assert_eq!(LeagueMembership::read(user.id, &mut tx).await.unwrap(), membership);
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_record_solved_task() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("leaderboard_test".to_string(), "aaaaa".to_string(), Some("test@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+            let task_id = query!("SELECT id FROM tasks LIMIT 1").fetch_one(tx.as_mut()).await.unwrap().id;
+            let task_id = Uuid::parse_str(&task_id).unwrap();
+
+            assert!(User::record_solved_task(user.id, task_id, &mut tx).await.unwrap());
+            assert!(!User::record_solved_task(user.id, task_id, &mut tx).await.unwrap());
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_tier() {
+        let tier = LeagueTier::Gold;
+        assert_eq!(tier.next(0, 10, 50), LeagueTier::Sapphire);
+        assert_eq!(tier.next(1, 10, 50), LeagueTier::Sapphire);
+        assert_eq!(tier.next(2, 10, 50), LeagueTier::Gold);
+        assert_eq!(tier.next(7, 10, 0), LeagueTier::Gold);
+        assert_eq!(tier.next(8, 10, 0), LeagueTier::Silver);
+        assert_eq!(tier.next(0, 10, 0), LeagueTier::Gold);
+        assert_eq!(LeagueTier::Diamond.next(0, 1, 50), LeagueTier::Diamond);
+        assert_eq!(LeagueTier::Bronze.next(9, 10, 0), LeagueTier::Bronze);
+    }
+
+    #[test]
+    fn test_week_start() {
+        let week_start = current_week_start();
+        assert_eq!(week_start.weekday(), Weekday::Mon);
+        assert!(Utc::now().date_naive() - week_start < chrono::Duration::days(7));
+    }
+
+    #[test]
+    fn test_offset() {
+        assert_eq!(offset(3, 20), 60);
+        assert_eq!(offset(u32::MAX, 100), u32::MAX);
+    }
+}
@@ -0,0 +1,227 @@
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::{info, warn};
+use super::password_reset::{generate_token, hash_token};
+use super::user::User;
+use crate::config::LockoutConfig;
+
+/// What failed logins are counted for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Throttle {
+    Account(Uuid),
+    Ip(String)
+}
+
+impl Throttle {
+    fn scope(&self) -> &'static str {
+        match self {
+            Throttle::Account(_) => "Account",
+            Throttle::Ip(_) => "Ip"
+        }
+    }
+
+    fn subject(&self) -> String {
+        match self {
+            Throttle::Account(id) => id.to_string(),
+            Throttle::Ip(ip) => ip.clone()
+        }
+    }
+
+    fn free_attempts(&self, config: &LockoutConfig) -> u32 {
+        match self {
+            Throttle::Account(_) => config.free_attempts,
+            Throttle::Ip(_) => config.ip_free_attempts
+        }
+    }
+}
+
+pub mod database {
+    use super::*;
+
+    impl Throttle {
+        /// How long until the next login can be tried, `None` if it can be tried now
+        pub async fn wait(&self, config: &LockoutConfig, transaction: &mut Transaction<'static, MySql>) -> Result<Option<chrono::Duration>, sqlx::Error> {
+            let row = query!(
+                "SELECT failures, last_failure_time, locked_until FROM login_failures WHERE scope = ? AND subject = ?",
+                self.scope(),
+                self.subject()
+            ).fetch_optional(transaction.as_mut()).await?;
+
+            let row = match row {
+                Some(row) => row,
+                None => return Ok(None)
+            };
+
+            let now = Utc::now();
+            if let Some(locked_until) = row.locked_until.map(|time| time.and_utc()) {
+                if locked_until > now {
+                    return Ok(Some(locked_until - now));
+                }
+            }
+
+            let last_failure = row.last_failure_time.and_utc();
+            if last_failure + config.forget_after() < now {
+                return Ok(None);
+            }
+
+            let next_allowed = last_failure + config.backoff(row.failures as u32, self.free_attempts(config));
+            Ok((next_allowed > now).then(|| next_allowed - now))
+        }
+
+        /// Returns `true` if this failure locked the account
+        pub async fn record_failure(&self, config: &LockoutConfig, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            let now = Utc::now();
+
+            // `failures` is updated first, so it still sees the previous failure time
+            query!(
+                "INSERT INTO login_failures (scope, subject, failures, last_failure_time, locked_until) VALUES (?, ?, 1, ?, NULL)
+                ON DUPLICATE KEY UPDATE failures = IF(last_failure_time < ?, 1, failures + 1), last_failure_time = ?",
+                self.scope(),
+                self.subject(),
+                now,
+                now - config.forget_after(),
+                now
+            ).execute(transaction.as_mut()).await?;
+
+            // Addresses only get slowed down, a whole school could be locked out otherwise
+            if let Throttle::Ip(_) = self {
+                return Ok(false);
+            }
+
+            let row = query!(
+                "SELECT failures, locked_until FROM login_failures WHERE scope = ? AND subject = ?",
+                self.scope(),
+                self.subject()
+            ).fetch_one(transaction.as_mut()).await?;
+
+            let locked = row.locked_until.is_some_and(|time| time.and_utc() > now);
+            if locked || (row.failures as u32) < config.lock_after {
+                return Ok(false);
+            }
+
+            query!(
+                "UPDATE login_failures SET locked_until = ? WHERE scope = ? AND subject = ?",
+                now + config.lock_duration(),
+                self.scope(),
+                self.subject()
+            ).execute(transaction.as_mut()).await?;
+
+            warn!("{:?} locked after {} failed logins", self, row.failures);
+            Ok(true)
+        }
+
+        pub async fn clear(&self, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!("DELETE FROM login_failures WHERE scope = ? AND subject = ?", self.scope(), self.subject())
+                .execute(transaction.as_mut()).await?;
+            Ok(())
+        }
+    }
+
+    impl User {
+        pub async fn read_id_by_username(username: &str, transaction: &mut Transaction<'static, MySql>) -> Result<Option<Uuid>, sqlx::Error> {
+            let row = query!("SELECT id FROM users WHERE username COLLATE utf8mb4_bin = ?", username)
+                .fetch_optional(transaction.as_mut()).await?;
+
+            Ok(row.map(|row| Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid")))
+        }
+
+        /// For the link sent when the account gets locked, it works as long as the lock
+        pub async fn create_unlock_token(id: Uuid, config: &LockoutConfig, transaction: &mut Transaction<'static, MySql>) -> Result<String, sqlx::Error> {
+            let token = generate_token();
+
+            query!(
+                "INSERT INTO unlock_tokens (token_hash, user_id, expiration_time) VALUES (?, ?, ?)",
+                hash_token(&token),
+                id.to_string(),
+                Utc::now() + config.lock_duration()
+            ).execute(transaction.as_mut()).await?;
+
+            Ok(token)
+        }
+
+        /// Lifts the lock and forgets the failures, returns whose account it was
+        pub async fn unlock(token: &str, transaction: &mut Transaction<'static, MySql>) -> Result<Option<Uuid>, sqlx::Error> {
+            let row = query!(
+                "SELECT user_id FROM unlock_tokens WHERE token_hash = ? AND expiration_time > ?",
+                hash_token(token),
+                Utc::now()
+            ).fetch_optional(transaction.as_mut()).await?;
+
+            let id = match row {
+                Some(row) => Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid"),
+                None => return Ok(None)
+            };
+
+            query!("DELETE FROM unlock_tokens WHERE user_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+            Throttle::Account(id).clear(transaction).await?;
+
+            info!("User {} unlocked their account", id);
+            Ok(Some(id))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+
+        #[tokio::test]
+        async fn test_backoff_and_lock() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let config = LockoutConfig { free_attempts: 2, lock_after: 4, ..Default::default() };
+
+            let account = Throttle::Account(Uuid::new_v4());
+            assert_eq!(account.wait(&config, &mut tx).await.unwrap(), None);
+
+            assert!(!account.record_failure(&config, &mut tx).await.unwrap());
+            assert_eq!(account.wait(&config, &mut tx).await.unwrap(), None);
+            assert!(!account.record_failure(&config, &mut tx).await.unwrap());
+            assert!(account.wait(&config, &mut tx).await.unwrap().is_some());
+
+            assert!(!account.record_failure(&config, &mut tx).await.unwrap());
+            assert!(account.record_failure(&config, &mut tx).await.unwrap());
+            let wait = account.wait(&config, &mut tx).await.unwrap().unwrap();
+            assert!(wait > chrono::Duration::minutes(59));
+
+            // Already locked, it isn't locked again
+            assert!(!account.record_failure(&config, &mut tx).await.unwrap());
+
+            // Addresses are never locked
+            let ip = Throttle::Ip("10.0.0.1".to_string());
+            for _ in 0..30 {
+                assert!(!ip.record_failure(&config, &mut tx).await.unwrap());
+            }
+            assert!(ip.wait(&config, &mut tx).await.unwrap().unwrap() <= chrono::Duration::seconds(config.max_delay_secs as i64));
+
+            account.clear(&mut tx).await.unwrap();
+            assert_eq!(account.wait(&config, &mut tx).await.unwrap(), None);
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_unlock() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let config = LockoutConfig { free_attempts: 1, lock_after: 1, ..Default::default() };
+
+            let user = User::new("lockout_test".to_string(), "aaaaa".to_string(), Some("lockout@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+            assert_eq!(User::read_id_by_username("lockout_test", &mut tx).await.unwrap(), Some(user.id));
+
+            let account = Throttle::Account(user.id);
+            assert!(account.record_failure(&config, &mut tx).await.unwrap());
+            let token = User::create_unlock_token(user.id, &config, &mut tx).await.unwrap();
+
+            assert_eq!(User::unlock("wrong", &mut tx).await.unwrap(), None);
+            assert_eq!(User::unlock(&token, &mut tx).await.unwrap(), Some(user.id));
+            assert_eq!(account.wait(&config, &mut tx).await.unwrap(), None);
+            assert_eq!(User::unlock(&token, &mut tx).await.unwrap(), None);
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
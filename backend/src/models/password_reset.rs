@@ -0,0 +1,188 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::{info, warn};
+use super::user::User;
+use crate::password::{hasher, HashError};
+
+#[derive(Debug)]
+pub enum PasswordResetError {
+    InvalidToken,
+    EmptyPassword,
+    DatabaseError(sqlx::Error),
+    HashError(HashError)
+}
+
+/// Random token sent to the user, only its hash is stored
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub mod database {
+    use super::*;
+
+    impl User {
+        /// Finds the user a reset was requested for, by verified email or phone
+        ///
+        /// Contacts aren't unique, if more than one account has verified it nobody gets the reset
+        pub async fn read_by_contact(email: Option<&str>, phone: Option<&str>, transaction: &mut Transaction<'static, MySql>) -> Result<Option<User>, sqlx::Error> {
+            let rows = query!(
+                "SELECT id FROM users
+                WHERE (email = ? AND email_verified_time IS NOT NULL) OR (phone = ? AND phone_verified_time IS NOT NULL)
+                LIMIT 2",
+                email,
+                phone
+            ).fetch_all(transaction.as_mut()).await?;
+
+            match rows.as_slice() {
+                [row] => {
+                    let id = Uuid::parse_str(&row.id)
+                        .map_err(|e| sqlx::Error::Decode(e.into()))?;
+                    Ok(Some(User::read(id, transaction).await?))
+                },
+                [] => Ok(None),
+                _ => {
+                    warn!("Password reset contact is verified by more than one account");
+                    Ok(None)
+                }
+            }
+        }
+
+        /// Replaces any earlier unused token of the user, returns the new token in plain text
+        pub async fn create_password_reset(id: Uuid, ttl: chrono::Duration, transaction: &mut Transaction<'static, MySql>) -> Result<String, sqlx::Error> {
+            query!("DELETE FROM password_reset_tokens WHERE user_id = ? AND used_time IS NULL", id.to_string())
+                .execute(transaction.as_mut()).await?;
+
+            let token = generate_token();
+            query!(
+                "INSERT INTO password_reset_tokens (token_hash, user_id, creation_time, expiration_time) VALUES (?, ?, ?, ?)",
+                hash_token(&token),
+                id.to_string(),
+                Utc::now(),
+                Utc::now() + ttl
+            ).execute(transaction.as_mut()).await?;
+
+            info!("Password reset requested for user {}", id);
+            Ok(token)
+        }
+
+        /// Uses up the token, sets the new password and logs the user out everywhere
+        pub async fn reset_password(token: &str, password: String, transaction: &mut Transaction<'static, MySql>) -> Result<Uuid, PasswordResetError> {
+            if password.is_empty() {
+                return Err(PasswordResetError::EmptyPassword);
+            }
+
+            let token_hash = hash_token(token);
+            let row = query!(
+                "SELECT user_id FROM password_reset_tokens WHERE token_hash = ? AND used_time IS NULL AND expiration_time > ? FOR UPDATE",
+                token_hash,
+                Utc::now()
+            ).fetch_optional(transaction.as_mut()).await.map_err(PasswordResetError::DatabaseError)?;
+
+            let user_id = match row {
+                Some(row) => Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid"),
+                None => {
+                    warn!("Password reset with an invalid, used or expired token");
+                    return Err(PasswordResetError::InvalidToken);
+                }
+            };
+
+            query!("UPDATE password_reset_tokens SET used_time = ? WHERE token_hash = ?", Utc::now(), token_hash)
+                .execute(transaction.as_mut()).await.map_err(PasswordResetError::DatabaseError)?;
+
+            let password_hash = hasher().hash(&password).await.map_err(PasswordResetError::HashError)?;
+            query!("UPDATE users SET password_hash = ? WHERE id = ?", password_hash, user_id.to_string())
+                .execute(transaction.as_mut()).await.map_err(PasswordResetError::DatabaseError)?;
+
+            query!("DELETE FROM sessions WHERE user_id = ?", user_id.to_string())
+                .execute(transaction.as_mut()).await.map_err(PasswordResetError::DatabaseError)?;
+
+            info!("Password of user {} reset, all sessions invalidated", user_id);
+            Ok(user_id)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+        use crate::config::{SessionConfig, TwoFactorConfig};
+        use crate::models::session::ClientInfo;
+
+        #[tokio::test]
+        async fn test_reset_password() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("reset_test".to_string(), "aaaaa".to_string(), Some("reset@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+            User::login("reset_test".to_string(), "aaaaa".to_string(), &ClientInfo::default(), &SessionConfig::default(), &TwoFactorConfig::default(), &mut tx).await.unwrap();
+
+            // Only verified contacts count
+            assert!(User::read_by_contact(Some("reset@test.com"), None, &mut tx).await.unwrap().is_none());
+
+            query!("UPDATE users SET email_verified_time = ? WHERE id = ?", Utc::now(), user.id.to_string())
+                .execute(tx.as_mut()).await.unwrap();
+            let found = User::read_by_contact(Some("reset@test.com"), None, &mut tx).await.unwrap().unwrap();
+            assert_eq!(found.id, user.id);
+
+            // Ambiguous contacts don't match anyone
+            let other = User::new("reset_test_other".to_string(), "aaaaa".to_string(), Some("reset@test.com".to_string()), None, &mut tx).await.unwrap();
+            other.create(&mut tx).await.unwrap();
+            query!("UPDATE users SET email_verified_time = ? WHERE id = ?", Utc::now(), other.id.to_string())
+                .execute(tx.as_mut()).await.unwrap();
+            assert!(User::read_by_contact(Some("reset@test.com"), None, &mut tx).await.unwrap().is_none());
+
+            let token = User::create_password_reset(user.id, chrono::Duration::minutes(30), &mut tx).await.unwrap();
+            assert_eq!(User::reset_password(&token, "bbbbb".to_string(), &mut tx).await.unwrap(), user.id);
+
+            // Single use
+            assert!(matches!(User::reset_password(&token, "ccccc".to_string(), &mut tx).await, Err(PasswordResetError::InvalidToken)));
+
+            assert!(User::login("reset_test".to_string(), "aaaaa".to_string(), &ClientInfo::default(), &SessionConfig::default(), &TwoFactorConfig::default(), &mut tx).await.is_err());
+            assert!(User::login("reset_test".to_string(), "bbbbb".to_string(), &ClientInfo::default(), &SessionConfig::default(), &TwoFactorConfig::default(), &mut tx).await.is_ok());
+
+            let sessions = query!("SELECT COUNT(*) AS count FROM sessions WHERE user_id = ?", user.id.to_string())
+                .fetch_one(tx.as_mut()).await.unwrap();
+            assert_eq!(sessions.count, 1);
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_expired_token() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("reset_test".to_string(), "aaaaa".to_string(), Some("reset@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+
+            let token = User::create_password_reset(user.id, chrono::Duration::minutes(-1), &mut tx).await.unwrap();
+            assert!(matches!(User::reset_password(&token, "bbbbb".to_string(), &mut tx).await, Err(PasswordResetError::InvalidToken)));
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_hash() {
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(generate_token(), token);
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use tracing::info;
+use super::achievement::UnlockedAchievement;
+use super::user::{User, UserLevel};
+
+/// Who gets to see a field of the public profile
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Visibility {
+    Public,
+    Friends,
+    Private
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "Public",
+            Visibility::Friends => "Friends",
+            Visibility::Private => "Private"
+        }
+    }
+
+    pub fn parse(visibility: &str) -> Option<Visibility> {
+        match visibility {
+            "Public" => Some(Visibility::Public),
+            "Friends" => Some(Visibility::Friends),
+            "Private" => Some(Visibility::Private),
+            _ => None
+        }
+    }
+
+    pub fn allows(&self, viewer: Viewer) -> bool {
+        match self {
+            Visibility::Public => true,
+            Visibility::Friends => viewer != Viewer::Stranger,
+            Visibility::Private => viewer == Viewer::Owner
+        }
+    }
+}
+
+/// How whoever looks at a profile is related to its owner, anonymous requests are strangers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Viewer {
+    Owner,
+    Friend,
+    Stranger
+}
+
+/// The username and avatar are always public, everything else can be hidden
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PrivacySettings {
+    pub bio: Visibility,
+    pub level: Visibility,
+    pub streak: Visibility,
+    pub achievements: Visibility,
+    /// Shows up in search and friend suggestions, the profile can still be opened directly
+    #[serde(default = "discoverable_default")]
+    pub discoverable: bool
+}
+
+fn discoverable_default() -> bool {
+    true
+}
+
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        PrivacySettings {
+            bio: Visibility::Public,
+            level: Visibility::Public,
+            streak: Visibility::Public,
+            achievements: Visibility::Public,
+            discoverable: true
+        }
+    }
+}
+
+/// What others see of a user, hidden fields are left out
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PublicProfile {
+    pub id: Uuid,
+    pub username: String,
+    #[serde(skip)]
+    pub avatar_version: Option<String>,
+    /// Size to url, filled in by the server which knows where avatars are stored
+    pub avatar: Option<BTreeMap<String, String>>,
+    pub bio: Option<String>,
+    pub level: Option<UserLevel>,
+    pub streak: Option<u32>,
+    pub achievements: Option<Vec<UnlockedAchievement>>
+}
+
+pub mod database {
+    use super::*;
+
+    fn parse_visibility(visibility: &str) -> Visibility {
+        Visibility::parse(visibility).unwrap_or_else(|| panic!("Invalid visibility {} in the database", visibility))
+    }
+
+    impl User {
+        /// Users who never changed them get the defaults
+        pub async fn read_privacy(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<PrivacySettings, sqlx::Error> {
+            let row = query!("SELECT bio, level, streak, achievements, discoverable FROM profile_privacy WHERE user_id = ?", id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+
+            Ok(match row {
+                Some(row) => PrivacySettings {
+                    bio: parse_visibility(&row.bio),
+                    level: parse_visibility(&row.level),
+                    streak: parse_visibility(&row.streak),
+                    achievements: parse_visibility(&row.achievements),
+                    discoverable: row.discoverable != 0
+                },
+                None => PrivacySettings::default()
+            })
+        }
+
+        pub async fn update_privacy(id: Uuid, settings: &PrivacySettings, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!(
+                "INSERT INTO profile_privacy (user_id, bio, level, streak, achievements, discoverable) VALUES (?, ?, ?, ?, ?, ?)
+                ON DUPLICATE KEY UPDATE bio = VALUES(bio), level = VALUES(level), streak = VALUES(streak), achievements = VALUES(achievements),
+                discoverable = VALUES(discoverable)",
+                id.to_string(),
+                settings.bio.as_str(),
+                settings.level.as_str(),
+                settings.streak.as_str(),
+                settings.achievements.as_str(),
+                settings.discoverable
+            ).execute(transaction.as_mut()).await?;
+
+            info!("User {} changed their privacy settings", id);
+            Ok(())
+        }
+
+        /// Returns the previous version, its images can be removed once this is committed
+        pub async fn set_avatar(id: Uuid, version: Option<&str>, transaction: &mut Transaction<'static, MySql>) -> Result<Option<String>, sqlx::Error> {
+            let previous = query!("SELECT avatar FROM users WHERE id = ? FOR UPDATE", id.to_string())
+                .fetch_optional(transaction.as_mut()).await?
+                .and_then(|row| row.avatar);
+
+            query!("UPDATE users SET avatar = ? WHERE id = ?", version, id.to_string())
+                .execute(transaction.as_mut()).await?;
+
+            Ok(previous)
+        }
+
+        /// `None` if there's no such user or `viewer` isn't supposed to know about them,
+        /// accounts waiting to be purged and blocks in either direction look the same as missing users
+        pub async fn read_profile(id: Uuid, viewer: Option<Uuid>, transaction: &mut Transaction<'static, MySql>) -> Result<Option<PublicProfile>, sqlx::Error> {
+            let row = query!("SELECT username, avatar, bio, level, xp, deletion_requested_time FROM users WHERE id = ?", id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+
+            let row = match row {
+                Some(row) if row.deletion_requested_time.is_none() => row,
+                _ => return Ok(None)
+            };
+
+            let viewer = match viewer {
+                Some(viewer_id) if viewer_id == id => Viewer::Owner,
+                Some(viewer_id) => {
+                    if User::is_blocked_between(&id, &viewer_id, transaction).await? {
+                        return Ok(None);
+                    }
+                    if User::are_friends(&id, &viewer_id, transaction).await? { Viewer::Friend } else { Viewer::Stranger }
+                },
+                None => Viewer::Stranger
+            };
+
+            let settings = User::read_privacy(id, transaction).await?;
+
+            let streak = match settings.streak.allows(viewer) {
+                true => Some(User::read_streak(id, transaction).await?),
+                false => None
+            };
+            let achievements = match settings.achievements.allows(viewer) {
+                true => Some(User::read_achievements(id, transaction).await?),
+                false => None
+            };
+
+            Ok(Some(PublicProfile {
+                id,
+                username: row.username,
+                avatar_version: row.avatar,
+                avatar: None,
+                bio: row.bio.filter(|_| settings.bio.allows(viewer)),
+                level: settings.level.allows(viewer).then(|| UserLevel { level: row.level as u32, xp: row.xp as u32 }),
+                streak,
+                achievements
+            }))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+
+        #[tokio::test]
+        async fn test_privacy_settings() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("privacy_test".to_string(), "aaaaa".to_string(), Some("privacy@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+            assert_eq!(User::read_privacy(user.id, &mut tx).await.unwrap(), PrivacySettings::default());
+
+            let settings = PrivacySettings { streak: Visibility::Friends, achievements: Visibility::Private, discoverable: false, ..Default::default() };
+            User::update_privacy(user.id, &settings, &mut tx).await.unwrap();
+            assert_eq!(User::read_privacy(user.id, &mut tx).await.unwrap(), settings);
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_read_profile() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let mut owner = User::new("profile_test".to_string(), "aaaaa".to_string(), Some("profile@test.com".to_string()), None, &mut tx).await.unwrap();
+            owner.bio = Some("Hi".to_string());
+            owner.create(&mut tx).await.unwrap();
+            let stranger = User::new("profile_test_2".to_string(), "aaaaa".to_string(), Some("profile2@test.com".to_string()), None, &mut tx).await.unwrap();
+            stranger.create(&mut tx).await.unwrap();
+
+            let settings = PrivacySettings { bio: Visibility::Friends, streak: Visibility::Private, ..Default::default() };
+            User::update_privacy(owner.id, &settings, &mut tx).await.unwrap();
+
+            let profile = User::read_profile(owner.id, None, &mut tx).await.unwrap().unwrap();
+            assert_eq!(profile.username, "profile_test");
+            assert_eq!(profile.bio, None);
+            assert_eq!(profile.streak, None);
+            assert!(profile.level.is_some());
+            assert!(profile.achievements.is_some());
+
+            let profile = User::read_profile(owner.id, Some(owner.id), &mut tx).await.unwrap().unwrap();
+            assert_eq!(profile.bio.as_deref(), Some("Hi"));
+            assert!(profile.streak.is_some());
+
+            assert!(User::read_profile(owner.id, Some(stranger.id), &mut tx).await.unwrap().unwrap().bio.is_none());
+            assert_eq!(User::read_profile(Uuid::new_v4(), None, &mut tx).await.unwrap(), None);
+
+            assert_eq!(User::set_avatar(owner.id, Some("v1"), &mut tx).await.unwrap(), None);
+            assert_eq!(User::set_avatar(owner.id, Some("v2"), &mut tx).await.unwrap().as_deref(), Some("v1"));
+            let profile = User::read_profile(owner.id, None, &mut tx).await.unwrap().unwrap();
+            assert_eq!(profile.avatar_version.as_deref(), Some("v2"));
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visibility() {
+        assert!(Visibility::Public.allows(Viewer::Stranger));
+        assert!(!Visibility::Friends.allows(Viewer::Stranger));
+        assert!(Visibility::Friends.allows(Viewer::Friend));
+        assert!(!Visibility::Private.allows(Viewer::Friend));
+        assert!(Visibility::Private.allows(Viewer::Owner));
+        assert_eq!(Visibility::parse(Visibility::Friends.as_str()), Some(Visibility::Friends));
+    }
+}
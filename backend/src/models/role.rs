@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use tracing::info;
+use super::user::User;
+
+/// Extra permissions on top of a regular learner account
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Role {
+    Author,
+    Teacher,
+    Admin
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Author => "Author",
+            Role::Teacher => "Teacher",
+            Role::Admin => "Admin"
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "Author" => Some(Role::Author),
+            "Teacher" => Some(Role::Teacher),
+            "Admin" => Some(Role::Admin),
+            _ => None
+        }
+    }
+}
+
+pub mod database {
+    use super::*;
+
+    impl User {
+        pub async fn read_roles(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<Role>, sqlx::Error> {
+            let rows = query!("SELECT role FROM user_roles WHERE user_id = ? ORDER BY role", id.to_string())
+                .fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.into_iter()
+                .map(|row| Role::parse(&row.role).unwrap_or_else(|| panic!("Invalid role {} in the database", row.role)))
+                .collect())
+        }
+
+        pub async fn grant_role(id: Uuid, role: Role, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!("INSERT IGNORE INTO user_roles (user_id, role) VALUES (?, ?)", id.to_string(), role.as_str())
+                .execute(transaction.as_mut()).await?;
+            info!("User {} granted role {:?}", id, role);
+            Ok(())
+        }
+
+        pub async fn revoke_role(id: Uuid, role: Role, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!("DELETE FROM user_roles WHERE user_id = ? AND role = ?", id.to_string(), role.as_str())
+                .execute(transaction.as_mut()).await?;
+            info!("User {} lost role {:?}", id, role);
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+
+        #[tokio::test]
+        async fn test_roles() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("role_test".to_string(), "aaaaa".to_string(), Some("role@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+            assert!(User::read_roles(user.id, &mut tx).await.unwrap().is_empty());
+
+            User::grant_role(user.id, Role::Teacher, &mut tx).await.unwrap();
+            User::grant_role(user.id, Role::Admin, &mut tx).await.unwrap();
+            User::grant_role(user.id, Role::Admin, &mut tx).await.unwrap();
+            assert_eq!(User::read_roles(user.id, &mut tx).await.unwrap(), vec![Role::Admin, Role::Teacher]);
+
+            User::revoke_role(user.id, Role::Admin, &mut tx).await.unwrap();
+            assert_eq!(User::read_roles(user.id, &mut tx).await.unwrap(), vec![Role::Teacher]);
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::sync::OnceLock;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::{query, MySql, MySqlPool, Row, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::{info, warn};
+use super::password_reset::{generate_token, hash_token};
+use crate::config::SessionConfig;
+
+// Sliding expiration isn't written more often than this, so every request doesn't update the row
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+// Only long enough to open the notification stream right after asking for it
+const STREAM_TICKET_TTL_SECS: i64 = 30;
+
+static TOKEN_KEY: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Sets the secret tokens are hashed with, returns `false` if it was already set
+pub fn init_token_key(key: &str) -> bool {
+    TOKEN_KEY.set(key.as_bytes().to_vec()).is_ok()
+}
+
+fn token_key() -> &'static [u8] {
+    TOKEN_KEY.get_or_init(|| {
+        warn!("No session.token_key set, using a random one, sessions won't survive a restart");
+        let mut key = vec![0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        key
+    })
+}
+
+/// Random auth token, the uuid format is only kept for the clients
+pub fn generate_auth_token() -> Uuid {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
+/// Keyed hash of an auth or refresh token, the only form tokens are stored and looked up in
+pub fn hash_session_token(token: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(token_key()).expect("HMAC takes keys of any length");
+    mac.update(token.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Where a login came from, shown in the session list
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>
+}
+
+/// A logged in device, the tokens themselves are never listed
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub creation_time: DateTime<Utc>,
+    pub last_used_time: DateTime<Utc>,
+    pub expiration_time: DateTime<Utc>,
+    pub current: bool // The session of the token used for the request
+}
+
+/// The refresh token is only returned once, only its hash is stored
+pub struct SessionTokens {
+    pub session_id: Uuid,
+    pub auth_token: Uuid,
+    pub refresh_token: String
+}
+
+impl SessionTokens {
+    fn generate(session_id: Uuid) -> SessionTokens {
+        SessionTokens { session_id, auth_token: generate_auth_token(), refresh_token: generate_token() }
+    }
+}
+
+// Keeps the tokens out of logs
+impl std::fmt::Debug for SessionTokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SessionTokens { <redacted> }")
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    InvalidRefreshToken,
+    RefreshTokenReused,
+    DatabaseError(sqlx::Error)
+}
+
+pub mod database {
+    use super::*;
+
+    impl Session {
+        pub async fn create(user_id: Uuid, client: &ClientInfo, config: &SessionConfig, transaction: &mut Transaction<'static, MySql>) -> Result<SessionTokens, sqlx::Error> {
+            let tokens = SessionTokens::generate(Uuid::new_v4());
+            let now = Utc::now();
+
+            query!(
+                "INSERT INTO sessions (id, user_id, auth_token_hash, refresh_token_hash, user_agent, ip_address, creation_time, last_used_time, expiration_time, max_expiration_time)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                tokens.session_id.to_string(),
+                user_id.to_string(),
+                hash_session_token(&tokens.auth_token.to_string()),
+                hash_session_token(&tokens.refresh_token),
+                client.user_agent.as_deref().map(|agent| agent.chars().take(256).collect::<String>()),
+                client.ip_address,
+                now,
+                now,
+                now + config.ttl().min(config.max_age()),
+                now + config.max_age()
+            ).execute(transaction.as_mut()).await?;
+
+            Ok(tokens)
+        }
+
+        /// Session and user of an auth token, `None` if there's no such session
+        pub async fn find(auth_token: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Option<(Uuid, Uuid)>, sqlx::Error> {
+            let row = query!("SELECT id, user_id FROM sessions WHERE auth_token_hash = ?", hash_session_token(&auth_token.to_string()))
+                .fetch_optional(transaction.as_mut()).await?;
+
+            Ok(row.map(|row| (
+                Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid"),
+                Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid")
+            )))
+        }
+
+        /// `false` once the session was revoked or expired, open notification streams are checked with this
+        pub async fn is_active(id: Uuid, db_pool: &MySqlPool) -> Result<bool, sqlx::Error> {
+            let row = query!("SELECT id FROM sessions WHERE id = ? AND expiration_time > ?", id.to_string(), Utc::now())
+                .fetch_optional(db_pool).await?;
+            Ok(row.is_some())
+        }
+
+        /// Short lived and single use, so `EventSource` (which can't set headers) never has the auth token in its url
+        pub async fn create_stream_ticket(id: Uuid, user_id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<String, sqlx::Error> {
+            query!("DELETE FROM stream_tickets WHERE expiration_time < ?", Utc::now())
+                .execute(transaction.as_mut()).await?;
+
+            let ticket = generate_token();
+            query!(
+                "INSERT INTO stream_tickets (ticket_hash, session_id, user_id, expiration_time) VALUES (?, ?, ?, ?)",
+                hash_token(&ticket),
+                id.to_string(),
+                user_id.to_string(),
+                Utc::now() + chrono::Duration::seconds(STREAM_TICKET_TTL_SECS)
+            ).execute(transaction.as_mut()).await?;
+
+            Ok(ticket)
+        }
+
+        /// Session and user the ticket was made for, `None` if it's unknown, expired or was already used
+        pub async fn redeem_stream_ticket(ticket: &str, transaction: &mut Transaction<'static, MySql>) -> Result<Option<(Uuid, Uuid)>, sqlx::Error> {
+            let row = query!(
+                "SELECT session_id, user_id FROM stream_tickets WHERE ticket_hash = ? AND expiration_time > ? FOR UPDATE",
+                hash_token(ticket),
+                Utc::now()
+            ).fetch_optional(transaction.as_mut()).await?;
+
+            query!("DELETE FROM stream_tickets WHERE ticket_hash = ?", hash_token(ticket))
+                .execute(transaction.as_mut()).await?;
+
+            Ok(row.and_then(|row| Some((Uuid::parse_str(&row.session_id).ok()?, Uuid::parse_str(&row.user_id).ok()?))))
+        }
+
+        /// Owner of an auth token, for when there's no transaction, like in the rate limiter
+        pub async fn find_user_id(auth_token: Uuid, db_pool: &MySqlPool) -> Result<Option<Uuid>, sqlx::Error> {
+            let row = query!("SELECT user_id FROM sessions WHERE auth_token_hash = ?", hash_session_token(&auth_token.to_string()))
+                .fetch_optional(db_pool).await?;
+
+            Ok(row.map(|row| Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid")))
+        }
+
+        /// Sessions that can still be used or refreshed
+        pub async fn list(user_id: Uuid, current_session: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<Session>, sqlx::Error> {
+            let rows = query!(
+                "SELECT id, user_agent, ip_address, creation_time, last_used_time, expiration_time FROM sessions
+                WHERE user_id = ? AND max_expiration_time > ? ORDER BY last_used_time DESC",
+                user_id.to_string(),
+                Utc::now()
+            ).fetch_all(transaction.as_mut()).await?;
+
+            Ok(rows.into_iter().map(|row| Session {
+                id: Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid"),
+                user_agent: row.user_agent,
+                ip_address: row.ip_address,
+                creation_time: row.creation_time.and_utc(),
+                last_used_time: row.last_used_time.and_utc(),
+                expiration_time: row.expiration_time.and_utc(),
+                current: row.id == current_session.to_string()
+            }).collect())
+        }
+
+        /// Returns `false` if the user has no such session
+        pub async fn revoke(user_id: Uuid, id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            let result = query!("DELETE FROM sessions WHERE id = ? AND user_id = ?", id.to_string(), user_id.to_string())
+                .execute(transaction.as_mut()).await?;
+            Ok(result.rows_affected() > 0)
+        }
+
+        /// Logs the user out everywhere except `current_session`
+        pub async fn revoke_others(user_id: Uuid, current_session: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<u64, sqlx::Error> {
+            let result = query!("DELETE FROM sessions WHERE user_id = ? AND id <> ?", user_id.to_string(), current_session.to_string())
+                .execute(transaction.as_mut()).await?;
+            Ok(result.rows_affected())
+        }
+
+        /// Swaps both tokens for new ones. A refresh token that was already swapped means it leaked, so the session is revoked
+        pub async fn refresh(refresh_token: &str, config: &SessionConfig, transaction: &mut Transaction<'static, MySql>) -> Result<(Uuid, SessionTokens), SessionError> {
+            let token_hash = hash_session_token(refresh_token);
+            let now = Utc::now();
+
+            let row = query!(
+                "SELECT id, user_id, refresh_token_hash, max_expiration_time FROM sessions
+                WHERE refresh_token_hash = ? OR previous_refresh_token_hash = ? FOR UPDATE",
+                token_hash,
+                token_hash
+            ).fetch_optional(transaction.as_mut()).await.map_err(SessionError::DatabaseError)?;
+
+            let row = match row {
+                Some(row) if row.max_expiration_time.and_utc() > now => row,
+                _ => return Err(SessionError::InvalidRefreshToken)
+            };
+
+            if row.refresh_token_hash != token_hash {
+                query!("DELETE FROM sessions WHERE id = ?", row.id)
+                    .execute(transaction.as_mut()).await.map_err(SessionError::DatabaseError)?;
+                warn!("Refresh token of session {} reused, session revoked", row.id);
+                return Err(SessionError::RefreshTokenReused);
+            }
+
+            let tokens = SessionTokens::generate(Uuid::parse_str(&row.id).expect("Couldn't parse string to Uuid"));
+            query!(
+                "UPDATE sessions SET auth_token_hash = ?, refresh_token_hash = ?, previous_refresh_token_hash = ?, last_used_time = ?, expiration_time = LEAST(?, max_expiration_time)
+                WHERE id = ?",
+                hash_session_token(&tokens.auth_token.to_string()),
+                hash_session_token(&tokens.refresh_token),
+                token_hash,
+                now,
+                now + config.ttl(),
+                row.id
+            ).execute(transaction.as_mut()).await.map_err(SessionError::DatabaseError)?;
+
+            info!("Session {} refreshed", row.id);
+            Ok((Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid"), tokens))
+        }
+
+        /// Moves the expiration forward by the time since the last use, up to the session's maximum age.
+        /// Runs in the request's transaction, so it's only kept if that commits
+        pub async fn touch(auth_token: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            let now = Utc::now();
+            query!(
+                "UPDATE sessions
+                SET expiration_time = LEAST(expiration_time + INTERVAL TIMESTAMPDIFF(SECOND, last_used_time, ?) SECOND, max_expiration_time),
+                last_used_time = ?
+                WHERE auth_token_hash = ? AND expiration_time > ? AND last_used_time < ?",
+                now,
+                now,
+                hash_session_token(&auth_token.to_string()),
+                now,
+                now - chrono::Duration::seconds(TOUCH_INTERVAL_SECS)
+            ).execute(transaction.as_mut()).await?;
+            Ok(())
+        }
+
+        /// Removes sessions that can't be used or refreshed anymore
+        pub async fn purge_expired(transaction: &mut Transaction<'static, MySql>) -> Result<u64, sqlx::Error> {
+            let result = query!("DELETE FROM sessions WHERE max_expiration_time <= ?", Utc::now())
+                .execute(transaction.as_mut()).await?;
+
+            if result.rows_affected() > 0 {
+                info!("Purged {} expired sessions", result.rows_affected());
+            }
+            Ok(result.rows_affected())
+        }
+
+        /// Hashes the tokens of sessions from before they were hashed, then drops the plaintext column.
+        /// Does nothing on an up to date database, the queries aren't checked at compile time since the column is gone there
+        pub async fn migrate_plaintext_tokens(db_pool: &MySqlPool) -> Result<u64, sqlx::Error> {
+            let column_exists = |column: &'static str| async move {
+                sqlx::query("SELECT COUNT(*) FROM information_schema.columns WHERE table_schema = DATABASE() AND table_name = 'sessions' AND column_name = ?")
+                    .bind(column)
+                    .fetch_one(db_pool).await
+                    .map(|row| row.get::<i64, _>(0) > 0)
+            };
+
+            if !column_exists("auth_token").await? {
+                return Ok(0);
+            }
+
+            info!("Hashing plaintext session tokens");
+            if !column_exists("auth_token_hash").await? {
+                sqlx::query("ALTER TABLE sessions ADD COLUMN auth_token_hash char(64) NULL AFTER auth_token")
+                    .execute(db_pool).await?;
+            }
+
+            let mut tx = db_pool.begin().await?;
+            let tokens = sqlx::query("SELECT auth_token FROM sessions WHERE auth_token_hash IS NULL FOR UPDATE")
+                .fetch_all(tx.as_mut()).await?;
+            for row in &tokens {
+                let token: String = row.get(0);
+                sqlx::query("UPDATE sessions SET auth_token_hash = ? WHERE auth_token = ?")
+                    .bind(hash_session_token(&token))
+                    .bind(&token)
+                    .execute(tx.as_mut()).await?;
+            }
+            tx.commit().await?;
+
+            // DDL commits on its own, it only runs once every row has its hash
+            sqlx::query("ALTER TABLE sessions DROP COLUMN auth_token, MODIFY auth_token_hash char(64) NOT NULL, ADD UNIQUE KEY auth_token_hash (auth_token_hash)")
+                .execute(db_pool).await?;
+
+            info!("Hashed the tokens of {} sessions", tokens.len());
+            Ok(tokens.len() as u64)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+        use crate::models::user::User;
+
+        async fn test_user(tx: &mut Transaction<'static, MySql>) -> User {
+            let user = User::new("session_test".to_string(), "aaaaa".to_string(), Some("session@test.com".to_string()), None, tx).await.unwrap();
+            user.create(tx).await.unwrap();
+            user
+        }
+
+        #[tokio::test]
+        async fn test_list_and_revoke() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let user = test_user(&mut tx).await;
+            let client = ClientInfo { user_agent: Some("test agent".to_string()), ip_address: Some("127.0.0.1".to_string()) };
+
+            let first = Session::create(user.id, &client, &SessionConfig::default(), &mut tx).await.unwrap();
+            let second = Session::create(user.id, &ClientInfo::default(), &SessionConfig::default(), &mut tx).await.unwrap();
+            Session::create(user.id, &ClientInfo::default(), &SessionConfig::default(), &mut tx).await.unwrap();
+
+            assert_eq!(Session::find(first.auth_token, &mut tx).await.unwrap(), Some((first.session_id, user.id)));
+            let sessions = Session::list(user.id, first.session_id, &mut tx).await.unwrap();
+            assert_eq!(sessions.len(), 3);
+            assert_eq!(sessions.iter().filter(|session| session.current).count(), 1);
+            assert!(sessions.iter().any(|session| session.current && session.user_agent.as_deref() == Some("test agent")));
+
+            let other = sessions.iter().find(|session| !session.current).unwrap();
+            assert!(Session::revoke(user.id, other.id, &mut tx).await.unwrap());
+            assert!(!Session::revoke(Uuid::new_v4(), other.id, &mut tx).await.unwrap());
+
+            assert_eq!(Session::revoke_others(user.id, second.session_id, &mut tx).await.unwrap(), 1);
+            let sessions = Session::list(user.id, second.session_id, &mut tx).await.unwrap();
+            assert_eq!(sessions.len(), 1);
+            assert!(sessions[0].current);
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_refresh_rotation() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let user = test_user(&mut tx).await;
+            let config = SessionConfig::default();
+
+            let tokens = Session::create(user.id, &ClientInfo::default(), &config, &mut tx).await.unwrap();
+            let (user_id, refreshed) = Session::refresh(&tokens.refresh_token, &config, &mut tx).await.unwrap();
+            assert_eq!(user_id, user.id);
+            assert_ne!(refreshed.auth_token, tokens.auth_token);
+
+            // The old access token is gone with the rotation
+            assert_eq!(User::get_id_by_token(tokens.auth_token, &mut tx).await.unwrap(), None);
+            assert_eq!(User::get_id_by_token(refreshed.auth_token, &mut tx).await.unwrap(), Some(user.id));
+
+            assert!(matches!(Session::refresh("not a token", &config, &mut tx).await, Err(SessionError::InvalidRefreshToken)));
+
+            // Reusing the swapped token revokes the whole session
+            assert!(matches!(Session::refresh(&tokens.refresh_token, &config, &mut tx).await, Err(SessionError::RefreshTokenReused)));
+            assert!(matches!(Session::refresh(&refreshed.refresh_token, &config, &mut tx).await, Err(SessionError::InvalidRefreshToken)));
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_purge_expired() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let user = test_user(&mut tx).await;
+
+            let expired = SessionConfig { ttl_hours: 1, max_age_hours: 1, ..Default::default() };
+            let tokens = Session::create(user.id, &ClientInfo::default(), &expired, &mut tx).await.unwrap();
+            query!("UPDATE sessions SET max_expiration_time = ? WHERE auth_token_hash = ?", Utc::now() - chrono::Duration::minutes(1), hash_session_token(&tokens.auth_token.to_string()))
+                .execute(tx.as_mut()).await.unwrap();
+            Session::create(user.id, &ClientInfo::default(), &SessionConfig::default(), &mut tx).await.unwrap();
+
+            assert!(Session::purge_expired(&mut tx).await.unwrap() >= 1);
+            assert_eq!(User::get_id_by_token(tokens.auth_token, &mut tx).await.unwrap(), None);
+            assert_eq!(Session::list(user.id, tokens.session_id, &mut tx).await.unwrap().len(), 1);
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_stream_ticket() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let user = test_user(&mut tx).await;
+
+            let tokens = Session::create(user.id, &ClientInfo::default(), &SessionConfig::default(), &mut tx).await.unwrap();
+            let ticket = Session::create_stream_ticket(tokens.session_id, user.id, &mut tx).await.unwrap();
+
+            assert_eq!(Session::redeem_stream_ticket(&ticket, &mut tx).await.unwrap(), Some((tokens.session_id, user.id)));
+            assert_eq!(Session::redeem_stream_ticket(&ticket, &mut tx).await.unwrap(), None);
+            assert_eq!(Session::redeem_stream_ticket(&tokens.auth_token.to_string(), &mut tx).await.unwrap(), None);
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_hash() {
+        init_token_key("0123456789abcdef0123456789abcdef");
+
+        let token = generate_auth_token();
+        assert_eq!(token.get_version(), Some(uuid::Version::Random));
+        assert_ne!(generate_auth_token(), token);
+
+        let hash = hash_session_token(&token.to_string());
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_session_token(&token.to_string()));
+        assert!(!hash.contains(&token.simple().to_string()));
+        // Keyed, so it's not the plain sha256 of the token
+        assert_ne!(hash, super::super::password_reset::hash_token(&token.to_string()));
+    }
+}
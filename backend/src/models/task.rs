@@ -3,6 +3,7 @@ use sqlx::MySqlPool;
 use uuid::Uuid;
 use sqlx::query;
 use std::collections::HashSet;
+use crate::events::DomainEvent;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct OpenQuestionTask {
@@ -137,6 +138,8 @@ pub mod database {
                 .await?;
             }
             
+            DomainEvent::TaskCreated { task_id: self.id }.record(transaction).await?;
+            
             Ok(())
         }
         
@@ -253,6 +256,10 @@ pub mod database {
                 &id.to_string())
             .execute(transaction.as_mut()).await?;
             
+            query!("DELETE FROM solved_tasks WHERE task_id = ?",
+                &id.to_string())
+            .execute(transaction.as_mut()).await?;
+            
             // Remove the task from the tasks table
             query!("DELETE FROM tasks WHERE id = ?",
                 &id.to_string())
@@ -269,8 +276,7 @@ pub mod database {
         
         #[tokio::test]
         async fn test_create() {
-            let binding = database::get_database_connection_pool(None).await.unwrap();
-            let pool = binding.lock().await;
+            let pool = database::get_database_connection_pool(None).await.unwrap();
             let content = OpenQuestionTask { content: "Code an AGI. You have 2 minutes and cannot use google".to_string() };
             let tags = HashSet::from([Tag::new("AI".to_string(), &pool).await, Tag::new("AGI".to_string(), &pool).await]);
             let task = Task::new("Test task".to_string(), TaskContent::OpenQuestion(content), tags);
@@ -283,8 +289,7 @@ pub mod database {
         
         #[tokio::test]
         async fn test_read() {
-            let binding = database::get_database_connection_pool(None).await.unwrap();
-            let pool = binding.lock().await;
+            let pool = database::get_database_connection_pool(None).await.unwrap();
             let content = OpenQuestionTask { content: "Code an AGI. You have 2 minutes and cannot use google".to_string() };
             let tags = HashSet::from([Tag::new("AI".to_string(), &pool).await, Tag::new("AGI".to_string(), &pool).await]);
             let task = Task::new("Test task".to_string(), TaskContent::OpenQuestion(content), tags);
@@ -315,8 +320,7 @@ pub mod database {
     
         #[tokio::test]
         async fn test_update() {
-            let binding = database::get_database_connection_pool(None).await.unwrap();
-            let pool = binding.lock().await;
+            let pool = database::get_database_connection_pool(None).await.unwrap();
             let content = OpenQuestionTask { content: "Code an AGI. You have 2 minutes and cannot use google".to_string() };
             let tags = HashSet::from([Tag::new("AI".to_string(), &pool).await, Tag::new("AGI".to_string(), &pool).await]);
             let mut task = Task::new("Test task".to_string(), TaskContent::OpenQuestion(content), tags);
@@ -338,8 +342,7 @@ pub mod database {
         
         #[tokio::test]
         async fn test_delete() {
-            let binding = database::get_database_connection_pool(None).await.unwrap();
-            let pool = binding.lock().await;
+            let pool = database::get_database_connection_pool(None).await.unwrap();
             let content = OpenQuestionTask { content: "Code an AGI. You have 2 minutes and cannot use google".to_string() };
             let tags = HashSet::from([Tag::new("AI".to_string(), &pool).await, Tag::new("AGI".to_string(), &pool).await]);
             let task = Task::new("Test task".to_string(), TaskContent::OpenQuestion(content), tags);
@@ -396,8 +399,7 @@ mod tests {
                 Tag{id: Uuid::parse_str("87d016c1-2af7-4a7b-a987-28395a9bf4fd").unwrap(), name: "syntax".to_string()}
             ]));
         
-        let binding = database::get_database_connection_pool(None).await.unwrap();
-        let pool = binding.lock().await;
+        let pool = database::get_database_connection_pool(None).await.unwrap();
         let mut tx = pool.begin().await.unwrap();
         
         assert!(task.create(&mut tx).await.is_ok());
@@ -0,0 +1,404 @@
+use rand::Rng;
+use serde::Serialize;
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::{info, warn};
+use super::password_reset::{generate_token, hash_token};
+use super::session::{ClientInfo, SessionTokens};
+use super::user::User;
+use super::role::Role;
+use crate::config::{SessionConfig, TwoFactorConfig};
+use crate::totp;
+
+/// Shown once, the secret is also in the uri for apps that scan a QR code
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Enrollment {
+    pub secret: String,
+    pub otpauth_uri: String
+}
+
+/// Sent instead of a session when the password was right but a code is still needed
+#[derive(Debug, Serialize)]
+pub struct Challenge {
+    pub challenge: String
+}
+
+pub enum LoginStep {
+    Done(User, SessionTokens),
+    TwoFactor(Challenge)
+}
+
+#[derive(Debug)]
+pub enum TwoFactorError {
+    AlreadyEnabled,
+    NotEnrolled,
+    NotEnabled,
+    /// 2FA can't be turned off for the user's roles
+    Required,
+    InvalidChallenge,
+    InvalidCode,
+    TooManyAttempts,
+    DatabaseError(sqlx::Error)
+}
+
+impl From<sqlx::Error> for TwoFactorError {
+    fn from(e: sqlx::Error) -> Self {
+        TwoFactorError::DatabaseError(e)
+    }
+}
+
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Like `abcde-fghjk`, without the characters that are easy to mix up
+pub fn generate_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut code: String = (0..10)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+// Typed however the user likes, salted with the user id like verification codes
+fn hash_recovery_code(user_id: Uuid, code: &str) -> String {
+    let code: String = code.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    hash_token(&format!("{}:{}", user_id, code.to_lowercase()))
+}
+
+pub mod database {
+    use super::*;
+
+    struct Secret {
+        secret: Vec<u8>,
+        enabled: bool,
+        last_used_step: Option<i64>
+    }
+
+    impl User {
+        async fn read_two_factor(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Option<Secret>, sqlx::Error> {
+            let row = query!("SELECT secret, enabled_time, last_used_step FROM two_factor WHERE user_id = ? FOR UPDATE", id.to_string())
+                .fetch_optional(transaction.as_mut()).await?;
+
+            let Some(row) = row else {
+                return Ok(None);
+            };
+            let secret = totp::decode_secret(&row.secret)
+                .ok_or_else(|| sqlx::Error::Decode(format!("Invalid 2FA secret of user {}", id).into()))?;
+
+            Ok(Some(Secret {
+                secret,
+                enabled: row.enabled_time.is_some(),
+                last_used_step: row.last_used_step
+            }))
+        }
+
+        pub async fn has_two_factor(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            Ok(User::read_two_factor(id, transaction).await?.is_some_and(|secret| secret.enabled))
+        }
+
+        pub async fn two_factor_required(id: Uuid, config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            let roles = User::read_roles(id, transaction).await?;
+            Ok(roles.iter().any(|role| config.required_roles.contains(role)))
+        }
+
+        /// The roles that count for authorization, the ones that require 2FA only once it's on
+        pub async fn read_active_roles(id: Uuid, config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<Role>, sqlx::Error> {
+            let roles = User::read_roles(id, transaction).await?;
+            if User::has_two_factor(id, transaction).await? {
+                return Ok(roles);
+            }
+            Ok(roles.into_iter().filter(|role| !config.required_roles.contains(role)).collect())
+        }
+
+        /// A new secret, 2FA stays off until a code from it is confirmed
+        pub async fn enroll_two_factor(id: Uuid, config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<Enrollment, TwoFactorError> {
+            if User::has_two_factor(id, transaction).await? {
+                return Err(TwoFactorError::AlreadyEnabled);
+            }
+
+            let user = User::read(id, transaction).await?;
+            let secret = totp::encode_secret(&totp::generate_secret());
+
+            query!(
+                "REPLACE INTO two_factor (user_id, secret, enabled_time, last_used_step, creation_time) VALUES (?, ?, NULL, NULL, ?)",
+                id.to_string(),
+                secret,
+                Utc::now()
+            ).execute(transaction.as_mut()).await?;
+
+            info!("User {} started 2FA enrollment", id);
+            Ok(Enrollment {
+                otpauth_uri: totp::otpauth_uri(&config.issuer, &user.username, &secret),
+                secret
+            })
+        }
+
+        /// Turns 2FA on with a code from the enrolled secret, returns the recovery codes in plain text
+        pub async fn enable_two_factor(id: Uuid, code: &str, config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<String>, TwoFactorError> {
+            match User::read_two_factor(id, transaction).await? {
+                None => return Err(TwoFactorError::NotEnrolled),
+                Some(secret) if secret.enabled => return Err(TwoFactorError::AlreadyEnabled),
+                Some(secret) => {
+                    if !User::check_totp(id, &secret, code, transaction).await? {
+                        return Err(TwoFactorError::InvalidCode);
+                    }
+                }
+            }
+
+            query!("UPDATE two_factor SET enabled_time = ? WHERE user_id = ?", Utc::now(), id.to_string())
+                .execute(transaction.as_mut()).await?;
+
+            info!("User {} enabled 2FA", id);
+            Ok(User::replace_recovery_codes(id, config, transaction).await?)
+        }
+
+        /// Needs a current code, a stolen session alone can't turn it off
+        pub async fn disable_two_factor(id: Uuid, code: &str, config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<(), TwoFactorError> {
+            if User::two_factor_required(id, config, transaction).await? {
+                return Err(TwoFactorError::Required);
+            }
+
+            match User::read_two_factor(id, transaction).await? {
+                Some(secret) if secret.enabled => {
+                    if !User::check_second_factor(id, &secret, code, transaction).await? {
+                        return Err(TwoFactorError::InvalidCode);
+                    }
+                },
+                _ => return Err(TwoFactorError::NotEnabled)
+            }
+
+            query!("DELETE FROM two_factor WHERE user_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+            query!("DELETE FROM recovery_codes WHERE user_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+
+            info!("User {} disabled 2FA", id);
+            Ok(())
+        }
+
+        /// The old codes stop working, only an authenticator code is accepted for this
+        pub async fn regenerate_recovery_codes(id: Uuid, code: &str, config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<String>, TwoFactorError> {
+            match User::read_two_factor(id, transaction).await? {
+                Some(secret) if secret.enabled => {
+                    if !User::check_totp(id, &secret, code, transaction).await? {
+                        return Err(TwoFactorError::InvalidCode);
+                    }
+                },
+                _ => return Err(TwoFactorError::NotEnabled)
+            }
+
+            Ok(User::replace_recovery_codes(id, config, transaction).await?)
+        }
+
+        async fn replace_recovery_codes(id: Uuid, config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<Vec<String>, sqlx::Error> {
+            query!("DELETE FROM recovery_codes WHERE user_id = ?", id.to_string())
+                .execute(transaction.as_mut()).await?;
+
+            let codes: Vec<String> = (0..config.recovery_codes).map(|_| generate_recovery_code()).collect();
+            for code in &codes {
+                query!("INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)", id.to_string(), hash_recovery_code(id, code))
+                    .execute(transaction.as_mut()).await?;
+            }
+
+            Ok(codes)
+        }
+
+        // A code can't be used twice, not even within its 30 seconds
+        async fn check_totp(id: Uuid, secret: &Secret, code: &str, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            match totp::verify(&secret.secret, code, Utc::now()) {
+                Some(step) if secret.last_used_step.map_or(true, |last| step > last) => {
+                    query!("UPDATE two_factor SET last_used_step = ? WHERE user_id = ?", step, id.to_string())
+                        .execute(transaction.as_mut()).await?;
+                    Ok(true)
+                },
+                _ => Ok(false)
+            }
+        }
+
+        // An authenticator code or one of the recovery codes, which is used up
+        async fn check_second_factor(id: Uuid, secret: &Secret, code: &str, transaction: &mut Transaction<'static, MySql>) -> Result<bool, sqlx::Error> {
+            if User::check_totp(id, secret, code, transaction).await? {
+                return Ok(true);
+            }
+
+            let used = query!("DELETE FROM recovery_codes WHERE user_id = ? AND code_hash = ?", id.to_string(), hash_recovery_code(id, code))
+                .execute(transaction.as_mut()).await?;
+
+            if used.rows_affected() > 0 {
+                info!("User {} used a recovery code", id);
+            }
+            Ok(used.rows_affected() > 0)
+        }
+
+        /// `None` if the password is enough for the user. Users who should have 2FA but don't get in without it,
+        /// a secret handed out here would go to whoever knows the password, see `read_active_roles`
+        pub async fn two_factor_challenge(id: Uuid, config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<Option<Challenge>, sqlx::Error> {
+            if !User::has_two_factor(id, transaction).await? {
+                return Ok(None);
+            }
+
+            let challenge = generate_token();
+            query!(
+                "INSERT INTO login_challenges (token_hash, user_id, attempts, expiration_time) VALUES (?, ?, 0, ?)",
+                hash_token(&challenge),
+                id.to_string(),
+                Utc::now() + config.challenge_ttl()
+            ).execute(transaction.as_mut()).await?;
+
+            Ok(Some(Challenge { challenge }))
+        }
+
+        /// Whose login a challenge is for, failed codes are counted against that account
+        pub async fn read_challenge_user(challenge: &str, transaction: &mut Transaction<'static, MySql>) -> Result<Option<Uuid>, sqlx::Error> {
+            let row = query!(
+                "SELECT user_id FROM login_challenges WHERE token_hash = ? AND expiration_time > ?",
+                hash_token(challenge),
+                Utc::now()
+            ).fetch_optional(transaction.as_mut()).await?;
+
+            Ok(row.and_then(|row| Uuid::parse_str(&row.user_id).ok()))
+        }
+
+        /// Second step of the login. Wrong codes count as attempts, so the transaction has to be committed on `InvalidCode` too
+        pub async fn complete_login(challenge: &str, code: &str, client: &ClientInfo, session_config: &SessionConfig, config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<(User, SessionTokens), TwoFactorError> {
+            let challenge_hash = hash_token(challenge);
+            let row = query!(
+                "SELECT user_id, attempts FROM login_challenges WHERE token_hash = ? AND expiration_time > ? FOR UPDATE",
+                challenge_hash,
+                Utc::now()
+            ).fetch_optional(transaction.as_mut()).await?;
+
+            let row = row.ok_or(TwoFactorError::InvalidChallenge)?;
+            let id = Uuid::parse_str(&row.user_id).expect("Couldn't parse string to Uuid");
+
+            if row.attempts as u32 >= config.max_attempts {
+                return Err(TwoFactorError::TooManyAttempts);
+            }
+
+            let secret = match User::read_two_factor(id, transaction).await? {
+                Some(secret) if secret.enabled => secret,
+                _ => return Err(TwoFactorError::NotEnabled)
+            };
+
+            if !User::check_second_factor(id, &secret, code, transaction).await? {
+                query!("UPDATE login_challenges SET attempts = attempts + 1 WHERE token_hash = ?", challenge_hash)
+                    .execute(transaction.as_mut()).await?;
+                warn!("Wrong 2FA code for user {} (attempt {}/{})", id, row.attempts + 1, config.max_attempts);
+                return Err(TwoFactorError::InvalidCode);
+            }
+
+            query!("DELETE FROM login_challenges WHERE token_hash = ? OR expiration_time < ?", challenge_hash, Utc::now())
+                .execute(transaction.as_mut()).await?;
+
+            User::start_session(id, client, session_config, transaction).await.map_err(TwoFactorError::DatabaseError)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+
+        fn current_code(enrollment: &Enrollment, steps_ago: i64) -> String {
+            let secret = totp::decode_secret(&enrollment.secret).unwrap();
+            totp::code(&secret, totp::step(Utc::now()) - steps_ago)
+        }
+
+        #[tokio::test]
+        async fn test_enrollment_and_login() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let config = TwoFactorConfig::default();
+            let client = ClientInfo::default();
+
+            let user = User::new("two_factor_test".to_string(), "aaaaa".to_string(), Some("2fa@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+            assert!(User::two_factor_challenge(user.id, &config, &mut tx).await.unwrap().is_none());
+
+            let enrollment = User::enroll_two_factor(user.id, &config, &mut tx).await.unwrap();
+            assert!(enrollment.otpauth_uri.contains(&enrollment.secret));
+            assert!(matches!(User::enable_two_factor(user.id, "000000x", &config, &mut tx).await, Err(TwoFactorError::InvalidCode)));
+
+            let recovery_codes = User::enable_two_factor(user.id, &current_code(&enrollment, 1), &config, &mut tx).await.unwrap();
+            assert_eq!(recovery_codes.len(), config.recovery_codes as usize);
+
+            // The same code doesn't work twice
+            let challenge = User::two_factor_challenge(user.id, &config, &mut tx).await.unwrap().unwrap();
+            assert!(matches!(
+                User::complete_login(&challenge.challenge, &current_code(&enrollment, 1), &client, &SessionConfig::default(), &config, &mut tx).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+            let (logged_in, _) = User::complete_login(&challenge.challenge, &current_code(&enrollment, 0), &client, &SessionConfig::default(), &config, &mut tx).await.unwrap();
+            assert_eq!(logged_in.id, user.id);
+            assert!(matches!(
+                User::complete_login(&challenge.challenge, &current_code(&enrollment, 0), &client, &SessionConfig::default(), &config, &mut tx).await,
+                Err(TwoFactorError::InvalidChallenge)
+            ));
+
+            // Recovery codes are single use and don't care about formatting
+            let challenge = User::two_factor_challenge(user.id, &config, &mut tx).await.unwrap().unwrap();
+            User::complete_login(&challenge.challenge, &recovery_codes[0].to_uppercase().replace('-', " "), &client, &SessionConfig::default(), &config, &mut tx).await.unwrap();
+            let challenge = User::two_factor_challenge(user.id, &config, &mut tx).await.unwrap().unwrap();
+            assert!(matches!(
+                User::complete_login(&challenge.challenge, &recovery_codes[0], &client, &SessionConfig::default(), &config, &mut tx).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+
+            User::disable_two_factor(user.id, &recovery_codes[1], &config, &mut tx).await.unwrap();
+            assert!(!User::has_two_factor(user.id, &mut tx).await.unwrap());
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_required_for_roles() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let config = TwoFactorConfig { max_attempts: 1, ..Default::default() };
+            let client = ClientInfo::default();
+
+            let user = User::new("two_factor_test".to_string(), "aaaaa".to_string(), Some("2fa@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+            User::grant_role(user.id, Role::Author, &mut tx).await.unwrap();
+
+            // The password alone gets in, but the role only counts once 2FA is set up with that session
+            assert!(User::two_factor_challenge(user.id, &config, &mut tx).await.unwrap().is_none());
+            assert!(User::read_active_roles(user.id, &config, &mut tx).await.unwrap().is_empty());
+
+            let enrollment = User::enroll_two_factor(user.id, &config, &mut tx).await.unwrap();
+            User::enable_two_factor(user.id, &current_code(&enrollment, 0), &config, &mut tx).await.unwrap();
+            assert_eq!(User::read_active_roles(user.id, &config, &mut tx).await.unwrap(), vec![Role::Author]);
+
+            assert!(matches!(User::disable_two_factor(user.id, &current_code(&enrollment, 0), &config, &mut tx).await, Err(TwoFactorError::Required)));
+
+            let challenge = User::two_factor_challenge(user.id, &config, &mut tx).await.unwrap().unwrap();
+            assert!(matches!(
+                User::complete_login(&challenge.challenge, "wrong", &client, &SessionConfig::default(), &config, &mut tx).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+            assert!(matches!(
+                User::complete_login(&challenge.challenge, &current_code(&enrollment, -1), &client, &SessionConfig::default(), &config, &mut tx).await,
+                Err(TwoFactorError::TooManyAttempts)
+            ));
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_codes() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(code.chars().nth(5), Some('-'));
+
+        let user_id = Uuid::new_v4();
+        assert_eq!(hash_recovery_code(user_id, &code), hash_recovery_code(user_id, &code.to_uppercase().replace('-', "")));
+        assert_ne!(hash_recovery_code(user_id, &code), hash_recovery_code(Uuid::new_v4(), &code));
+    }
+}
@@ -3,8 +3,13 @@ use sqlx::{query, MySql, Transaction};
 use uuid::Uuid;
 use regex::Regex;
 use chrono::prelude::*;
-use tracing::{error, info, warn};
+use tracing::{info, warn};
 use super::serde_uuid_vec;
+use super::session::{hash_session_token, ClientInfo, Session, SessionTokens};
+use super::two_factor::LoginStep;
+use crate::config::{SessionConfig, TwoFactorConfig};
+use crate::events::DomainEvent;
+use crate::password::{hasher, HashError};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct UserProgress {
@@ -29,8 +34,8 @@ impl UserProgress {
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct UserLevel {
-    level: u32,
-    xp: u32
+    pub level: u32,
+    pub xp: u32
 }
 
 impl UserLevel {
@@ -49,8 +54,13 @@ pub struct User {
     pub username: String,
     pub email: Option<String>,
     pub phone: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub phone_verified: bool,
     pub bio: Option<String>,
-    // pub avatar: ?
+    /// Version of the current avatar, the images themselves are in the avatar storage
+    pub avatar: Option<String>,
     #[serde(with="serde_uuid_vec")]
     pub friends: Vec<Uuid>,
     pub level: UserLevel,
@@ -67,7 +77,7 @@ pub enum UserError {
     BadPhone,
     MissingFields,
     DatabaseError(sqlx::Error),
-    HashError(bcrypt::BcryptError)
+    HashError(HashError)
 }
 
 #[derive(Debug, PartialEq)]
@@ -96,28 +106,24 @@ impl User {
             return Err(UserError::MissingFields);
         }
 
-        if let Some(email) = &email {
-            let re = Regex::new(r#"(?:[a-z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-z0-9!#$%&'*+/=?^_`{|}~-]+)*|"(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21\x23-\x5b\x5d-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])*")@(?:(?:[a-z0-9](?:[a-z0-9-]*[a-z0-9])?\.)+[a-z0-9](?:[a-z0-9-]*[a-z0-9])?|\[(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?|[a-z0-9-]*[a-z0-9]:(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21-\x5a\x53-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])+)\])"#).expect("Couldn't create regex");
-            if !re.is_match(email.as_str()) && email.len() > 128 {
-                return Err(UserError::BadEmail);
-            }
+        if email.as_deref().is_some_and(|email| !valid_email(email)) {
+            return Err(UserError::BadEmail);
         }
 
-        if let Some(phone) = &phone {
-            let re = Regex::new(r#"^(?:\+48)?[0-9]{9}$"#).expect("Couldn't create regex");
-            if !re.is_match(phone.as_str()) && phone.len() > 32 {
-                return Err(UserError::BadPhone);
-            }
+        if phone.as_deref().is_some_and(|phone| !valid_phone(phone)) {
+            return Err(UserError::BadPhone);
         }
 
         Ok(User {
             id : Uuid::new_v4(),
-            password_hash: bcrypt::hash(password, 10).unwrap_or_else(|e| {error!("Couldn't hash password: {}", e); String::from("")}),
+            password_hash: hasher().hash(&password).await.map_err(UserError::HashError)?,
             username: username.to_string(),
             email,
             phone,
+            email_verified: false,
+            phone_verified: false,
             bio: None,
-            // avatar: ?
+            avatar: None,
             friends: Vec::new(),
             level: UserLevel::new(),
             progress: UserProgress::new(),
@@ -127,6 +133,37 @@ impl User {
 
 }
 
+pub fn valid_email(email: &str) -> bool {
+    let re = Regex::new(r#"(?i)^(?:[a-z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-z0-9!#$%&'*+/=?^_`{|}~-]+)*|"(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21\x23-\x5b\x5d-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])*")@(?:(?:[a-z0-9](?:[a-z0-9-]*[a-z0-9])?\.)+[a-z0-9](?:[a-z0-9-]*[a-z0-9])?|\[(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?|[a-z0-9-]*[a-z0-9]:(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21-\x5a\x53-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])+)\])$"#).expect("Couldn't create regex");
+    re.is_match(email) && email.len() <= 128
+}
+
+pub fn valid_phone(phone: &str) -> bool {
+    let re = Regex::new(r#"^(?:\+48)?[0-9]{9}$"#).expect("Couldn't create regex");
+    re.is_match(phone) && phone.len() <= 32
+}
+
+/// Number of consecutive days of activity ending today or yesterday, `days` have to be sorted descending
+pub fn streak_length(days: &[NaiveDate], today: NaiveDate) -> u32 {
+    let mut expected = match days.first() {
+        Some(day) if *day == today || Some(*day) == today.pred_opt() => *day,
+        _ => return 0
+    };
+
+    let mut streak = 0;
+    for day in days {
+        if *day != expected {
+            break;
+        }
+        streak += 1;
+        expected = match expected.pred_opt() {
+            Some(day) => day,
+            None => break
+        };
+    }
+    streak
+}
+
 pub mod json {
     use super::*;
     impl User {
@@ -165,10 +202,7 @@ pub mod database {
                 self.progress.level,
                 self.progress.task).execute(transaction.as_mut()).await?;
 
-            for friend_id in &self.friends {
-                query!("INSERT INTO friends (user_id_1, user_id_2) VALUES (?, ?)", self.id.to_string(), friend_id.to_string())
-                    .execute(transaction.as_mut()).await?;
-            }
+            DomainEvent::UserRegistered { user_id: self.id }.record(transaction).await?;
 
             Ok(())
         }
@@ -202,7 +236,10 @@ pub mod database {
                     .to_string(),
                 email: user_row.email,
                 phone: user_row.phone,
+                email_verified: user_row.email_verified_time.is_some(),
+                phone_verified: user_row.phone_verified_time.is_some(),
                 bio: user_row.bio,
+                avatar: user_row.avatar,
                 friends,
                 level: UserLevel {level: user_row.level as u32, xp: user_row.xp as u32},
                 progress: user_progress,
@@ -212,8 +249,11 @@ pub mod database {
 
         pub async fn update(&self, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
 
+            // Changing the email or phone drops its verification, MySQL assigns left to right so it's compared before the update
             query!("UPDATE users
-                SET password_hash = ?,
+                SET email_verified_time = IF(email <=> ?, email_verified_time, NULL),
+                phone_verified_time = IF(phone <=> ?, phone_verified_time, NULL),
+                password_hash = ?,
                 username = ?,
                 email = ?,
                 phone = ?,
@@ -221,6 +261,8 @@ pub mod database {
                 level = ?,
                 xp = ?
                 WHERE id = ?",
+                self.email,
+                self.phone,
                 self.password_hash,
                 self.username,
                 self.email,
@@ -245,34 +287,15 @@ pub mod database {
                 self.progress.task,
                 self.id.to_string()).execute(transaction.as_mut()).await?;
 
-            // Get user's friends
-            let database_friend_ids = query!("SELECT user_id_2 FROM friends WHERE user_id_1 = ?", self.id.to_string()).fetch_all(transaction.as_mut()).await?
-                .iter()
-                .map(|record| Uuid::parse_str(record.user_id_2.to_string().as_str()).expect("Couldn't parse string to Uuid"))
-                .collect::<Vec<Uuid>>();
-
-            for friend_id in &self.friends {
-                if !database_friend_ids.contains(friend_id) { // STRUCT YES, DB NO
-                    query!("INSERT INTO friends (user_id_1, user_id_2) VALUES (?, ?)", self.id.to_string(), friend_id.to_string())
-                        .execute(transaction.as_mut()).await?;
-                }
-            }
-
-            for db_friend_id in database_friend_ids {
-                if !self.friends.contains(&db_friend_id) { // DB YES, STRUCT NO
-                    query!("DELETE FROM friends WHERE user_id_1 = ? AND user_id_2 = ?", self.id.to_string(), db_friend_id.to_string())
-                        .execute(transaction.as_mut()).await?;
-                }
-            }
+            // Friends are managed through friend requests, see models::friend
 
             Ok(())
         }
 
+        /// Removes the user for good, their answers are only kept without the link to them.
+        /// Everything pointing at `users` goes first, or the foreign keys stop it
         pub async fn delete(id : Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
 
-            query!("DELETE FROM users WHERE id = ?",
-                &id.to_string()).execute(transaction.as_mut()).await?;
-
             query!("DELETE FROM user_progress WHERE user_id = ?",
                 &id.to_string()).execute(transaction.as_mut()).await?;
 
@@ -280,16 +303,90 @@ pub mod database {
                 &id.to_string(),
                 &id.to_string()).execute(transaction.as_mut()).await?;
 
+            query!("DELETE FROM league_members WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM solved_tasks WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM friend_requests WHERE sender_id = ? OR receiver_id = ?",
+                &id.to_string(),
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM blocks WHERE blocker_id = ? OR blocked_id = ?",
+                &id.to_string(),
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM stream_tickets WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
             query!("DELETE FROM sessions WHERE user_id = ?",
                 &id.to_string()).execute(transaction.as_mut()).await?;
 
+            query!("DELETE FROM password_reset_tokens WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM verification_codes WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM user_roles WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM external_identities WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM two_factor WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM recovery_codes WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM login_challenges WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM unlock_tokens WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM login_failures WHERE scope = 'Account' AND subject = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("INSERT INTO anonymized_answers (id, task_id, content, correct, anonymized_time)
+                SELECT a.id, a.task_id, a.content, r.correct, ? FROM answers a LEFT JOIN answer_results r ON r.answer_id = a.id WHERE a.user_id = ?",
+                Utc::now(),
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM answer_results WHERE answer_id IN (SELECT id FROM answers WHERE user_id = ?)",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM grading_jobs WHERE answer_id IN (SELECT id FROM answers WHERE user_id = ?)",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
             query!("DELETE FROM answers WHERE user_id = ?",
                 &id.to_string()).execute(transaction.as_mut()).await?;
 
+            query!("DELETE FROM user_achievements WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM user_activity WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM profile_privacy WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            User::delete_classrooms(id, transaction).await?;
+
+            // The log stays, but not where the user was
+            query!("UPDATE audit_log SET ip_address = NULL WHERE user_id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
+            query!("DELETE FROM users WHERE id = ?",
+                &id.to_string()).execute(transaction.as_mut()).await?;
+
             Ok(())
         }
 
-        pub async fn login(username : String, password : String, transaction: &mut Transaction<'static, MySql>) -> Result<User, UserError> {
+        /// Starts a new session, the refresh token is only returned here
+        pub async fn login(username : String, password : String, client: &ClientInfo, session_config: &SessionConfig, two_factor_config: &TwoFactorConfig, transaction: &mut Transaction<'static, MySql>) -> Result<LoginStep, UserError> {
             let user_id: Uuid = match query!(
                 "SELECT id, password_hash FROM users WHERE username COLLATE utf8mb4_bin = ?",
                 username
@@ -298,7 +395,7 @@ pub mod database {
                     match result {
                         Some(row) => {
                             // Check password
-                            match bcrypt::verify(password, &row.password_hash) {
+                            match hasher().verify(&password, &row.password_hash).await {
                                 Ok(true) => (),
                                 Ok(false) => {
                                     warn!("User {} (id: {}) tried to log in with wrong password", username, &row.id);
@@ -307,7 +404,15 @@ pub mod database {
                                 Err(e) => return Err(UserError::HashError(e))
                             }
                             
-                            Uuid::parse_str(row.id.as_str()).expect("Couldn't parse string into Uid")
+                            let user_id = Uuid::parse_str(row.id.as_str()).expect("Couldn't parse string into Uid");
+                            
+                            // The only time the plain password is around to hash it again
+                            if hasher().needs_rehash(&row.password_hash) {
+                                User::set_password(user_id, &password, transaction).await?;
+                                info!("Password hash of user {} upgraded", user_id);
+                            }
+                            
+                            user_id
                         },
                         None => return Err(UserError::NoSuchUser)
                     }
@@ -315,27 +420,61 @@ pub mod database {
                 Err(e) => return Err(UserError::DatabaseError(e))
             };
 
-            let mut user = match User::read(user_id, transaction).await {
-                Ok(user) => user,
+            match User::two_factor_challenge(user_id, two_factor_config, transaction).await {
+                Ok(Some(challenge)) => {
+                    info!("User {} needs a second factor to log in", user_id);
+                    return Ok(LoginStep::TwoFactor(challenge));
+                },
+                Ok(None) => (),
                 Err(e) => return Err(UserError::DatabaseError(e))
-            };
+            }
+
+            let (user, tokens) = User::start_session(user_id, client, session_config, transaction).await.map_err(UserError::DatabaseError)?;
+            Ok(LoginStep::Done(user, tokens))
+        }
+
+        pub async fn set_password(id: Uuid, password: &str, transaction: &mut Transaction<'static, MySql>) -> Result<(), UserError> {
+            let password_hash = hasher().hash(password).await.map_err(UserError::HashError)?;
+            query!("UPDATE users SET password_hash = ? WHERE id = ?", password_hash, id.to_string())
+                .execute(transaction.as_mut()).await.map_err(UserError::DatabaseError)?;
+            Ok(())
+        }
 
-            user.auth_token = Some(Uuid::new_v4());
+        /// Needs the current password, so a stolen session alone can't take over the account
+        pub async fn change_password(id: Uuid, current_password: &str, new_password: &str, transaction: &mut Transaction<'static, MySql>) -> Result<(), UserError> {
+            let row = query!("SELECT password_hash FROM users WHERE id = ?", id.to_string())
+                .fetch_optional(transaction.as_mut()).await.map_err(UserError::DatabaseError)?
+                .ok_or(UserError::NoSuchUser)?;
 
-            query!(
-                "INSERT INTO sessions (user_id, auth_token, expiration_time, creation_time) VALUES (?, ?, ?, ?)",
-                &user.id.to_string(),
-                &user.auth_token.unwrap().to_string(),
-                Utc::now() + chrono::Days::new(14),
-                Utc::now()
-            ).execute(transaction.as_mut()).await.map_err(UserError::DatabaseError)?;
+            if !hasher().verify(current_password, &row.password_hash).await.map_err(UserError::HashError)? {
+                warn!("User {} tried to change their password with a wrong current one", id);
+                return Err(UserError::BadCredentials);
+            }
+
+            User::set_password(id, new_password, transaction).await?;
+            info!("User {} changed their password", id);
+            Ok(())
+        }
+
+        /// Once the user proved who they are, with or without a second factor
+        pub async fn start_session(id: Uuid, client: &ClientInfo, session_config: &SessionConfig, transaction: &mut Transaction<'static, MySql>) -> Result<(User, SessionTokens), sqlx::Error> {
+            let mut user = User::read(id, transaction).await?;
+
+            if User::cancel_deletion(id, transaction).await? {
+                info!("User {} logged in during the grace period, their account won't be deleted", id);
+            }
+
+            let tokens = Session::create(user.id, client, session_config, transaction).await?;
+            user.auth_token = Some(tokens.auth_token);
+
+            DomainEvent::UserLoggedIn { user_id: user.id }.record(transaction).await?;
             
-            info!("User {} logged in, token: {}", &user.id, &user.auth_token.unwrap());
-            Ok(user)
+            info!("User {} logged in", &user.id);
+            Ok((user, tokens))
         }
 
         pub async fn logout(auth_token: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
-            query!("DELETE FROM sessions WHERE auth_token = ?", auth_token.to_string())
+            query!("DELETE FROM sessions WHERE auth_token_hash = ?", hash_session_token(&auth_token.to_string()))
                 .execute(transaction.as_mut()).await?;
             Ok(())
         }
@@ -351,7 +490,7 @@ pub mod database {
             match auth_token {
                 None => Ok(Err(NoTokenInUser)),
                 Some(token) => {
-                    match query!("SELECT expiration_time FROM sessions WHERE auth_token = ?", token.to_string())
+                    match query!("SELECT expiration_time FROM sessions WHERE auth_token_hash = ?", hash_session_token(&token.to_string()))
                     .fetch_optional(transaction.as_mut()).await? {
                         None => Ok(Err(TokenNotInDatabse)),
                         Some(record) => {
@@ -366,10 +505,34 @@ pub mod database {
             }
         }
         
+        pub async fn record_activity(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+            query!("INSERT IGNORE INTO user_activity (user_id, day) VALUES (?, ?)",
+                id.to_string(),
+                Utc::now().date_naive()).execute(transaction.as_mut()).await?;
+            Ok(())
+        }
+
+        pub async fn read_streak(id: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<u32, sqlx::Error> {
+            let days = query!("SELECT day FROM user_activity WHERE user_id = ? ORDER BY day DESC", id.to_string())
+                .fetch_all(transaction.as_mut()).await?
+                .into_iter()
+                .map(|record| record.day)
+                .collect::<Vec<NaiveDate>>();
+
+            Ok(streak_length(&days, Utc::now().date_naive()))
+        }
+
+        pub async fn get_id_by_token(auth_token: Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Option<Uuid>, sqlx::Error> {
+            let record = query!("SELECT user_id FROM sessions WHERE auth_token_hash = ?", hash_session_token(&auth_token.to_string()))
+                .fetch_optional(transaction.as_mut()).await?;
+
+            Ok(record.map(|record| Uuid::parse_str(&record.user_id).expect("Couldn't parse string to Uuid")))
+        }
+        
         pub async fn check_authorization(auth_token: Uuid, user_id: &Uuid, transaction: &mut Transaction<'static, MySql>) -> Result<Result<(), AuthorizationError>, sqlx::Error> {
             use AuthorizationError::*;
             
-            let record = query!("SELECT user_id FROM sessions WHERE auth_token = ?", auth_token.to_string())
+            let record = query!("SELECT user_id FROM sessions WHERE auth_token_hash = ?", hash_session_token(&auth_token.to_string()))
                 .fetch_optional(transaction.as_mut()).await?;
             
             match record {
@@ -392,8 +555,7 @@ pub mod database {
 
         #[tokio::test]
         async fn test_create() {
-            let binding = db::get_database_connection_pool(None).await.unwrap();
-            let pool = binding.lock().await;
+            let pool = db::get_database_connection_pool(None).await.unwrap();
             let mut tx = pool.begin().await.unwrap();
 
             let user = User::new("test".to_string(), "aaaaa".to_string(), Some("test@test.com".to_string()), None, &mut tx).await.unwrap();
@@ -409,8 +571,7 @@ pub mod database {
 
         #[tokio::test]
         async fn test_read() {
-            let binding = db::get_database_connection_pool(None).await.unwrap();
-            let pool = binding.lock().await;
+            let pool = db::get_database_connection_pool(None).await.unwrap();
             let mut tx = pool.begin().await.unwrap();
 
             let user = User::new("test".to_string(), "aaaaa".to_string(), Some("test@test.com".to_string()), None, &mut tx).await.unwrap();
@@ -428,8 +589,7 @@ pub mod database {
 
         #[tokio::test]
         async fn test_update() {
-            let binding = db::get_database_connection_pool(None).await.unwrap();
-            let pool = binding.lock().await;
+            let pool = db::get_database_connection_pool(None).await.unwrap();
             let mut tx = pool.begin().await.unwrap();
 
             let mut user = User::new("test".to_string(), "aaaaa".to_string(), Some("test@test.com".to_string()), None, &mut tx).await.unwrap();
@@ -452,8 +612,7 @@ pub mod database {
 
         #[tokio::test]
         async fn test_delete() {
-            let binding = db::get_database_connection_pool(None).await.unwrap();
-            let pool = binding.lock().await;
+            let pool = db::get_database_connection_pool(None).await.unwrap();
             let mut tx = pool.begin().await.unwrap();
 
             let user = User::new("test".to_string(), "aaaaa".to_string(), Some("test@test.com".to_string()), None, &mut tx).await.unwrap();
@@ -467,6 +626,35 @@ pub mod database {
 
             assert!(result.is_ok());
         }
+
+        #[tokio::test]
+        async fn test_rehash_and_change_password() {
+            use crate::config::{SessionConfig, TwoFactorConfig};
+            use crate::models::session::ClientInfo;
+            use crate::models::user::UserError;
+            use sqlx::query;
+
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+
+            let user = User::new("rehash_test".to_string(), "aaaaa".to_string(), Some("rehash@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+
+            // An account from before Argon2
+            let old_hash = bcrypt::hash("aaaaa", 4).unwrap();
+            query!("UPDATE users SET password_hash = ? WHERE id = ?", old_hash, user.id.to_string())
+                .execute(tx.as_mut()).await.unwrap();
+
+            User::login("rehash_test".to_string(), "aaaaa".to_string(), &ClientInfo::default(), &SessionConfig::default(), &TwoFactorConfig::default(), &mut tx).await.unwrap();
+            let row = query!("SELECT password_hash FROM users WHERE id = ?", user.id.to_string()).fetch_one(tx.as_mut()).await.unwrap();
+            assert!(row.password_hash.starts_with("$argon2id$"));
+
+            assert!(matches!(User::change_password(user.id, "wrong", "bbbbb", &mut tx).await, Err(UserError::BadCredentials)));
+            User::change_password(user.id, "aaaaa", "bbbbb", &mut tx).await.unwrap();
+            assert!(User::login("rehash_test".to_string(), "bbbbb".to_string(), &ClientInfo::default(), &SessionConfig::default(), &TwoFactorConfig::default(), &mut tx).await.is_ok());
+
+            tx.rollback().await.unwrap();
+        }
     }
 }
 
@@ -482,7 +670,10 @@ mod tests {
             password_hash: "aaaaa".to_string(),
             email: Some("test@test.com".to_string()),
             phone: None,
+            email_verified: false,
+            phone_verified: false,
             bio: None,
+            avatar: None,
             friends: vec![],
             level: UserLevel {
                 level: 1,
@@ -514,4 +705,28 @@ mod tests {
         
         assert_eq!(deserialized, user);
     }
+
+    #[test]
+    fn test_streak_length() {
+        let today = NaiveDate::from_ymd_opt(2024, 9, 9).unwrap();
+        let day = |d| NaiveDate::from_ymd_opt(2024, 9, d).unwrap();
+
+        assert_eq!(streak_length(&[], today), 0);
+        assert_eq!(streak_length(&[day(9), day(8), day(7), day(5)], today), 3);
+        assert_eq!(streak_length(&[day(8), day(7)], today), 2);
+        assert_eq!(streak_length(&[day(7), day(6)], today), 0);
+    }
+
+    #[test]
+    fn test_contact_validation() {
+        assert!(valid_email("test@test.com"));
+        assert!(!valid_email("not an email"));
+        assert!(!valid_email("test@test.com and more"));
+        assert!(!valid_email(&format!("{}@test.com", "a".repeat(128))));
+
+        assert!(valid_phone("123456789"));
+        assert!(valid_phone("+48123456789"));
+        assert!(!valid_phone("12345"));
+        assert!(!valid_phone("phone: 123456789"));
+    }
 }
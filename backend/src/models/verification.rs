@@ -0,0 +1,237 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySql, Transaction};
+use uuid::Uuid;
+use chrono::prelude::*;
+use tracing::{info, warn};
+use super::password_reset::hash_token;
+use super::user::User;
+use crate::config::VerificationConfig;
+use crate::notifier::Recipient;
+
+/// Contact of the user a code is sent to
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Channel {
+    Email,
+    Phone
+}
+
+impl Channel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Email => "Email",
+            Channel::Phone => "Phone"
+        }
+    }
+
+    pub fn recipient(&self, destination: String) -> Recipient {
+        match self {
+            Channel::Email => Recipient::Email(destination),
+            Channel::Phone => Recipient::Phone(destination)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VerificationError {
+    NoSuchContact,
+    AlreadyVerified,
+    TooSoon(chrono::Duration), // Until the next code can be sent
+    InvalidCode,
+    TooManyAttempts,
+    DatabaseError(sqlx::Error)
+}
+
+impl From<sqlx::Error> for VerificationError {
+    fn from(e: sqlx::Error) -> Self {
+        VerificationError::DatabaseError(e)
+    }
+}
+
+/// Short enough to be typed from a text message
+pub fn generate_code() -> String {
+    format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
+}
+
+// Salted with the user id, so equal codes of different users don't share a hash
+fn hash_code(user_id: Uuid, code: &str) -> String {
+    hash_token(&format!("{}:{}", user_id, code))
+}
+
+/// Contact of the user on `channel` and whether it's verified
+fn contact(user: &User, channel: Channel) -> (Option<&String>, bool) {
+    match channel {
+        Channel::Email => (user.email.as_ref(), user.email_verified),
+        Channel::Phone => (user.phone.as_ref(), user.phone_verified)
+    }
+}
+
+pub mod database {
+    use super::*;
+
+    impl User {
+        /// Replaces the pending code of `channel`, returns where to send it and the code in plain text
+        pub async fn create_verification(id: Uuid, channel: Channel, config: &VerificationConfig, transaction: &mut Transaction<'static, MySql>) -> Result<(Recipient, String), VerificationError> {
+            let user = User::read(id, transaction).await?;
+            let destination = match contact(&user, channel) {
+                (None, _) => return Err(VerificationError::NoSuchContact),
+                (Some(_), true) => return Err(VerificationError::AlreadyVerified),
+                (Some(destination), false) => destination.clone()
+            };
+
+            let previous = query!(
+                "SELECT creation_time FROM verification_codes WHERE user_id = ? AND channel = ? FOR UPDATE",
+                id.to_string(),
+                channel.as_str()
+            ).fetch_optional(transaction.as_mut()).await?;
+
+            if let Some(previous) = previous {
+                let next_allowed = previous.creation_time.and_utc() + config.resend_cooldown();
+                if next_allowed > Utc::now() {
+                    return Err(VerificationError::TooSoon(next_allowed - Utc::now()));
+                }
+            }
+
+            let code = generate_code();
+            query!(
+                "REPLACE INTO verification_codes (user_id, channel, destination, code_hash, attempts, creation_time, expiration_time) VALUES (?, ?, ?, ?, 0, ?, ?)",
+                id.to_string(),
+                channel.as_str(),
+                destination,
+                hash_code(id, &code),
+                Utc::now(),
+                Utc::now() + config.code_ttl()
+            ).execute(transaction.as_mut()).await?;
+
+            info!("Verification code of {:?} created for user {}", channel, id);
+            Ok((channel.recipient(destination), code))
+        }
+
+        /// Wrong codes count as attempts, so the transaction has to be committed on `InvalidCode` too
+        pub async fn verify_contact(id: Uuid, channel: Channel, code: &str, config: &VerificationConfig, transaction: &mut Transaction<'static, MySql>) -> Result<(), VerificationError> {
+            let user = User::read(id, transaction).await?;
+            let destination = match contact(&user, channel) {
+                (None, _) => return Err(VerificationError::NoSuchContact),
+                (Some(_), true) => return Err(VerificationError::AlreadyVerified),
+                (Some(destination), false) => destination.clone()
+            };
+
+            let row = query!(
+                "SELECT destination, code_hash, attempts FROM verification_codes
+                WHERE user_id = ? AND channel = ? AND expiration_time > ? FOR UPDATE",
+                id.to_string(),
+                channel.as_str(),
+                Utc::now()
+            ).fetch_optional(transaction.as_mut()).await?;
+
+            // The code is only good for the address it was sent to
+            let row = match row {
+                Some(row) if row.destination == destination => row,
+                _ => return Err(VerificationError::InvalidCode)
+            };
+
+            if row.attempts as u32 >= config.max_attempts {
+                return Err(VerificationError::TooManyAttempts);
+            }
+
+            if row.code_hash != hash_code(id, code) {
+                query!(
+                    "UPDATE verification_codes SET attempts = attempts + 1 WHERE user_id = ? AND channel = ?",
+                    id.to_string(),
+                    channel.as_str()
+                ).execute(transaction.as_mut()).await?;
+
+                warn!("Wrong verification code of {:?} for user {} (attempt {}/{})", channel, id, row.attempts + 1, config.max_attempts);
+                return Err(VerificationError::InvalidCode);
+            }
+
+            match channel {
+                Channel::Email => query!("UPDATE users SET email_verified_time = ? WHERE id = ?", Utc::now(), id.to_string())
+                    .execute(transaction.as_mut()).await?,
+                Channel::Phone => query!("UPDATE users SET phone_verified_time = ? WHERE id = ?", Utc::now(), id.to_string())
+                    .execute(transaction.as_mut()).await?
+            };
+
+            query!("DELETE FROM verification_codes WHERE user_id = ? AND channel = ?", id.to_string(), channel.as_str())
+                .execute(transaction.as_mut()).await?;
+
+            info!("{:?} of user {} verified", channel, id);
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::database as db;
+
+        #[tokio::test]
+        async fn test_verify_contact() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let config = VerificationConfig { resend_cooldown_secs: 0, ..Default::default() };
+
+            let user = User::new("verification_test".to_string(), "aaaaa".to_string(), Some("verify@test.com".to_string()), None, &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+
+            assert!(matches!(User::create_verification(user.id, Channel::Phone, &config, &mut tx).await, Err(VerificationError::NoSuchContact)));
+
+            let (recipient, code) = User::create_verification(user.id, Channel::Email, &config, &mut tx).await.unwrap();
+            assert_eq!(recipient, Recipient::Email("verify@test.com".to_string()));
+
+            let wrong = if code == "000000" { "000001" } else { "000000" };
+            assert!(matches!(User::verify_contact(user.id, Channel::Email, wrong, &config, &mut tx).await, Err(VerificationError::InvalidCode)));
+            User::verify_contact(user.id, Channel::Email, &code, &config, &mut tx).await.unwrap();
+
+            assert!(User::read(user.id, &mut tx).await.unwrap().email_verified);
+            assert!(matches!(User::create_verification(user.id, Channel::Email, &config, &mut tx).await, Err(VerificationError::AlreadyVerified)));
+
+            // A new address has to be verified again
+            let mut user = User::read(user.id, &mut tx).await.unwrap();
+            user.email = Some("verify2@test.com".to_string());
+            user.update(&mut tx).await.unwrap();
+            assert!(!User::read(user.id, &mut tx).await.unwrap().email_verified);
+
+            tx.rollback().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_attempts_and_cooldown() {
+            let pool = db::get_database_connection_pool(None).await.unwrap();
+            let mut tx = pool.begin().await.unwrap();
+            let config = VerificationConfig { max_attempts: 2, ..Default::default() };
+
+            let user = User::new("verification_test".to_string(), "aaaaa".to_string(), None, Some("123456789".to_string()), &mut tx).await.unwrap();
+            user.create(&mut tx).await.unwrap();
+
+            let (_, code) = User::create_verification(user.id, Channel::Phone, &config, &mut tx).await.unwrap();
+            assert!(matches!(User::create_verification(user.id, Channel::Phone, &config, &mut tx).await, Err(VerificationError::TooSoon(_))));
+
+            let wrong = if code == "000000" { "000001" } else { "000000" };
+            for _ in 0..2 {
+                assert!(matches!(User::verify_contact(user.id, Channel::Phone, wrong, &config, &mut tx).await, Err(VerificationError::InvalidCode)));
+            }
+            assert!(matches!(User::verify_contact(user.id, Channel::Phone, &code, &config, &mut tx).await, Err(VerificationError::TooManyAttempts)));
+
+            tx.rollback().await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_format() {
+        for _ in 0..100 {
+            let code = generate_code();
+            assert_eq!(code.len(), 6);
+            assert!(code.chars().all(|c| c.is_ascii_digit()));
+        }
+
+        let user_id = Uuid::new_v4();
+        assert_eq!(hash_code(user_id, "123456"), hash_code(user_id, "123456"));
+        assert_ne!(hash_code(user_id, "123456"), hash_code(Uuid::new_v4(), "123456"));
+    }
+}
@@ -0,0 +1,147 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Transaction};
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, StreamExt};
+use tokio_stream::wrappers::BroadcastStream;
+use tracing::warn;
+use uuid::Uuid;
+use crate::events::{DomainEvent, Subscriber};
+use crate::models::achievement::AchievementDefinition;
+use crate::models::leaderboard::LeagueTier;
+
+// Notifications for slow clients are dropped once this many are queued
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Pushed to the frontend in real time
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Notification {
+    AnswerGraded { answer_id: Uuid, correct: bool, explanation: Option<String> },
+    FriendRequestReceived { request_id: Uuid, sender_id: Uuid },
+    AchievementUnlocked { achievement_id: String, name: String },
+    LeaguePositionChanged { previous_tier: LeagueTier, tier: LeagueTier, rank: u32 }
+}
+
+impl Notification {
+    /// Who should get notified about `event`, if anyone
+    pub fn from_event(event: &DomainEvent) -> Option<(Uuid, Notification)> {
+        match event {
+            DomainEvent::AnswerVerified { answer_id, user_id, correct, explanation, .. } => Some((*user_id, Notification::AnswerGraded {
+                answer_id: *answer_id,
+                correct: *correct,
+                explanation: explanation.clone()
+            })),
+            DomainEvent::FriendRequestSent { request_id, sender_id, receiver_id } => Some((*receiver_id, Notification::FriendRequestReceived {
+                request_id: *request_id,
+                sender_id: *sender_id
+            })),
+            DomainEvent::AchievementUnlocked { user_id, achievement_id } => Some((*user_id, Notification::AchievementUnlocked {
+                achievement_id: achievement_id.clone(),
+                name: AchievementDefinition::find(achievement_id)
+                    .map(|definition| definition.name.to_string())
+                    .unwrap_or_else(|| achievement_id.clone())
+            })),
+            DomainEvent::LeagueChanged { user_id, previous_tier, tier, rank } => Some((*user_id, Notification::LeaguePositionChanged {
+                previous_tier: *previous_tier,
+                tier: *tier,
+                rank: *rank
+            })),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NotificationHub {
+    sender: broadcast::Sender<(Uuid, Notification)>
+}
+
+impl Default for NotificationHub {
+    fn default() -> Self {
+        NotificationHub::new()
+    }
+}
+
+impl NotificationHub {
+    pub fn new() -> NotificationHub {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        NotificationHub { sender }
+    }
+
+    pub fn send(&self, user_id: Uuid, notification: Notification) {
+        // An error only means that nobody is listening right now
+        let _ = self.sender.send((user_id, notification));
+    }
+
+    /// Notifications addressed to `user_id`, from now on
+    pub fn subscribe(&self, user_id: Uuid) -> impl Stream<Item = Notification> {
+        BroadcastStream::new(self.sender.subscribe())
+            .filter_map(move |message| match message {
+                Ok((recipient, notification)) if recipient == user_id => Some(notification),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("Notification stream of user {} lagged: {}", user_id, e);
+                    None
+                }
+            })
+    }
+}
+
+/// Forwards domain events to the connected users
+pub struct NotificationSubscriber {
+    hub: NotificationHub
+}
+
+impl NotificationSubscriber {
+    pub fn new(hub: NotificationHub) -> NotificationSubscriber {
+        NotificationSubscriber { hub }
+    }
+}
+
+#[async_trait]
+impl Subscriber for NotificationSubscriber {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    // Nothing to do until the delivery commits, a rolled back or retried one would push the same thing twice
+    async fn handle(&self, _event: &DomainEvent, _transaction: &mut Transaction<'static, MySql>) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    fn delivered(&self, event: &DomainEvent) {
+        if let Some((user_id, notification)) = Notification::from_event(event) {
+            self.hub.send(user_id, notification);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hub_filters_by_user() {
+        let hub = NotificationHub::new();
+        let user_id = Uuid::new_v4();
+        let mut stream = Box::pin(hub.subscribe(user_id));
+
+        let notification = Notification::AchievementUnlocked { achievement_id: "streak_3".to_string(), name: "Warming up".to_string() };
+        hub.send(Uuid::new_v4(), notification.clone());
+        hub.send(user_id, notification.clone());
+
+        assert_eq!(stream.next().await, Some(notification));
+    }
+
+    #[test]
+    fn test_from_event() {
+        let receiver_id = Uuid::new_v4();
+        let event = DomainEvent::FriendRequestSent { request_id: Uuid::new_v4(), sender_id: Uuid::new_v4(), receiver_id };
+
+        let (user_id, notification) = Notification::from_event(&event).unwrap();
+        assert_eq!(user_id, receiver_id);
+        assert!(matches!(notification, Notification::FriendRequestReceived { .. }));
+
+        assert!(Notification::from_event(&DomainEvent::UserRegistered { user_id: receiver_id }).is_none());
+    }
+}
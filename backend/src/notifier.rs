@@ -0,0 +1,263 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::info;
+use crate::config::{DeliveryMethod, NotifierConfig, SmsGatewayConfig, SmtpConfig};
+
+/// Where a message goes, taken from the `email`/`phone` fields of `User`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recipient {
+    Email(String),
+    Phone(String)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub subject: String,
+    pub body: String
+}
+
+#[derive(Debug)]
+pub enum NotifierError {
+    UnsupportedRecipient,
+    BadAddress(String),
+    MessageError(lettre::error::Error),
+    SmtpError(lettre::transport::smtp::Error),
+    RequestError(reqwest::Error),
+    IoError(std::io::Error)
+}
+
+/// Delivers messages to users outside of the app
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, recipient: &Recipient, message: &Message) -> Result<(), NotifierError>;
+}
+
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox
+}
+
+impl SmtpNotifier {
+    pub fn new(config: &SmtpConfig) -> Result<SmtpNotifier, NotifierError> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+            .map_err(NotifierError::SmtpError)?
+            .port(config.port);
+
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(SmtpNotifier {
+            transport: builder.build(),
+            from: config.from.parse().map_err(|_| NotifierError::BadAddress(config.from.clone()))?
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    async fn send(&self, recipient: &Recipient, message: &Message) -> Result<(), NotifierError> {
+        let address = match recipient {
+            Recipient::Email(address) => address,
+            Recipient::Phone(_) => return Err(NotifierError::UnsupportedRecipient)
+        };
+
+        let email = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(address.parse().map_err(|_| NotifierError::BadAddress(address.clone()))?)
+            .subject(&message.subject)
+            .body(message.body.clone())
+            .map_err(NotifierError::MessageError)?;
+
+        self.transport.send(email).await.map_err(NotifierError::SmtpError)?;
+        Ok(())
+    }
+}
+
+/// Sends text messages through an HTTP gateway, which gets `{"from", "to", "body"}` as json
+pub struct SmsNotifier {
+    client: reqwest::Client,
+    config: SmsGatewayConfig
+}
+
+impl SmsNotifier {
+    pub fn new(config: &SmsGatewayConfig) -> SmsNotifier {
+        SmsNotifier {
+            client: reqwest::Client::new(),
+            config: config.clone()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SmsRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    body: &'a str
+}
+
+#[async_trait]
+impl Notifier for SmsNotifier {
+    async fn send(&self, recipient: &Recipient, message: &Message) -> Result<(), NotifierError> {
+        let phone = match recipient {
+            Recipient::Phone(phone) => phone,
+            Recipient::Email(_) => return Err(NotifierError::UnsupportedRecipient)
+        };
+
+        let mut request = self.client.post(&self.config.url)
+            .json(&SmsRequest { from: &self.config.from, to: phone, body: &message.body });
+        if let Some(api_key) = &self.config.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        request.send().await
+            .and_then(|response| response.error_for_status())
+            .map_err(NotifierError::RequestError)?;
+        Ok(())
+    }
+}
+
+/// Appends messages to a file, for development and tests
+pub struct FileNotifier {
+    path: PathBuf,
+    // Keeps messages from interleaving
+    lock: Mutex<()>
+}
+
+impl FileNotifier {
+    pub fn new(path: impl Into<PathBuf>) -> FileNotifier {
+        FileNotifier { path: path.into(), lock: Mutex::new(()) }
+    }
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn send(&self, recipient: &Recipient, message: &Message) -> Result<(), NotifierError> {
+        let _guard = self.lock.lock().await;
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path).await
+            .map_err(NotifierError::IoError)?;
+
+        let entry = format!("[{}] to: {:?}\nsubject: {}\n{}\n\n", Utc::now().to_rfc3339(), recipient, message.subject, message.body);
+        file.write_all(entry.as_bytes()).await.map_err(NotifierError::IoError)?;
+        // tokio finishes writes in the background, without this the next message can get ahead of this one
+        file.flush().await.map_err(NotifierError::IoError)
+    }
+}
+
+/// Only logs the messages, their content included, never use it in production
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn send(&self, recipient: &Recipient, message: &Message) -> Result<(), NotifierError> {
+        info!("Message to {:?}: {}\n{}", recipient, message.subject, message.body);
+        Ok(())
+    }
+}
+
+/// Picks the notifier based on the kind of recipient
+pub struct Notifiers {
+    email: Box<dyn Notifier>,
+    sms: Box<dyn Notifier>
+}
+
+#[async_trait]
+impl Notifier for Notifiers {
+    async fn send(&self, recipient: &Recipient, message: &Message) -> Result<(), NotifierError> {
+        match recipient {
+            Recipient::Email(_) => self.email.send(recipient, message).await,
+            Recipient::Phone(_) => self.sms.send(recipient, message).await
+        }
+    }
+}
+
+impl Notifiers {
+    pub fn new(email: impl Notifier + 'static, sms: impl Notifier + 'static) -> Notifiers {
+        Notifiers { email: Box::new(email), sms: Box::new(sms) }
+    }
+
+    pub fn from_config(config: &NotifierConfig) -> Result<Arc<dyn Notifier>, NotifierError> {
+        let build = |method: DeliveryMethod| -> Result<Box<dyn Notifier>, NotifierError> {
+            Ok(match method {
+                DeliveryMethod::Smtp => Box::new(SmtpNotifier::new(&config.smtp)?),
+                DeliveryMethod::Http => Box::new(SmsNotifier::new(&config.sms_gateway)),
+                DeliveryMethod::File => Box::new(FileNotifier::new(&config.file)),
+                DeliveryMethod::Log => Box::new(LogNotifier)
+            })
+        };
+
+        Ok(Arc::new(Notifiers {
+            email: build(config.email)?,
+            sms: build(config.sms)?
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct RecordingNotifier(Arc<std::sync::Mutex<Vec<Recipient>>>);
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn send(&self, recipient: &Recipient, _message: &Message) -> Result<(), NotifierError> {
+            self.0.lock().unwrap().push(recipient.clone());
+            Ok(())
+        }
+    }
+
+    fn message() -> Message {
+        Message { subject: "Hi".to_string(), body: "Hello there".to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_routing() {
+        let emails = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let texts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let notifiers = Notifiers::new(RecordingNotifier(emails.clone()), RecordingNotifier(texts.clone()));
+
+        notifiers.send(&Recipient::Email("test@test.com".to_string()), &message()).await.unwrap();
+        notifiers.send(&Recipient::Phone("123456789".to_string()), &message()).await.unwrap();
+
+        assert_eq!(*emails.lock().unwrap(), vec![Recipient::Email("test@test.com".to_string())]);
+        assert_eq!(*texts.lock().unwrap(), vec![Recipient::Phone("123456789".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_file_notifier() {
+        let path = std::env::temp_dir().join(format!("notifier_test_{}.log", uuid::Uuid::new_v4()));
+        let notifier = FileNotifier::new(&path);
+
+        notifier.send(&Recipient::Email("test@test.com".to_string()), &message()).await.unwrap();
+        notifier.send(&Recipient::Phone("123456789".to_string()), &message()).await.unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(content.contains("test@test.com"));
+        assert!(content.contains("123456789"));
+        assert_eq!(content.matches("Hello there").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_smtp_rejects_phone() {
+        let config = SmtpConfig { host: "localhost".to_string(), ..Default::default() };
+        let notifier = SmtpNotifier::new(&config).unwrap();
+
+        let result = notifier.send(&Recipient::Phone("123456789".to_string()), &message()).await;
+        assert!(matches!(result, Err(NotifierError::UnsupportedRecipient)));
+    }
+}
@@ -0,0 +1,321 @@
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{self, PasswordHash, PasswordHasher as _, PasswordVerifier as _, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version, ARGON2ID_IDENT};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use tracing::warn;
+use crate::config::{HashAlgorithm, PasswordHashingConfig, PasswordPolicyConfig};
+
+static HASHER: OnceLock<PasswordHasher> = OnceLock::new();
+
+/// Sets how new passwords are hashed, returns `false` if it was already set
+pub fn init_hasher(hasher: PasswordHasher) -> bool {
+    HASHER.set(hasher).is_ok()
+}
+
+/// Argon2id with the default parameters unless `init_hasher` was called
+pub fn hasher() -> &'static PasswordHasher {
+    HASHER.get_or_init(|| {
+        PasswordHasher::new(&PasswordHashingConfig::default()).expect("The default hashing parameters are valid")
+    })
+}
+
+#[derive(Debug)]
+pub enum HashError {
+    BadParams(argon2::Error),
+    Argon2(password_hash::Error),
+    Bcrypt(bcrypt::BcryptError),
+    /// The blocking task hashing the password panicked
+    Task(tokio::task::JoinError)
+}
+
+impl fmt::Display for HashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashError::BadParams(e) => write!(f, "bad Argon2 parameters: {}", e),
+            HashError::Argon2(e) => write!(f, "Argon2: {}", e),
+            HashError::Bcrypt(e) => write!(f, "bcrypt: {}", e),
+            HashError::Task(e) => write!(f, "hashing task: {}", e)
+        }
+    }
+}
+
+/// Hashes new passwords with the configured algorithm and checks old ones with whatever they were hashed with,
+/// both kinds are PHC strings (`$argon2id$...`, `$2b$...`) so they're told apart by the prefix
+#[derive(Clone)]
+pub struct PasswordHasher {
+    algorithm: HashAlgorithm,
+    argon2: Argon2<'static>,
+    params: Params,
+    bcrypt_cost: u32
+}
+
+impl PasswordHasher {
+    pub fn new(config: &PasswordHashingConfig) -> Result<PasswordHasher, HashError> {
+        let params = Params::new(config.argon2_memory_kib, config.argon2_iterations, config.argon2_parallelism, None)
+            .map_err(HashError::BadParams)?;
+
+        Ok(PasswordHasher {
+            algorithm: config.algorithm,
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone()),
+            params,
+            bcrypt_cost: config.bcrypt_cost
+        })
+    }
+
+    /// Hashing is slow on purpose, so it runs on the blocking thread pool instead of stalling the runtime
+    pub async fn hash(&self, password: &str) -> Result<String, HashError> {
+        let hasher = self.clone();
+        let password = password.to_string();
+        tokio::task::spawn_blocking(move || hasher.hash_blocking(&password))
+            .await.map_err(HashError::Task)?
+    }
+
+    /// Only an error if the stored hash is broken, a wrong password is `Ok(false)`
+    pub async fn verify(&self, password: &str, hash: &str) -> Result<bool, HashError> {
+        let hasher = self.clone();
+        let (password, hash) = (password.to_string(), hash.to_string());
+        tokio::task::spawn_blocking(move || hasher.verify_blocking(&password, &hash))
+            .await.map_err(HashError::Task)?
+    }
+
+    fn hash_blocking(&self, password: &str) -> Result<String, HashError> {
+        match self.algorithm {
+            HashAlgorithm::Argon2id => {
+                let salt = SaltString::generate(&mut OsRng);
+                let hash = self.argon2.hash_password(password.as_bytes(), &salt).map_err(HashError::Argon2)?;
+                Ok(hash.to_string())
+            },
+            HashAlgorithm::Bcrypt => bcrypt::hash(password, self.bcrypt_cost).map_err(HashError::Bcrypt)
+        }
+    }
+
+    fn verify_blocking(&self, password: &str, hash: &str) -> Result<bool, HashError> {
+        if hash.starts_with("$argon2") {
+            let parsed = PasswordHash::new(hash).map_err(HashError::Argon2)?;
+            // Argon2 would call a hash without the hash part just a wrong password
+            if parsed.hash.is_none() {
+                return Err(HashError::Argon2(password_hash::Error::PhcStringField));
+            }
+            // The parameters come from the hash itself, not the config
+            return match self.argon2.verify_password(password.as_bytes(), &parsed) {
+                Ok(()) => Ok(true),
+                Err(password_hash::Error::Password) => Ok(false),
+                Err(e) => Err(HashError::Argon2(e))
+            };
+        }
+
+        bcrypt::verify(password, hash).map_err(HashError::Bcrypt)
+    }
+
+    /// Whether the hash was made with another algorithm or weaker parameters than the configured ones
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        match self.algorithm {
+            HashAlgorithm::Argon2id => {
+                let parsed = match PasswordHash::new(hash) {
+                    Ok(parsed) if parsed.algorithm == ARGON2ID_IDENT => parsed,
+                    _ => return true
+                };
+
+                match Params::try_from(&parsed) {
+                    Ok(params) => params.m_cost() < self.params.m_cost()
+                        || params.t_cost() < self.params.t_cost()
+                        || params.p_cost() < self.params.p_cost(),
+                    Err(e) => {
+                        warn!("Couldn't read the parameters of an Argon2 hash: {}", e);
+                        true
+                    }
+                }
+            },
+            HashAlgorithm::Bcrypt => match hash.parse::<bcrypt::HashParts>() {
+                Ok(parts) => parts.get_cost() < self.bcrypt_cost,
+                Err(_) => true
+            }
+        }
+    }
+}
+
+/// Why a password was turned down, shown to the user next to the field
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum PasswordProblem {
+    TooShort { min: usize },
+    TooLong { max: usize },
+    TooWeak { bits: u32, required: u32 },
+    ContainsUsername,
+    Breached { count: u64 }
+}
+
+impl fmt::Display for PasswordProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordProblem::TooShort { min } => write!(f, "must be at least {} characters long", min),
+            PasswordProblem::TooLong { max } => write!(f, "can't be longer than {} characters", max),
+            PasswordProblem::TooWeak { .. } => write!(f, "is too easy to guess, use a longer one or mix in other kinds of characters"),
+            PasswordProblem::ContainsUsername => write!(f, "can't contain the username"),
+            PasswordProblem::Breached { count } => write!(f, "appeared in {} known data breaches, pick another one", count)
+        }
+    }
+}
+
+/// Rough guess in bits, repeated and consecutive characters (aaa, 123, abc) count once
+pub fn estimate_entropy(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+
+    let classes = [
+        (chars.iter().any(|c| c.is_ascii_lowercase()), 26),
+        (chars.iter().any(|c| c.is_ascii_uppercase()), 26),
+        (chars.iter().any(|c| c.is_ascii_digit()), 10),
+        (chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' '), 33),
+        (chars.iter().any(|c| !c.is_ascii()), 100)
+    ];
+    let pool: u32 = classes.iter().filter(|(present, _)| *present).map(|(_, size)| size).sum();
+    if pool == 0 {
+        return 0.0;
+    }
+
+    let effective_length = chars.iter().enumerate()
+        .filter(|(i, c)| *i == 0 || (**c as i64 - chars[i - 1] as i64).abs() > 1)
+        .count();
+
+    effective_length as f64 * (pool as f64).log2()
+}
+
+pub struct PasswordPolicy {
+    config: PasswordPolicyConfig
+}
+
+impl PasswordPolicy {
+    pub fn new(config: &PasswordPolicyConfig) -> PasswordPolicy {
+        PasswordPolicy { config: config.clone() }
+    }
+
+    /// Every problem with the password, empty if it's fine. Only reading the breach list can fail
+    pub async fn check(&self, username: Option<&str>, password: &str) -> Result<Vec<PasswordProblem>, std::io::Error> {
+        let mut problems = Vec::new();
+        let length = password.chars().count();
+
+        if length < self.config.min_length {
+            problems.push(PasswordProblem::TooShort { min: self.config.min_length });
+        }
+        if length > self.config.max_length {
+            problems.push(PasswordProblem::TooLong { max: self.config.max_length });
+        }
+
+        let bits = estimate_entropy(password) as u32;
+        if bits < self.config.min_entropy_bits {
+            problems.push(PasswordProblem::TooWeak { bits, required: self.config.min_entropy_bits });
+        }
+
+        if let Some(username) = username.filter(|username| username.chars().count() >= 3) {
+            if password.to_lowercase().contains(&username.to_lowercase()) {
+                problems.push(PasswordProblem::ContainsUsername);
+            }
+        }
+
+        if let Some(count) = self.breach_count(password).await? {
+            problems.push(PasswordProblem::Breached { count });
+        }
+
+        Ok(problems)
+    }
+
+    /// Looked up in range files like the ones of Pwned Passwords: the file named after the first 5 hex digits
+    /// of the SHA-1 holds `SUFFIX:COUNT` lines for the rest. Prefixes without a file count as not breached
+    async fn breach_count(&self, password: &str) -> Result<Option<u64>, std::io::Error> {
+        let dir = match &self.config.breached_passwords_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => return Ok(None)
+        };
+
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+
+        let mut content = None;
+        for name in [prefix.to_string(), format!("{}.txt", prefix)] {
+            match tokio::fs::read_to_string(dir.join(name)).await {
+                Ok(file) => {
+                    content = Some(file);
+                    break;
+                },
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e)
+            }
+        }
+
+        // Padding entries have a count of 0
+        Ok(content.and_then(|content| content.lines()
+            .filter_map(|line| line.trim().split_once(':'))
+            .find(|(line_suffix, _)| line_suffix.eq_ignore_ascii_case(suffix))
+            .and_then(|(_, count)| count.trim().parse::<u64>().ok())
+            .filter(|count| *count >= self.config.min_breach_count)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hashing() {
+        let config = PasswordHashingConfig { argon2_memory_kib: 1024, argon2_iterations: 1, ..Default::default() };
+        let hasher = PasswordHasher::new(&config).unwrap();
+
+        let hash = hasher.hash("hunter22").await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(hasher.verify("hunter22", &hash).await.unwrap());
+        assert!(!hasher.verify("hunter23", &hash).await.unwrap());
+        assert!(!hasher.needs_rehash(&hash));
+
+        // Old bcrypt hashes still work, but get replaced
+        let old = bcrypt::hash("hunter22", 4).unwrap();
+        assert!(hasher.verify("hunter22", &old).await.unwrap());
+        assert!(hasher.needs_rehash(&old));
+
+        // So do Argon2 hashes with weaker parameters
+        let stronger = PasswordHasher::new(&PasswordHashingConfig { argon2_iterations: 2, ..config.clone() }).unwrap();
+        assert!(stronger.needs_rehash(&hash));
+        assert!(stronger.verify("hunter22", &hash).await.unwrap());
+
+        let bcrypt_hasher = PasswordHasher::new(&PasswordHashingConfig { algorithm: HashAlgorithm::Bcrypt, bcrypt_cost: 5, ..config }).unwrap();
+        assert!(bcrypt_hasher.needs_rehash(&old));
+        assert!(bcrypt_hasher.needs_rehash(&hash));
+        assert!(!bcrypt_hasher.needs_rehash(&bcrypt_hasher.hash("hunter22").await.unwrap()));
+
+        assert!(hasher.verify("hunter22", "$argon2id$garbage").await.is_err());
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(estimate_entropy(""), 0.0);
+        assert!(estimate_entropy("aaaaaaaaaaaa") < 5.0);
+        assert!(estimate_entropy("123456789") < 4.0);
+        assert!(estimate_entropy("password") < estimate_entropy("Password1!"));
+        assert!(estimate_entropy("correct horse battery staple") > 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_policy() {
+        let dir = std::env::temp_dir().join(format!("breached_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        // Only the range file of the one breached password is there
+        let hash = hex::encode_upper(Sha1::digest(b"Tr0ub4dor&3"));
+        std::fs::write(dir.join(&hash[..5]), format!("0000000000000000000000000000000000A:0\r\n{}:42\r\n", &hash[5..])).unwrap();
+
+        let config = PasswordPolicyConfig { breached_passwords_dir: Some(dir.to_string_lossy().to_string()), ..Default::default() };
+        let policy = PasswordPolicy::new(&config);
+
+        assert_eq!(policy.check(Some("jan"), "Tr0ub4dor&3").await.unwrap(), vec![PasswordProblem::Breached { count: 42 }]);
+        assert_eq!(policy.check(Some("jan"), "correct horse battery staple").await.unwrap(), vec![]);
+        assert_eq!(policy.check(None, "JanKowalski!92").await.unwrap(), vec![]);
+        assert_eq!(policy.check(Some("kowalski"), "JanKowalski!92").await.unwrap(), vec![PasswordProblem::ContainsUsername]);
+
+        let problems = policy.check(None, "aaa").await.unwrap();
+        assert!(matches!(problems[..], [PasswordProblem::TooShort { min: 8 }, PasswordProblem::TooWeak { .. }]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
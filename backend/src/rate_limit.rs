@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sqlx::MySqlPool;
+use tokio::time::Instant;
+use tracing::{error, warn};
+use uuid::Uuid;
+use crate::config::{RateLimitConfig, RateLimitRule};
+use crate::jwt;
+use crate::models::session::Session;
+
+// Full buckets carry no information, they're dropped once there are this many
+const PRUNE_ABOVE: usize = 10_000;
+
+pub const LIMIT_HEADER: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const REMAINING_HEADER: HeaderName = HeaderName::from_static("ratelimit-remaining");
+pub const RESET_HEADER: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// Routes that share a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    Auth,
+    Answers,
+    Tasks,
+    Default
+}
+
+impl RouteGroup {
+    pub fn of(method: &Method, path: &str) -> RouteGroup {
+        const AUTH_PREFIXES: [&str; 7] = ["/user/login", "/user/register", "/user/password", "/user/verify", "/user/unlock", "/user/sessions/refresh", "/user/oidc"];
+
+        if AUTH_PREFIXES.iter().any(|prefix| path.starts_with(prefix)) {
+            RouteGroup::Auth
+        } else if path.starts_with("/answer") && (method == Method::POST || method == Method::PUT) {
+            RouteGroup::Answers
+        } else if path.starts_with("/task/") {
+            RouteGroup::Tasks
+        } else {
+            RouteGroup::Default
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteGroup::Auth => "auth",
+            RouteGroup::Answers => "answers",
+            RouteGroup::Tasks => "tasks",
+            RouteGroup::Default => "default"
+        }
+    }
+
+    pub fn rule(&self, config: &RateLimitConfig) -> RateLimitRule {
+        match self {
+            RouteGroup::Auth => config.auth,
+            RouteGroup::Answers => config.answers,
+            RouteGroup::Tasks => config.tasks,
+            RouteGroup::Default => config.default_rule()
+        }
+    }
+}
+
+/// Whose bucket a request is taken from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Identity {
+    User(Uuid),
+    Ip(IpAddr)
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Identity::User(id) => write!(f, "user:{}", id),
+            Identity::Ip(ip) => write!(f, "ip:{}", ip)
+        }
+    }
+}
+
+/// What taking a request from a bucket came out as
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Until the bucket is full again
+    pub reset: Duration,
+    /// Until the next request is allowed, only set when this one wasn't
+    pub retry_after: Option<Duration>
+}
+
+impl Decision {
+    fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(LIMIT_HEADER, HeaderValue::from(self.limit));
+        headers.insert(REMAINING_HEADER, HeaderValue::from(self.remaining));
+        headers.insert(RESET_HEADER, HeaderValue::from(self.reset.as_secs_f64().ceil() as u64));
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after.as_secs_f64().ceil() as u64));
+        }
+    }
+}
+
+/// Where the buckets are kept, the in-memory store only works for a single server
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    async fn take(&self, key: &str, rule: RateLimitRule) -> Decision;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant
+}
+
+impl Bucket {
+    fn refill(&mut self, rule: RateLimitRule, now: Instant) {
+        let rate = rule.requests_per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rule.burst as f64);
+        self.updated = now;
+    }
+
+    fn is_full(&self, rule: RateLimitRule, now: Instant) -> bool {
+        let mut bucket = *self;
+        bucket.refill(rule, now);
+        bucket.tokens >= rule.burst as f64
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, (Bucket, RateLimitRule)>>
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    pub fn take_at(&self, key: &str, rule: RateLimitRule, now: Instant) -> Decision {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if buckets.len() > PRUNE_ABOVE {
+            buckets.retain(|_, (bucket, rule)| !bucket.is_full(*rule, now));
+        }
+
+        let (bucket, bucket_rule) = buckets.entry(key.to_string())
+            .or_insert((Bucket { tokens: rule.burst as f64, updated: now }, rule));
+        *bucket_rule = rule;
+        bucket.refill(rule, now);
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        let rate = rule.requests_per_minute as f64 / 60.0;
+        Decision {
+            allowed,
+            limit: rule.burst,
+            remaining: bucket.tokens.floor() as u32,
+            reset: Duration::from_secs_f64((rule.burst as f64 - bucket.tokens) / rate),
+            retry_after: (!allowed).then(|| Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn take(&self, key: &str, rule: RateLimitRule) -> Decision {
+        self.take_at(key, rule, Instant::now())
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    config: RateLimitConfig,
+    db_pool: MySqlPool
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>, config: RateLimitConfig, db_pool: MySqlPool) -> RateLimiter {
+        RateLimiter { store, config, db_pool }
+    }
+
+    // Bad tokens are counted against the address, the handler rejects them anyway
+    async fn user_id(&self, headers: &HeaderMap) -> Option<Uuid> {
+        let token = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+        let token = token.strip_prefix("Bearer ").unwrap_or(token);
+
+        if let (Some(keys), true) = (jwt::keys(), token.contains('.')) {
+            return keys.verify(token).ok().map(|claims| claims.sub);
+        }
+
+        let token = Uuid::parse_str(token).ok()?;
+        match Session::find_user_id(token, &self.db_pool).await {
+            Ok(user_id) => user_id,
+            Err(e) => {
+                error!("Couldn't look up session for rate limiting: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn identity(&self, group: RouteGroup, headers: &HeaderMap, ip: IpAddr) -> Identity {
+        if group == RouteGroup::Auth {
+            return Identity::Ip(ip);
+        }
+
+        match self.user_id(headers).await {
+            Some(user_id) => Identity::User(user_id),
+            None => Identity::Ip(ip)
+        }
+    }
+}
+
+/// Takes a request from the bucket of its route group and identity, sets the RateLimit headers on the response
+pub async fn limit(
+    State(limiter): State<RateLimiter>,
+    ConnectInfo(address): ConnectInfo<std::net::SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !limiter.config.enabled {
+        return next.run(request).await;
+    }
+
+    let group = RouteGroup::of(request.method(), request.uri().path());
+    let identity = limiter.identity(group, request.headers(), address.ip()).await;
+    let decision = limiter.store.take(&format!("{}:{}", group.as_str(), identity), group.rule(&limiter.config)).await;
+
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        warn!("Rate limit of {} exceeded by {}", group.as_str(), identity);
+        StatusCode::TOO_MANY_REQUESTS.into_response()
+    };
+
+    decision.apply(response.headers_mut());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULE: RateLimitRule = RateLimitRule { requests_per_minute: 60, burst: 3 };
+
+    #[test]
+    fn test_bucket() {
+        let store = MemoryStore::new();
+        let start = Instant::now();
+
+        for remaining in [2, 1, 0] {
+            let decision = store.take_at("a", RULE, start);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+
+        let denied = store.take_at("a", RULE, start);
+        assert!(!denied.allowed);
+        assert_eq!(denied.limit, 3);
+        assert_eq!(denied.retry_after, Some(Duration::from_secs(1)));
+        assert_eq!(denied.reset, Duration::from_secs(3));
+
+        // Other keys have their own buckets
+        assert!(store.take_at("b", RULE, start).allowed);
+
+        // One request a second comes back, never more than the burst
+        assert!(store.take_at("a", RULE, start + Duration::from_secs(1)).allowed);
+        assert!(!store.take_at("a", RULE, start + Duration::from_secs(1)).allowed);
+        assert_eq!(store.take_at("a", RULE, start + Duration::from_secs(60)).remaining, 2);
+    }
+
+    #[test]
+    fn test_route_groups() {
+        assert_eq!(RouteGroup::of(&Method::POST, "/user/login"), RouteGroup::Auth);
+        assert_eq!(RouteGroup::of(&Method::POST, "/user/login/2fa"), RouteGroup::Auth);
+        assert_eq!(RouteGroup::of(&Method::GET, "/user/oidc/school"), RouteGroup::Auth);
+        assert_eq!(RouteGroup::of(&Method::POST, "/answer"), RouteGroup::Answers);
+        assert_eq!(RouteGroup::of(&Method::GET, "/answer/1234"), RouteGroup::Default);
+        assert_eq!(RouteGroup::of(&Method::GET, "/task/random"), RouteGroup::Tasks);
+        assert_eq!(RouteGroup::of(&Method::GET, "/user/sessions"), RouteGroup::Default);
+
+        let config = RateLimitConfig::default();
+        assert_eq!(RouteGroup::Auth.rule(&config), config.auth);
+        assert_eq!(RouteGroup::Default.rule(&config).burst, config.burst);
+    }
+
+    #[tokio::test]
+    async fn test_middleware() {
+        use axum::{routing::get, Router, body::Body};
+        use tower::ServiceExt;
+
+        let config = RateLimitConfig { auth: RateLimitRule { requests_per_minute: 1, burst: 2 }, ..Default::default() };
+        // Never connected, requests without a session token don't touch the database
+        let db_pool = MySqlPool::connect_lazy("mysql://localhost/unused").unwrap();
+        let limiter = RateLimiter::new(Arc::new(MemoryStore::new()), config, db_pool);
+
+        let app = Router::new()
+            .route("/user/login", get(|| async { "ok" }))
+            .route("/task/random", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(limiter, limit));
+
+        let request = |path: &str, ip: [u8; 4]| {
+            let mut request = Request::builder().uri(path).body(Body::empty()).unwrap();
+            request.extensions_mut().insert(ConnectInfo(std::net::SocketAddr::from((ip, 1234))));
+            request
+        };
+
+        for remaining in ["1", "0"] {
+            let response = app.clone().oneshot(request("/user/login", [10, 0, 0, 1])).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[&REMAINING_HEADER], remaining);
+            assert_eq!(response.headers()[&LIMIT_HEADER], "2");
+        }
+
+        let response = app.clone().oneshot(request("/user/login", [10, 0, 0, 1])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+
+        // Another address, and another group for the same one, aren't affected
+        let response = app.clone().oneshot(request("/user/login", [10, 0, 0, 2])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(request("/task/random", [10, 0, 0, 1])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[&LIMIT_HEADER], "20");
+    }
+}
@@ -2,11 +2,26 @@
 
 use crate::models::user::*;
 use crate::models::task::*;
+use crate::models::session::{ClientInfo, Session, SessionTokens};
+use crate::models::two_factor::LoginStep;
+use crate::models::audit::{AuditAction, AuditEntry};
+use crate::jwt::{self, Claims, JwtError};
+use crate::events::{EventBus, subscribers};
+use crate::notifications::{NotificationHub, NotificationSubscriber};
+use crate::grading::GradingQueue;
+use crate::config::Config;
+use crate::notifier::{Notifier, Notifiers};
+use crate::oidc::OidcClient;
+use crate::rate_limit::{self, MemoryStore, RateLimiter};
+use crate::storage;
+use crate::password::PasswordPolicy;
+use crate::avatar::Avatars;
 use axum::http::HeaderValue;
 use axum::{
-    extract::{Path, State},
+    extract::{ConnectInfo, DefaultBodyLimit, Path, State},
     http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
+    routing::delete,
     routing::get,
     routing::post,
     routing::put,
@@ -15,37 +30,99 @@ use axum::{
 use sqlx::MySql;
 use sqlx::MySqlPool;
 use sqlx::Transaction;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 use uuid::Uuid;
 use tracing::{info, warn, error};
 use tracing::span;
 use tower_http::cors::CorsLayer;
 use http::Method;
 
-const DEFAULT_IP_ADDRESS: &str = "127.0.0.1";
-const DEFAULT_PORT: u32 = 8080;
+const LEAGUE_ROLLOVER_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const REFRESH_TOKEN_HEADER: &str = "x-refresh-token";
+// Boundaries and part headers around the uploaded file
+const MULTIPART_OVERHEAD: usize = 16 * 1024;
 
+// Cloned for every request, the pool is reference counted so clones share the same connections
 #[derive(Clone)]
 struct AppState {
-    db_pool: Arc<Mutex<MySqlPool>>,
+    db_pool: MySqlPool,
+    notifications: NotificationHub,
+    notifier: Arc<dyn Notifier>,
+    oidc: Arc<OidcClient>,
+    password_policy: Arc<PasswordPolicy>,
+    avatars: Arc<Avatars>,
+    config: Arc<Config>,
 }
 
 
-pub async fn start(db_pool: Arc<Mutex<MySqlPool>>, ip_address: Option<&str>, port: Option<u32>) -> Result<(), std::io::Error> {
+pub async fn start(db_pool: MySqlPool, config: Config) -> Result<(), std::io::Error> {
+    let listener = tokio::net::TcpListener::bind(format!("{}:{}", config.server.ip_address, config.server.port))
+        .await?;
+
+    info!("Server started on {}:{}", config.server.ip_address, config.server.port);
+
+    serve(listener, db_pool, config).await
+}
+
+/// Serves the API on an already bound listener
+async fn serve(listener: tokio::net::TcpListener, db_pool: MySqlPool, config: Config) -> Result<(), std::io::Error> {
     
-    let origin = match "http://localhost:3000".parse::<HeaderValue>() {
-        Ok(origin) => origin,
-        Err(e) => {
-            error!("Error parsing origin: {}", e);
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid origin"));
+    let mut origins = Vec::new();
+    for origin in &config.server.cors_origins {
+        match origin.parse::<HeaderValue>() {
+            Ok(origin) => origins.push(origin),
+            Err(e) => {
+                error!("Error parsing origin: {}", e);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid origin"));
+            }
         }
-    };
+    }
     
     let cors_layer = CorsLayer::very_permissive()
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
-        .allow_origin(origin);
+        .expose_headers([
+            header::AUTHORIZATION,
+            header::RETRY_AFTER,
+            header::HeaderName::from_static(REFRESH_TOKEN_HEADER),
+            rate_limit::LIMIT_HEADER,
+            rate_limit::REMAINING_HEADER,
+            rate_limit::RESET_HEADER,
+        ])
+        .allow_origin(origins);
+    
+    let notifications = NotificationHub::new();
+    
+    let notifier = match Notifiers::from_config(&config.notifier) {
+        Ok(notifier) => notifier,
+        Err(e) => {
+            error!("Error setting up notifier: {:?}", e);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid notifier configuration"));
+        }
+    };
+    
+    let event_bus = EventBus::new()
+        .subscribe(subscribers::ProgressSubscriber)
+        .subscribe(subscribers::AchievementSubscriber)
+        .subscribe(NotificationSubscriber::new(notifications.clone()));
+    tokio::spawn(event_bus.run(db_pool.clone()));
+    tokio::spawn(leaderboard::run_league_rollover(db_pool.clone()));
+    tokio::spawn(session::run_purge(db_pool.clone(), config.session.purge_interval()));
+    
+    let oidc = Arc::new(OidcClient::new(&config.oidc));
+    
+    let password_policy = Arc::new(PasswordPolicy::new(&config.password_policy));
+    
+    let avatars = Arc::new(Avatars::new(storage::from_config(&config.avatars), &config.avatars));
+    tokio::spawn(account::run_purge(db_pool.clone(), config.account_deletion.clone(), avatars.clone()));
+    
+    let rate_limiter = RateLimiter::new(Arc::new(MemoryStore::new()), config.rate_limit.clone(), db_pool.clone());
+    
+    let grading_queue = GradingQueue::new(config.verifier.clone());
+    tokio::spawn(grading_queue.run(db_pool.clone()));
     
     let app = Router::new()
         .route("/test", get(test))
@@ -54,7 +131,45 @@ pub async fn start(db_pool: Arc<Mutex<MySqlPool>>, ip_address: Option<&str>, por
         .route("/user/login", post(user::login))
         .route("/user/register", post(user::register))
         .route("/user/logout", post(user::logout))
+        .route("/user/sessions", get(session::list).delete(session::revoke_others))
+        .route("/user/sessions/refresh", post(session::refresh))
+        .route("/user/sessions/:id", delete(session::revoke))
+        .route("/user/password/forgot", post(password_reset::forgot))
+        .route("/user/password/reset", post(password_reset::reset))
+        .route("/user/password", put(password_reset::change))
+        .route("/user/verify", post(verification::verify))
+        .route("/user/verify/resend", post(verification::resend))
+        .route("/user/login/2fa", post(two_factor::login))
+        .route("/user/unlock", post(user::unlock))
+        .route("/user/audit", get(user::get_audit))
+        .route("/user/2fa", delete(two_factor::disable))
+        .route("/user/2fa/enroll", post(two_factor::enroll))
+        .route("/user/2fa/confirm", post(two_factor::confirm))
+        .route("/user/2fa/recovery-codes", post(two_factor::regenerate_recovery_codes))
+        .route("/user/oidc", get(oidc::providers))
+        .route("/user/oidc/:provider", get(oidc::start))
+        .route("/user/oidc/:provider/callback", post(oidc::callback))
+        .route("/user/identities", get(oidc::identities))
+        .route("/user/privacy", get(profile::get_privacy).put(profile::put_privacy))
         .route("/user/:id", get(user::get).delete(user::delete))
+        .route("/user/:id/friends", get(friend::get_friends))
+        .route("/user/:id/achievements", get(user::get_achievements))
+        .route("/user/:id/export", get(account::export))
+        .route("/user/:id/profile", get(profile::get_by_id))
+        .route("/user/:id/avatar", put(avatar::put).delete(avatar::delete)
+            .layer(DefaultBodyLimit::max(config.avatars.max_upload_bytes() + MULTIPART_OVERHEAD)))
+        .route("/avatars/*key", get(avatar::serve))
+        .route("/profile/:username", get(profile::get_by_username))
+        
+        .route("/friends/:id", delete(friend::unfriend))
+        .route("/friends/requests", get(friend::get_requests).post(friend::send_request))
+        .route("/friends/suggestions", get(discovery::suggest_friends))
+        .route("/friends/requests/:id", delete(friend::cancel_request))
+        .route("/friends/requests/:id/accept", post(friend::accept_request))
+        .route("/friends/requests/:id/decline", post(friend::decline_request))
+        .route("/blocks", get(friend::get_blocked))
+        .route("/users/search", get(discovery::search))
+        .route("/blocks/:id", post(friend::block).delete(friend::unblock))
         
         .route("/task/:id", get(task::get))
         .route("/task/random", get(task::get_random))
@@ -62,22 +177,44 @@ pub async fn start(db_pool: Arc<Mutex<MySqlPool>>, ip_address: Option<&str>, por
         
         .route("/answer", post(answer::post).put(answer::put).delete(answer::delete))
         .route("/answer/:id", get(answer::get))
-        .with_state(AppState { db_pool })
+        .route("/answer/:id/result", get(answer::get_result))
+        
+        .route("/classrooms", get(classroom::get_all).post(classroom::create))
+        .route("/classrooms/join", post(classroom::join))
+        .route("/classrooms/:id", get(classroom::get).delete(classroom::delete))
+        .route("/classrooms/:id/invite-code", post(classroom::regenerate_invite_code))
+        .route("/classrooms/:id/students/:user_id", delete(classroom::remove_student))
+        .route("/classrooms/:id/assignments", get(classroom::get_assignments).post(classroom::create_assignment))
+        .route("/classrooms/:id/assignments/:assignment_id", delete(classroom::delete_assignment))
+        .route("/classrooms/:id/assignments/:assignment_id/gradebook", get(classroom::get_gradebook))
+        .route("/classrooms/:id/assignments/:assignment_id/grade", get(classroom::get_grade))
+        .route("/classrooms/:id/roster", get(classroom::get_roster))
+        
+        .route("/leaderboard/global", get(leaderboard::get_global))
+        .route("/leaderboard/friends", get(leaderboard::get_friends))
+        .route("/leaderboard/league", get(leaderboard::get_league))
+        
+        .route("/notifications", get(notification::stream))
+        .route("/notifications/ticket", post(notification::create_ticket))
+        .with_state(AppState { db_pool, notifications, notifier, oidc, password_policy, avatars, config: Arc::new(config.clone()) })
+        .layer(axum::middleware::from_fn_with_state(rate_limiter, rate_limit::limit))
         .layer(cors_layer);
-    
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", ip_address.unwrap_or(DEFAULT_IP_ADDRESS), port.unwrap_or(DEFAULT_PORT)))
-        .await?;
-
-    info!("Server started on {}:{}", ip_address.unwrap_or(DEFAULT_IP_ADDRESS), port.unwrap_or(DEFAULT_PORT));
 
-    axum::serve(listener, app)
+    // The client address is recorded with new sessions
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;  
 
     Ok(())
 }
 
+/// What the AUTH header turned out to carry
+enum Credential {
+    Session(Uuid), // Auth token of a session in the database
+    Jwt(Claims) // Already verified, no database needed
+}
+
 // Check if the request comes with a valid auth token
-async fn validate_token(headers: HeaderMap, tx : &mut Transaction<'static, MySql>) -> Result<Uuid, impl IntoResponse> {
+async fn validate_token(headers: HeaderMap, tx : &mut Transaction<'static, MySql>) -> Result<Credential, impl IntoResponse> {
     let header_value = match headers.get(header::AUTHORIZATION) {
         Some(auth) => auth,
         None => {
@@ -86,16 +223,33 @@ async fn validate_token(headers: HeaderMap, tx : &mut Transaction<'static, MySql
         }
     };
     
-    let token = match header_value.to_str() {
-        Ok(token_str) => match Uuid::parse_str(token_str) {
-            Ok(id) => id,
+    let token_str = match header_value.to_str() {
+        Ok(token_str) => token_str.strip_prefix("Bearer ").unwrap_or(token_str),
+        Err(e) => {
+            warn!("Couldn't parse AUTH header as string\nError: {}", e);
+            return Err(StatusCode::BAD_REQUEST.into_response());
+        }
+    };
+    
+    // UUIDs have no dots, so anything with one is treated as a JWT
+    if let (Some(keys), true) = (jwt::keys(), token_str.contains('.')) {
+        return match keys.verify(token_str) {
+            Ok(claims) => Ok(Credential::Jwt(claims)),
+            Err(JwtError::Expired) => {
+                warn!("Access token expired");
+                Err(StatusCode::FORBIDDEN.into_response())
+            },
             Err(e) => {
-                warn!("Couldn't parse AUTH header as UUID\nError: {}", e);
-                return Err(StatusCode::BAD_REQUEST.into_response());
+                warn!("Invalid access token: {:?}", e);
+                Err(StatusCode::UNAUTHORIZED.into_response())
             }
-        },
+        };
+    }
+    
+    let token = match Uuid::parse_str(token_str) {
+        Ok(id) => id,
         Err(e) => {
-            warn!("Couldn't parse AUTH header as string\nError: {}", e);
+            warn!("Couldn't parse AUTH header as UUID\nError: {}", e);
             return Err(StatusCode::BAD_REQUEST.into_response());
         }
     };
@@ -131,13 +285,47 @@ async fn validate_token(headers: HeaderMap, tx : &mut Transaction<'static, MySql
         }
     };
     
-    Ok(token)
+    if let Err(e) = Session::touch(token, tx).await {
+        error!("Couldn't extend session!\nError: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    }
+    
+    Ok(Credential::Session(token))
+}
+
+// Check if the request comes with a valid auth token and return the id of the user it belongs to
+async fn get_authorized_user_id(headers: HeaderMap, tx : &mut Transaction<'static, MySql>) -> Result<Uuid, axum::response::Response> {
+    let token = match validate_token(headers, tx).await {
+        Ok(Credential::Session(token)) => token,
+        Ok(Credential::Jwt(claims)) => return Ok(claims.sub),
+        Err(e) => return Err(e.into_response())
+    };
+    
+    match User::get_id_by_token(token, tx).await {
+        Ok(Some(user_id)) => Ok(user_id),
+        Ok(None) => {
+            warn!("Token not in database.");
+            Err(StatusCode::UNAUTHORIZED.into_response())
+        },
+        Err(e) => {
+            error!("Couldn't get user id by token!\nError: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
 }
 
 pub async fn check_authorization(headers: HeaderMap, user_id : &Uuid, tx : &mut Transaction<'static, MySql>) -> Result<(), impl IntoResponse> {
 //! Check if the request comes with a valid auth token and the user id is the same as the one in the token
     let token = match validate_token(headers.clone(), tx).await {
-        Ok(token) => token,
+        Ok(Credential::Session(token)) => token,
+        Ok(Credential::Jwt(claims)) if claims.sub == *user_id => {
+            info!("Authorization of user {} successful", user_id);
+            return Ok(());
+        },
+        Ok(Credential::Jwt(_)) => {
+            warn!("Unauthorized attempt on user {}!", user_id);
+            return Err(StatusCode::FORBIDDEN.into_response());
+        },
         Err(e) => {
             return Err(e.into_response());
         }
@@ -148,15 +336,15 @@ pub async fn check_authorization(headers: HeaderMap, user_id : &Uuid, tx : &mut
             if let Err(e) = result {
                 match e {
                     AuthorizationError::NotAuthorized => {
-                        warn!("Unauthorized attempt!\nToken: {}", token);
+                        warn!("Unauthorized attempt on user {}!", user_id);
                         Err(StatusCode::FORBIDDEN.into_response())
                     },
                     AuthorizationError::TokenExpired => {
-                        warn!("Token {} expired.", token);
+                        warn!("Token expired.");
                         Err(StatusCode::FORBIDDEN.into_response())
                     },
                     AuthorizationError::TokenNotInDatabse => {
-                        warn!("Token {} not in database.", token);
+                        warn!("Token not in database.");
                         Err(StatusCode::UNAUTHORIZED.into_response())
                     },
                     _ => {
@@ -165,7 +353,7 @@ pub async fn check_authorization(headers: HeaderMap, user_id : &Uuid, tx : &mut
                     }
                 }
             } else {
-                info!("Authorization successful - token: {}", token);
+                info!("Authorization of user {} successful", user_id);
                 Ok(())
             }
         },
@@ -176,12 +364,103 @@ pub async fn check_authorization(headers: HeaderMap, user_id : &Uuid, tx : &mut
     }
 }
 
+// For endpoints that also work without logging in, `None` if there's no auth header at all
+async fn get_optional_user_id(headers: HeaderMap, tx : &mut Transaction<'static, MySql>) -> Result<Option<Uuid>, axum::response::Response> {
+    if !headers.contains_key(header::AUTHORIZATION) {
+        return Ok(None);
+    }
+    get_authorized_user_id(headers, tx).await.map(Some)
+}
+
+fn client_info(headers: &HeaderMap, address: SocketAddr) -> ClientInfo {
+    ClientInfo {
+        user_agent: headers.get(header::USER_AGENT).and_then(|agent| agent.to_str().ok()).map(String::from),
+        ip_address: Some(address.ip().to_string()),
+    }
+}
+
+// In JWT mode the session's own auth token is never handed out, a signed token for the session is
+fn access_token(user_id: Uuid, tokens: &SessionTokens) -> String {
+    match jwt::keys() {
+        Some(keys) => keys.issue(user_id, tokens.session_id),
+        None => tokens.auth_token.to_string()
+    }
+}
+
+// Shared end of the ways to log in
+async fn login_response(user_id: Uuid, tokens: SessionTokens, tx: Transaction<'static, MySql>) -> axum::response::Response {
+    let access_token = access_token(user_id, &tokens);
+    
+    if let Err(e) = tx.commit().await {
+        error!("Couldn't commit transaction!\nError: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    
+    session_response(user_id, access_token, tokens)
+}
+
+// Same shape for logging in and refreshing, the user id in the body and the tokens in the headers
+fn session_response(user_id: Uuid, access_token: String, tokens: SessionTokens) -> axum::response::Response {
+    axum::http::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::AUTHORIZATION, access_token)
+        .header(REFRESH_TOKEN_HEADER, tokens.refresh_token)
+        .body(user_id.to_string().into())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
 async fn get_transaction(state: AppState) -> Result<Transaction<'static, MySql>, impl IntoResponse> {
-    state.db_pool.lock().await
+    state.db_pool
         .begin().await
         .map_err(|e| {error!("Couldn't get transaction!\nError: {}", e); StatusCode::INTERNAL_SERVER_ERROR.into_response()})
 }
 
+fn too_many_requests(wait: chrono::Duration) -> axum::response::Response {
+    axum::http::Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::RETRY_AFTER, (wait.num_seconds() + 1).to_string())
+        .body(axum::body::Body::empty())
+        .unwrap_or(StatusCode::TOO_MANY_REQUESTS.into_response())
+}
+
+/// `{"field": ["what's wrong with it", ...]}` for forms the user fills in
+fn field_errors(errors: BTreeMap<&'static str, Vec<String>>) -> axum::response::Response {
+    json_response(StatusCode::BAD_REQUEST, &errors)
+}
+
+// Policy problems as field errors, `None` if the password is fine
+async fn check_password(policy: &PasswordPolicy, username: Option<&str>, password: &str) -> Option<axum::response::Response> {
+    match policy.check(username, password).await {
+        Ok(problems) if problems.is_empty() => None,
+        Ok(problems) => {
+            warn!("Password rejected: {:?}", problems);
+            Some(field_errors(BTreeMap::from([("password", problems.iter().map(|problem| problem.to_string()).collect())])))
+        },
+        Err(e) => {
+            error!("Couldn't check the password against the breach list: {}", e);
+            Some(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+fn json_response<T: serde::Serialize>(status: StatusCode, value: &T) -> axum::response::Response {
+    let json = match serde_json::to_string(value) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Couldn't serialize response: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    
+    axum::http::Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(json.into())
+        .unwrap_or_else(|e| {
+            error!("Couldn't build response: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })
+}
 
 async fn test() -> impl IntoResponse {
     StatusCode::OK
@@ -189,6 +468,12 @@ async fn test() -> impl IntoResponse {
 
 mod user {
     use super::*;
+    use crate::config::LockoutConfig;
+    use crate::models::verification::Channel;
+    use crate::models::lockout::Throttle;
+    use crate::notifier::{Message, Recipient};
+    
+    const AUDIT_LOG_LENGTH: u32 = 100;
     
     #[derive(serde::Deserialize, Debug)]
     pub struct LoginForm {
@@ -196,35 +481,122 @@ mod user {
         password: String,
     }
     
-    pub async fn login(State(state): State<AppState>, Json(form): Json<LoginForm>) -> impl IntoResponse {
+    pub async fn login(
+        headers: HeaderMap,
+        ConnectInfo(address): ConnectInfo<SocketAddr>,
+        State(state): State<AppState>,
+        Json(form): Json<LoginForm>,
+    ) -> impl IntoResponse {
         let span = span!(tracing::Level::INFO, "login");
         let _enter = span.enter();
         
+        let session_config = state.config.session.clone();
+        let two_factor_config = state.config.two_factor.clone();
+        let lockout_config = state.config.lockout.clone();
+        let notifier = state.notifier.clone();
+        let client = client_info(&headers, address);
+        let ip = client.ip_address.clone();
+        
         let mut tx = match get_transaction(state).await {
             Ok(tx) => tx,
             Err(e) => return e.into_response(),
         };
         
-        match User::login(form.username.clone(), form.password, &mut tx).await {
-            Ok(user) => {
+        // Failures are counted per address and per account, if there's one with that name
+        let account = match User::read_id_by_username(&form.username, &mut tx).await {
+            Ok(account) => account,
+            Err(e) => {
+                error!("Couldn't look up user {}: {}", &form.username, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        let mut throttles: Vec<Throttle> = ip.iter().map(|ip| Throttle::Ip(ip.clone())).collect();
+        throttles.extend(account.map(Throttle::Account));
+        
+        let mut wait = None;
+        for throttle in &throttles {
+            match throttle.wait(&lockout_config, &mut tx).await {
+                Ok(throttle_wait) => wait = wait.max(throttle_wait),
+                Err(e) => {
+                    error!("Couldn't check failed logins: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+        }
+        
+        if let Some(wait) = wait {
+            warn!("Login attempt for user {} throttled for {}s", &form.username, wait.num_seconds());
+            if let Err(e) = AuditEntry::record(account, AuditAction::LoginThrottled, ip.as_deref(), None, &mut tx).await {
+                error!("Couldn't record audit entry: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            if let Err(e) = tx.commit().await {
+                error!("Couldn't commit transaction!\nError: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            return too_many_requests(wait);
+        }
+        
+        let result = User::login(form.username.clone(), form.password, &client, &session_config, &two_factor_config, &mut tx).await;
+        
+        // Only a session resets the count, a right password alone still leaves the second factor to guess
+        if let (Ok(LoginStep::Done(..)), Some(account)) = (&result, account) {
+            if let Err(e) = Throttle::Account(account).clear(&mut tx).await {
+                error!("Couldn't clear failed logins of user {}: {}", account, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+        
+        if matches!(result, Err(UserError::BadCredentials) | Err(UserError::NoSuchUser)) {
+            let unlock_message = match record_failed_login(&throttles, account, ip.as_deref(), &lockout_config, &mut tx).await {
+                Ok(unlock_message) => unlock_message,
+                Err(e) => {
+                    error!("Couldn't record failed login: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+            
+            if let Err(e) = tx.commit().await {
+                error!("Couldn't commit transaction!\nError: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            
+            if let Some((recipient, message)) = unlock_message {
+                tokio::spawn(async move {
+                    if let Err(e) = notifier.send(&recipient, &message).await {
+                        error!("Couldn't deliver unlock link to {:?}: {:?}", recipient, e);
+                    }
+                });
+            }
+            
+            // The transaction is gone, the answer is the same as without the failure tracking
+            return match result {
+                Err(UserError::BadCredentials) => {
+                    warn!("Unsuccessful login attempt for user {}, bad credentials", &form.username);
+                    StatusCode::FORBIDDEN.into_response()
+                },
+                _ => {
+                    warn!("Unsuccessful login attempt for user {}, no such user", &form.username);
+                    StatusCode::NOT_FOUND.into_response()
+                }
+            };
+        }
+        
+        match result {
+            Ok(LoginStep::Done(user, tokens)) => {
+                if let Err(e) = AuditEntry::record(Some(user.id), AuditAction::LoginSucceeded, ip.as_deref(), None, &mut tx).await {
+                    error!("Couldn't record audit entry: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                login_response(user.id, tokens, tx).await
+            },
+            Ok(LoginStep::TwoFactor(challenge)) => {
                 if let Err(e) = tx.commit().await {
                     error!("Couldn't commit transaction!\nError: {}", e);
                     return StatusCode::INTERNAL_SERVER_ERROR.into_response();
                 }
                 
-                let auth_token = match user.auth_token {
-                    Some(token) => token,
-                    None => {
-                        error!("No auth token in user! THIS SHOULDN'T HAPPEN!");
-                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                    }
-                };
-                
-                axum::http::Response::builder()
-                    .status(StatusCode::OK)
-                    .header(header::AUTHORIZATION, auth_token.to_string())
-                    .body(user.id.to_string().into())
-                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+                json_response(StatusCode::ACCEPTED, &challenge)
             },
             
             Err(e) => match e {
@@ -264,6 +636,119 @@ mod user {
         }
     }
     
+    // Returns the unlock link to send if this failure locked the account
+    pub async fn record_failed_login(throttles: &[Throttle], account: Option<Uuid>, ip: Option<&str>, config: &LockoutConfig, tx: &mut Transaction<'static, MySql>) -> Result<Option<(Recipient, Message)>, sqlx::Error> {
+        let mut locked = false;
+        for throttle in throttles {
+            locked |= throttle.record_failure(config, tx).await?;
+        }
+        AuditEntry::record(account, AuditAction::LoginFailed, ip, None, tx).await?;
+        
+        let user_id = match account {
+            Some(user_id) if locked => user_id,
+            _ => return Ok(None)
+        };
+        AuditEntry::record(Some(user_id), AuditAction::AccountLocked, ip, None, tx).await?;
+        
+        let user = User::read(user_id, tx).await?;
+        let recipient = match (user.email, user.phone) {
+            (Some(email), _) => Recipient::Email(email),
+            (None, Some(phone)) => Recipient::Phone(phone),
+            (None, None) => return Ok(None)
+        };
+        
+        let token = User::create_unlock_token(user_id, config, tx).await?;
+        let message = Message {
+            subject: "Your code samurai account was locked".to_string(),
+            body: format!(
+                "Hi {}!\n\nThere were too many failed logins to your account, so it's locked for {} minutes.\nIf it was you, use this link to unlock it right away:\n{}{}\n\nIf it wasn't, someone may be guessing your password, consider changing it.",
+                user.username, config.lock_minutes, config.unlock_link, token
+            ),
+        };
+        
+        Ok(Some((recipient, message)))
+    }
+    
+    #[derive(serde::Deserialize, Debug)]
+    pub struct UnlockForm {
+        pub token: String,
+    }
+    
+    pub async fn unlock(
+        headers: HeaderMap,
+        ConnectInfo(address): ConnectInfo<SocketAddr>,
+        State(state): State<AppState>,
+        Json(form): Json<UnlockForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "user unlock");
+        let _enter = span.enter();
+        
+        let client = client_info(&headers, address);
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match User::unlock(&form.token, &mut tx).await {
+            Ok(Some(user_id)) => user_id,
+            Ok(None) => {
+                warn!("Invalid or expired unlock token");
+                return StatusCode::BAD_REQUEST.into_response();
+            },
+            Err(e) => {
+                error!("Couldn't unlock account: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = AuditEntry::record(Some(user_id), AuditAction::AccountUnlocked, client.ip_address.as_deref(), None, &mut tx).await {
+            error!("Couldn't record audit entry: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        StatusCode::NO_CONTENT.into_response()
+    }
+    
+    // The user's own security log
+    pub async fn get_audit(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "user audit");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let entries = match AuditEntry::read_for_user(user_id, AUDIT_LOG_LENGTH, &mut tx).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Couldn't read audit log of user {}: {}", user_id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        json_response(StatusCode::OK, &entries)
+    }
+    
     #[derive(serde::Deserialize, Debug)]
     pub struct RegisterForm {
         username: String,
@@ -279,6 +764,37 @@ mod user {
         let span = span!(tracing::Level::INFO, "register");
         let _enter = span.enter();
         
+        let notifier = state.notifier.clone();
+        let verification_config = state.config.verification.clone();
+        let password_policy = state.password_policy.clone();
+        
+        let mut errors: BTreeMap<&'static str, Vec<String>> = BTreeMap::new();
+        if form.email.is_none() && form.phone.is_none() {
+            errors.entry("email").or_default().push("an email or a phone number is needed".to_string());
+        }
+        if form.email.as_deref().is_some_and(|email| !valid_email(email)) {
+            errors.entry("email").or_default().push("isn't a valid email address".to_string());
+        }
+        if form.phone.as_deref().is_some_and(|phone| !valid_phone(phone)) {
+            errors.entry("phone").or_default().push("isn't a valid phone number".to_string());
+        }
+        match password_policy.check(Some(&form.username), &form.password).await {
+            Ok(problems) => {
+                if !problems.is_empty() {
+                    errors.insert("password", problems.iter().map(|problem| problem.to_string()).collect());
+                }
+            },
+            Err(e) => {
+                error!("Couldn't check the password against the breach list: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+        
+        if !errors.is_empty() {
+            warn!("Registration of {} rejected: {:?}", form.username, errors);
+            return field_errors(errors);
+        }
+        
         let mut tx = match get_transaction(state).await {
             Ok(tx) => tx,
             Err(e) => return e.into_response(),
@@ -290,11 +806,28 @@ mod user {
                     error!("Couldn't create user in database: {}", e);
                     return StatusCode::INTERNAL_SERVER_ERROR.into_response();
                 }
+                
+                let channels = [(Channel::Email, user.email.is_some()), (Channel::Phone, user.phone.is_some())];
+                let mut codes = Vec::new();
+                for (channel, _) in channels.into_iter().filter(|(_, present)| *present) {
+                    match User::create_verification(user.id, channel, &verification_config, &mut tx).await {
+                        Ok(code) => codes.push(code),
+                        Err(e) => {
+                            error!("Couldn't create verification code for user {}: {:?}", user.username, e);
+                            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                        }
+                    }
+                }
+                
                 if let Err(e) = tx.commit().await {
                     error!("Couldn't commit transaction: {}", e);
                     return StatusCode::INTERNAL_SERVER_ERROR.into_response();
                 }
                 
+                for (recipient, code) in codes {
+                    verification::deliver_code(notifier.clone(), recipient, &user.username, code, &verification_config);
+                }
+                
                 info!("User {} registered successfully", user.username);
                 StatusCode::CREATED.into_response()
             }
@@ -352,6 +885,11 @@ mod user {
             return err_response.into_response();
         }
         
+        if user.email.as_deref().is_some_and(|email| !valid_email(email)) || user.phone.as_deref().is_some_and(|phone| !valid_phone(phone)) {
+            warn!("Bad email or phone in update of user {}", &user.username);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        
         match user.update(&mut tx).await {
             Ok(_) => {
                 if let Err(e) = tx.commit().await {
@@ -373,20 +911,28 @@ mod user {
         pub username: String,
         pub email: Option<String>,
         pub phone: Option<String>,
+        #[serde(default)]
+        pub email_verified: bool,
+        #[serde(default)]
+        pub phone_verified: bool,
         pub bio: Option<String>,
-        pub friends: Vec<Uuid>,
+        /// Size to url, set with `PUT /user/:id/avatar` and ignored here
+        #[serde(default, skip_deserializing)]
+        pub avatar: Option<BTreeMap<String, String>>,
         pub level: UserLevel,
         pub progress: UserProgress
     }
     
     impl UserInfo {
-        pub fn from_user(user: User) -> Self {
+        pub fn from_user(user: User, avatars: &Avatars) -> Self {
             Self {
+                avatar: user.avatar.as_deref().map(|version| avatars.urls(user.id, version)),
                 username: user.username,
                 email: user.email,
                 phone: user.phone,
+                email_verified: user.email_verified,
+                phone_verified: user.phone_verified,
                 bio: user.bio,
-                friends: user.friends,
                 level: user.level,
                 progress: user.progress,
             }
@@ -403,9 +949,13 @@ mod user {
                 username: self.username,
                 email: self.email,
                 phone: self.phone,
+                // Verification is only changed by the server, update() drops it for a new contact
+                email_verified: read_user.email_verified,
+                phone_verified: read_user.phone_verified,
                 bio: self.bio,
-                friends: self.friends,
-                level: self.level,
+                avatar: read_user.avatar, // Only changed through the avatar endpoints
+                friends: read_user.friends,
+                level: read_user.level, // XP is only awarded by the server
                 progress: self.progress,
                 auth_token: read_user.auth_token,
             })
@@ -420,14 +970,12 @@ mod user {
         let span = span!(tracing::Level::INFO, "user get");
         let _enter = span.enter();
         
+        let avatars = state.avatars.clone();
+        
         let mut tx = match get_transaction(state).await {
             Ok(tx) => tx,
             Err(e) => return e.into_response(),
         };
-        
-        if let Err(err_response) = validate_token(headers, &mut tx).await {
-            return err_response.into_response();
-        }
     
         let id = match Uuid::parse_str(&id_str) {
             Ok(id) => id,
@@ -436,6 +984,12 @@ mod user {
                 return StatusCode::BAD_REQUEST.into_response();
             }
         };
+        
+        // Email and phone are private, only the owner gets them (others use `/profile/{username}`).
+        // An account waiting to be purged still shows up here, the owner can change their mind
+        if let Err(err_response) = check_authorization(headers, &id, &mut tx).await {
+            return err_response.into_response();
+        }
     
         match User::read(id, &mut tx).await {
             Ok(user) => {
@@ -444,7 +998,7 @@ mod user {
                     return StatusCode::INTERNAL_SERVER_ERROR.into_response();
                 }
                 
-                let json = match serde_json::to_string(&UserInfo::from_user(user)) {
+                let json = match serde_json::to_string(&UserInfo::from_user(user, &avatars)) {
                     Ok(json) => json,
                     Err(e) => {
                         error!("Couldn't serialize user info to JSON: {}", e);
@@ -469,12 +1023,13 @@ mod user {
         }
     }
     
-    pub async fn delete(
+    // Same rules as the achievements on the profile
+    pub async fn get_achievements(
         headers: HeaderMap,
         State(state): State<AppState>,
-        Path(id_str) : Path<String>,
+        Path(id_str): Path<String>,
     ) -> impl IntoResponse {
-        let span = span!(tracing::Level::INFO, "user delete");
+        let span = span!(tracing::Level::INFO, "user achievements get");
         let _enter = span.enter();
         
         let mut tx = match get_transaction(state).await {
@@ -482,26 +1037,81 @@ mod user {
             Err(e) => return e.into_response(),
         };
         
+        let viewer = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(viewer) => viewer,
+            Err(response) => return response,
+        };
+        
         let id = match Uuid::parse_str(&id_str) {
             Ok(id) => id,
-            Err(e) => {
-                warn!("Invalid UUID: {}", e);
+            Err(_) => {
+                warn!("Invalid UUID: {}", id_str);
                 return StatusCode::BAD_REQUEST.into_response();
             }
         };
-    
-        if let Err(err_response) = check_authorization(headers, &id, &mut tx).await {
-            return err_response.into_response();
-        }
         
-        match User::delete(id, &mut tx).await {
-            Ok(_) => {
-                if let Err(e) = tx.commit().await {
-                    error!("Couldn't commit transaction: {}", e);
+        let achievements = match User::read_profile(id, Some(viewer), &mut tx).await {
+            Ok(Some(profile)) => profile.achievements,
+            Ok(None) => {
+                warn!("No profile of user {} for {}", id, viewer);
+                return StatusCode::NOT_FOUND.into_response();
+            },
+            Err(e) => {
+                error!("Couldn't read achievements of user {}: {}", id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            },
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        match achievements {
+            Some(achievements) => json_response(StatusCode::OK, &achievements),
+            None => {
+                warn!("User {} hides their achievements from {}", id, viewer);
+                StatusCode::FORBIDDEN.into_response()
+            }
+        }
+    }
+    
+    pub async fn delete(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str) : Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "user delete");
+        let _enter = span.enter();
+        
+        let deletion_config = state.config.account_deletion.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let id = match Uuid::parse_str(&id_str) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Invalid UUID: {}", e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+    
+        if let Err(err_response) = check_authorization(headers, &id, &mut tx).await {
+            return err_response.into_response();
+        }
+        
+        // Only marked here, the purge job deletes it once the grace period is over
+        match User::request_deletion(id, &deletion_config, &mut tx).await {
+            Ok(purge_time) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
                     return StatusCode::INTERNAL_SERVER_ERROR.into_response();
                 }
-                info!("Successfully deleted user {}", id);
-                StatusCode::NO_CONTENT.into_response()
+                info!("User {} will be deleted at {}", id, purge_time);
+                json_response(StatusCode::ACCEPTED, &serde_json::json!({ "purge_time": purge_time }))
             },
             Err(e) => {
                 error!("Couldn't delete user: {}", e);
@@ -521,12 +1131,14 @@ mod user {
             Err(e) => return e.into_response(),
         };
         
-        let token = match validate_token(headers, &mut tx).await {
-            Ok(token) => token,
+        let result = match validate_token(headers, &mut tx).await {
+            Ok(Credential::Session(token)) => User::logout(token, &mut tx).await,
+            // The access token itself stays valid until it expires, but can't be refreshed anymore
+            Ok(Credential::Jwt(claims)) => Session::revoke(claims.sub, claims.sid, &mut tx).await.map(|_| ()),
             Err(err) => return err.into_response()
         };
         
-        match User::logout(token, &mut tx).await {
+        match result {
             Ok(_) => {
                 if let Err(e) = tx.commit().await {
                     error!("Couldn't commit transaction: {}", e);
@@ -541,62 +1153,58 @@ mod user {
     }
 }
 
-mod task {
-    use reqwest::RequestBuilder;
-
+mod session {
     use super::*;
-   
-    pub async fn get(
-        Path(id_str): Path<String>, 
-        State(state): State<AppState>) -> impl IntoResponse {
-        
-        let span = span!(tracing::Level::INFO, "task get");
-        let _enter = span.enter();
-        
-        let id = match Uuid::parse_str(&id_str) {
-            Ok(id) => id,
-            Err(e) => {
-                warn!("Invalid UUID: {}", e);
-                return StatusCode::BAD_REQUEST.into_response();
-            }
-        };
+    use serde::Deserialize;
+    use crate::models::session::SessionError;
     
-        let mut tx = match get_transaction(state).await {
-            Ok(tx) => tx,
-            Err(e) => return e.into_response(),
-        };
-
-        let task = Task::read(id, &mut tx).await;
-        match task {
-            Ok(task) => {
-                if let Err(e) = tx.commit().await {
-                    error!("Couldn't commit transaction: {}", e);
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    pub async fn run_purge(db_pool: MySqlPool, every: Duration) {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            
+            let mut tx = match db_pool.begin().await {
+                Ok(tx) => tx,
+                Err(e) => {
+                    error!("Couldn't get transaction for session purge!\nError: {}", e);
+                    continue;
                 }
-                info!("Successfully read task {}", id);
-                Json(task).into_response()
-            },
+            };
             
-            Err(e) => match e {
-                sqlx::Error::RowNotFound => {
-                    warn!("Entry {} not found", id);
-                    StatusCode::NOT_FOUND.into_response()
-                },
-                
-                _ => {
-                    error!("Other error!");
-                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            match Session::purge_expired(&mut tx).await {
+                Ok(_) => {
+                    if let Err(e) = tx.commit().await {
+                        error!("Couldn't commit session purge: {}", e);
+                    }
                 },
-            },
+                Err(e) => error!("Session purge failed: {}", e),
+            }
         }
     }
     
-    pub async fn get_random(State(state): State<AppState>) -> impl IntoResponse {
-        use sqlx::query;
-        use rand::seq::SliceRandom;
-        use axum::http::Response;
+    // The session is needed too, to tell which one is the current one
+    pub async fn get_session_and_user_id(headers: HeaderMap, tx: &mut Transaction<'static, MySql>) -> Result<(Uuid, Uuid), axum::response::Response> {
+        let token = match validate_token(headers, tx).await {
+            Ok(Credential::Session(token)) => token,
+            Ok(Credential::Jwt(claims)) => return Ok((claims.sid, claims.sub)),
+            Err(e) => return Err(e.into_response()),
+        };
         
-        let span = span!(tracing::Level::INFO, "task get random");
+        match Session::find(token, tx).await {
+            Ok(Some(ids)) => Ok(ids),
+            Ok(None) => Err(StatusCode::UNAUTHORIZED.into_response()),
+            Err(e) => {
+                error!("Couldn't get session by token!\nError: {}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+        }
+    }
+    
+    pub async fn list(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "session list");
         let _enter = span.enter();
         
         let mut tx = match get_transaction(state).await {
@@ -604,147 +1212,75 @@ mod task {
             Err(e) => return e.into_response(),
         };
         
-        let task_id = match query!("SELECT id FROM tasks")
-            .fetch_all(tx.as_mut()).await {
-                Ok(records) => {
-                    let mut rng = rand::thread_rng();
-                    match records.choose(&mut rng) {
-                        Some(record) => {
-                            match uuid::Uuid::parse_str(&record.id) {
-                                Ok(id) => id,
-                                Err(e) => {
-                                    error!("Couldn't parse UUID: {}", e);
-                                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                                }
-                            }
-                        }
-                        None => {
-                            warn!("No tasks found");
-                            return StatusCode::NOT_FOUND.into_response();
-                        }
-                    }
-                },
-                Err(e) => {
-                    error!("Couldn't get random task: {}", e);
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                }
-            };
+        let (session_id, user_id) = match get_session_and_user_id(headers, &mut tx).await {
+            Ok(ids) => ids,
+            Err(response) => return response,
+        };
         
-        let task = match Task::read(task_id, &mut tx).await {
-            Ok(task) => task,
+        let sessions = match Session::list(user_id, session_id, &mut tx).await {
+            Ok(sessions) => sessions,
             Err(e) => {
-                error!("Couldn't read task: {}", e);
+                error!("Couldn't list sessions of user {}: {}", user_id, e);
                 return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
         };
         
-        let json = serde_json::to_string(&task);
-        
-        if let Err(e) = json {
-            error!("Couldn't serialize task: {}", e);
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
             return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
         
-        match Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-Type", "application/json")
-            .body(json.expect("This result is an Err, THIS SHOULDN'T HAPPEN").into()) {
-                Ok(response) => response,
-                Err(e) => {
-                    error!("Couldn't build response: {}", e);
-                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                }
-            }
+        json_response(StatusCode::OK, &sessions)
     }
     
-    pub async fn get_other_than(
+    pub async fn revoke(
+        headers: HeaderMap,
         State(state): State<AppState>,
-        Json(task_ids): Json<Vec<Uuid>>,
+        Path(id): Path<String>,
     ) -> impl IntoResponse {
-        use sqlx::query;
-        use axum::http::Response;
-        use rand::seq::SliceRandom;
-        
-        let span = span!(tracing::Level::INFO, "task get other than");
+        let span = span!(tracing::Level::INFO, "session revoke");
         let _enter = span.enter();
         
+        let id = match Uuid::parse_str(&id) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Couldn't parse session id: {}", e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        
         let mut tx = match get_transaction(state).await {
             Ok(tx) => tx,
             Err(e) => return e.into_response(),
         };
         
-        let task_id = match query!("SELECT id FROM tasks")
-            .fetch_all(tx.as_mut()).await {
-                Ok(records) => {
-                    let mut rng = rand::thread_rng();
-                    loop {
-                        match records.choose(&mut rng) {
-                            Some(record) => {
-                                match uuid::Uuid::parse_str(&record.id) {
-                                    Ok(id) => {
-                                        if !task_ids.contains(&id) {
-                                            break id;
-                                        }
-                                    },
-                                    Err(e) => {
-                                        error!("Couldn't parse UUID: {}", e);
-                                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                                    }
-                                }
-                            }
-                            None => {
-                                warn!("No tasks found");
-                                return StatusCode::NOT_FOUND.into_response();
-                            }
-                        }
-                    }
-                },
-                Err(e) => {
-                    error!("Couldn't get random task: {}", e);
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        match Session::revoke(user_id, id, &mut tx).await {
+            Ok(true) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
                     return StatusCode::INTERNAL_SERVER_ERROR.into_response();
                 }
-            };
-        
-        let task = match Task::read(task_id, &mut tx).await {
-            Ok(task) => task,
+                info!("User {} revoked session {}", user_id, id);
+                StatusCode::NO_CONTENT.into_response()
+            },
+            Ok(false) => StatusCode::NOT_FOUND.into_response(),
             Err(e) => {
-                error!("Couldn't read task: {}", e);
-                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                error!("Couldn't revoke session {}: {}", id, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
             }
-        };
-        
-        let json = serde_json::to_string(&task);
-        
-        if let Err(e) = json {
-            error!("Couldn't serialize task: {}", e);
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
-        
-        match Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-Type", "application/json")
-            .body(json.expect("This result is an Err, THIS SHOULDN'T HAPPEN").into()) {
-                Ok(response) => response,
-                Err(e) => {
-                    error!("Couldn't build response: {}", e);
-                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                }
-            }
     }
-}
-
-mod answer {
-    use axum::http::Response;
-    use super::*;
-    use serde::Deserialize;
-    use crate::models::answer::*;
     
-    pub async fn get(
-        Path(id_str) : Path<String>, 
-        State(state): State<AppState>,
+    pub async fn revoke_others(
         headers: HeaderMap,
+        State(state): State<AppState>,
     ) -> impl IntoResponse {
-        let span = span!(tracing::Level::INFO, "answer get");
+        let span = span!(tracing::Level::INFO, "session revoke others");
         let _enter = span.enter();
         
         let mut tx = match get_transaction(state).await {
@@ -752,210 +1288,3255 @@ mod answer {
             Err(e) => return e.into_response(),
         };
         
-        if let Err(err_response) = validate_token(headers, &mut tx).await {
-            return err_response.into_response();
-        }
-        
-        let id = match Uuid::parse_str(&id_str) {
-            Ok(id) => id,
-            Err(e) => {
-                warn!("Invalid UUID: {}", e);
-                return StatusCode::BAD_REQUEST.into_response();
-            }
+        let (session_id, user_id) = match get_session_and_user_id(headers, &mut tx).await {
+            Ok(ids) => ids,
+            Err(response) => return response,
         };
         
-        match Answer::read(id, &mut tx).await {
-            Ok(answer) => {
+        match Session::revoke_others(user_id, session_id, &mut tx).await {
+            Ok(revoked) => {
                 if let Err(e) = tx.commit().await {
                     error!("Couldn't commit transaction: {}", e);
                     return StatusCode::INTERNAL_SERVER_ERROR.into_response();
                 }
-                
-                match answer {
-                    Some(answer) => {
-                        info!("Successfully read answer {}", id);
-                        
-                        let serialized = match answer.serialize() {
-                            Ok(serialized) => serialized,
-                            Err(e) => {
-                                error!("Couldn't serialize answer: {}", e);
-                                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                            }
-                        };
-                        
-                        Response::builder()
-                            .status(StatusCode::OK)
-                            .body(serialized.into())
-                            .unwrap_or_else(|_| {
-                                error!("Couldn't build response");
-                                StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                            })
-                    },
-                    None => {
-                        warn!("Answer {} not found", id);
-                        StatusCode::NOT_FOUND.into_response()
-                    },
+                info!("User {} revoked {} other sessions", user_id, revoked);
+                StatusCode::NO_CONTENT.into_response()
+            },
+            Err(e) => {
+                error!("Couldn't revoke sessions of user {}: {}", user_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct RefreshForm {
+        pub refresh_token: String,
+    }
+    
+    // Works with an expired auth token, so it doesn't need one
+    pub async fn refresh(
+        State(state): State<AppState>,
+        Json(form): Json<RefreshForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "session refresh");
+        let _enter = span.enter();
+        
+        let session_config = state.config.session.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let result = Session::refresh(&form.refresh_token, &session_config, &mut tx).await
+            .map(|(user_id, tokens)| (user_id, access_token(user_id, &tokens), tokens));
+        
+        // A reused token revokes its session, that has to be kept as well
+        if matches!(result, Ok(_) | Err(SessionError::RefreshTokenReused)) {
+            if let Err(e) = tx.commit().await {
+                error!("Couldn't commit transaction: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+        
+        match result {
+            Ok((user_id, access_token, tokens)) => session_response(user_id, access_token, tokens),
+            Err(SessionError::InvalidRefreshToken) | Err(SessionError::RefreshTokenReused) => StatusCode::UNAUTHORIZED.into_response(),
+            Err(SessionError::DatabaseError(e)) => {
+                error!("Database error: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+mod account {
+    use super::*;
+    use crate::config::AccountDeletionConfig;
+    
+    pub async fn run_purge(db_pool: MySqlPool, config: AccountDeletionConfig, avatars: Arc<Avatars>) {
+        let mut interval = tokio::time::interval(config.purge_interval());
+        loop {
+            interval.tick().await;
+            
+            let mut tx = match db_pool.begin().await {
+                Ok(tx) => tx,
+                Err(e) => {
+                    error!("Couldn't get transaction for account purge!\nError: {}", e);
+                    continue;
+                }
+            };
+            
+            let purged = match User::purge_deleted(&config, &mut tx).await {
+                Ok(purged) => purged,
+                Err(e) => {
+                    error!("Account purge failed: {}", e);
+                    continue;
+                }
+            };
+            
+            if let Err(e) = tx.commit().await {
+                error!("Couldn't commit account purge: {}", e);
+                continue;
+            }
+            
+            // Avatar urls are cached forever, the files have to go with the account
+            for (id, avatar) in purged {
+                if let Some(version) = avatar {
+                    if let Err(e) = avatars.remove(id, &version).await {
+                        error!("Couldn't remove avatar {} of purged user {}: {:?}", version, id, e);
+                    }
+                }
+            }
+        }
+    }
+    
+    pub async fn export(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "user export");
+        let _enter = span.enter();
+        
+        let id = match Uuid::parse_str(&id_str) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Invalid UUID: {}", e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let (session_id, user_id) = match session::get_session_and_user_id(headers, &mut tx).await {
+            Ok(ids) => ids,
+            Err(response) => return response,
+        };
+        
+        if user_id != id {
+            warn!("User {} tried to export the data of user {}", user_id, id);
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        
+        let export = match User::export(id, session_id, &mut tx).await {
+            Ok(export) => export,
+            Err(e) => {
+                error!("Couldn't export data of user {}: {}", id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        let json = match serde_json::to_string_pretty(&export) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Couldn't serialize export of user {}: {}", id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        info!("User {} exported their data", id);
+        axum::http::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"code-samurai-{}.json\"", id))
+            .body(axum::body::Body::from(json))
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+    }
+}
+
+mod profile {
+    use super::*;
+    use crate::models::profile::PrivacySettings;
+    
+    // Shared by both lookups, the viewer decides which fields are shown
+    async fn profile_response(id: Uuid, headers: HeaderMap, avatars: &Avatars, mut tx: Transaction<'static, MySql>) -> axum::response::Response {
+        let viewer = match get_optional_user_id(headers, &mut tx).await {
+            Ok(viewer) => viewer,
+            Err(response) => return response,
+        };
+        
+        match User::read_profile(id, viewer, &mut tx).await {
+            Ok(Some(mut profile)) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                profile.avatar = profile.avatar_version.as_deref().map(|version| avatars.urls(id, version));
+                json_response(StatusCode::OK, &profile)
+            },
+            Ok(None) => {
+                warn!("No profile of user {} for {:?}", id, viewer);
+                StatusCode::NOT_FOUND.into_response()
+            },
+            Err(e) => {
+                error!("Couldn't read profile of user {}: {}", id, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+    
+    pub async fn get_by_id(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "profile get");
+        let _enter = span.enter();
+        
+        let id = match Uuid::parse_str(&id_str) {
+            Ok(id) => id,
+            Err(_) => {
+                warn!("Invalid UUID: {}", id_str);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        
+        let avatars = state.avatars.clone();
+        
+        let tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        profile_response(id, headers, &avatars, tx).await
+    }
+    
+    pub async fn get_by_username(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(username): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "profile get by username");
+        let _enter = span.enter();
+        
+        let avatars = state.avatars.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        match User::read_id_by_username(&username, &mut tx).await {
+            Ok(Some(id)) => profile_response(id, headers, &avatars, tx).await,
+            Ok(None) => {
+                warn!("No user named {}", username);
+                StatusCode::NOT_FOUND.into_response()
+            },
+            Err(e) => {
+                error!("Couldn't look up user {}: {}", username, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+    
+    pub async fn get_privacy(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "privacy get");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        match User::read_privacy(user_id, &mut tx).await {
+            Ok(settings) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                json_response(StatusCode::OK, &settings)
+            },
+            Err(e) => {
+                error!("Couldn't read privacy settings of user {}: {}", user_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+    
+    pub async fn put_privacy(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(settings): Json<PrivacySettings>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "privacy put");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        if let Err(e) = User::update_privacy(user_id, &settings, &mut tx).await {
+            error!("Couldn't update privacy settings of user {}: {}", user_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        StatusCode::OK.into_response()
+    }
+}
+
+mod avatar {
+    use super::*;
+    use axum::extract::Multipart;
+    use crate::avatar::AvatarError;
+    use crate::storage::StorageError;
+    
+    const FIELD_NAME: &str = "avatar";
+    
+    pub async fn put(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+        mut multipart: Multipart,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "avatar put");
+        let _enter = span.enter();
+        
+        let id = match Uuid::parse_str(&id_str) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Invalid UUID: {}", e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        
+        let avatars = state.avatars.clone();
+        
+        // Only for the authorization, no connection is held while the upload is read, resized and stored
+        let mut tx = match get_transaction(state.clone()).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        if let Err(err_response) = check_authorization(headers, &id, &mut tx).await {
+            return err_response.into_response();
+        }
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        // Other parts are skipped, the client's content type is never trusted
+        let data = loop {
+            match multipart.next_field().await {
+                Ok(Some(field)) if field.name() == Some(FIELD_NAME) => match field.bytes().await {
+                    Ok(data) => break data.to_vec(),
+                    Err(e) => {
+                        warn!("Couldn't read avatar upload: {}", e);
+                        return e.status().into_response();
+                    }
+                },
+                Ok(Some(_)) => continue,
+                Ok(None) => {
+                    warn!("No {} field in avatar upload", FIELD_NAME);
+                    return StatusCode::BAD_REQUEST.into_response();
+                },
+                Err(e) => {
+                    warn!("Invalid multipart body: {}", e);
+                    return e.status().into_response();
+                }
+            }
+        };
+        
+        let version = match avatars.save(id, data).await {
+            Ok(version) => version,
+            Err(AvatarError::TooLarge) => {
+                warn!("Avatar of user {} too large", id);
+                return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+            },
+            Err(AvatarError::UnsupportedFormat) => {
+                warn!("Avatar of user {} isn't png, jpeg, gif or webp", id);
+                return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response();
+            },
+            Err(AvatarError::BadImage(e)) => {
+                warn!("Couldn't decode avatar of user {}: {}", id, e);
+                return StatusCode::BAD_REQUEST.into_response();
+            },
+            Err(AvatarError::StorageError(e)) => {
+                error!("Couldn't store avatar of user {}: {:?}", id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        // The stored images are removed again below if this fails
+        let saved = match state.db_pool.begin().await {
+            Ok(mut tx) => match User::set_avatar(id, Some(&version), &mut tx).await {
+                Ok(previous) => tx.commit().await.map(|_| previous),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        
+        match saved {
+            Ok(previous) => {
+                if let Some(previous) = previous {
+                    if let Err(e) = avatars.remove(id, &previous).await {
+                        error!("Couldn't remove old avatar {} of user {}: {:?}", previous, id, e);
+                    }
                 }
+                info!("User {} changed their avatar", id);
+                json_response(StatusCode::OK, &avatars.urls(id, &version))
+            },
+            Err(e) => {
+                error!("Couldn't save avatar of user {}: {}", id, e);
+                if let Err(e) = avatars.remove(id, &version).await {
+                    error!("Couldn't remove unused avatar {} of user {}: {:?}", version, id, e);
+                }
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+    
+    pub async fn delete(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "avatar delete");
+        let _enter = span.enter();
+        
+        let id = match Uuid::parse_str(&id_str) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Invalid UUID: {}", e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        
+        let avatars = state.avatars.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        if let Err(err_response) = check_authorization(headers, &id, &mut tx).await {
+            return err_response.into_response();
+        }
+        
+        let previous = match User::set_avatar(id, None, &mut tx).await {
+            Ok(previous) => previous,
+            Err(e) => {
+                error!("Couldn't remove avatar of user {}: {}", id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        if let Some(previous) = previous {
+            if let Err(e) = avatars.remove(id, &previous).await {
+                error!("Couldn't remove avatar {} of user {}: {:?}", previous, id, e);
+            }
+        }
+        
+        StatusCode::OK.into_response()
+    }
+    
+    // Files of the local storage, every version has its own name so they never change
+    pub async fn serve(
+        State(state): State<AppState>,
+        Path(key): Path<String>,
+    ) -> impl IntoResponse {
+        match state.avatars.read(&key).await {
+            Ok(Some(data)) => axum::http::Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "image/png")
+                .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+                .body(axum::body::Body::from(data))
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+            Ok(None) | Err(StorageError::BadKey(_)) => StatusCode::NOT_FOUND.into_response(),
+            Err(e) => {
+                error!("Couldn't read avatar {}: {:?}", key, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+mod discovery {
+    use axum::extract::Query;
+    use super::*;
+    use serde::Deserialize;
+    
+    const DEFAULT_PAGE_SIZE: u32 = 20;
+    const MAX_PAGE_SIZE: u32 = 50;
+    const MAX_PAGE: u32 = 1_000;
+    const MAX_QUERY_LENGTH: usize = 64;
+    const DEFAULT_SUGGESTIONS: u32 = 10;
+    const MAX_SUGGESTIONS: u32 = 50;
+    
+    #[derive(Deserialize, Debug)]
+    pub struct SearchQuery {
+        pub q: String,
+        pub page: Option<u32>,
+        pub page_size: Option<u32>,
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct SuggestionQuery {
+        pub limit: Option<u32>,
+    }
+    
+    pub async fn search(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Query(query): Query<SearchQuery>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "user search");
+        let _enter = span.enter();
+        
+        let text = query.q.trim();
+        if text.is_empty() || text.chars().count() > MAX_QUERY_LENGTH {
+            warn!("Search text has to be 1 to {} characters long", MAX_QUERY_LENGTH);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        
+        // The offset has to fit in a u32, nobody scrolls that far through search results anyway
+        let page = query.page.unwrap_or(0);
+        let page_size = query.page_size.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        if page > MAX_PAGE || page.checked_mul(page_size).is_none() {
+            warn!("Search page {} is out of range", page);
+            return field_errors(BTreeMap::from([("page", vec![format!("Has to be at most {}", MAX_PAGE)])]));
+        }
+        
+        let avatars = state.avatars.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let mut results = match User::search(user_id, text, page, page_size, &mut tx).await {
+            Ok(results) => results,
+            Err(e) => {
+                error!("Couldn't search users: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        for user in &mut results.results {
+            user.avatar = user.avatar_version.as_deref().map(|version| avatars.urls(user.id, version));
+        }
+        json_response(StatusCode::OK, &results)
+    }
+    
+    pub async fn suggest_friends(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Query(query): Query<SuggestionQuery>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "friend suggestions");
+        let _enter = span.enter();
+        
+        let avatars = state.avatars.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let limit = query.limit.unwrap_or(DEFAULT_SUGGESTIONS).clamp(1, MAX_SUGGESTIONS);
+        let mut suggestions = match User::suggest_friends(user_id, limit, &mut tx).await {
+            Ok(suggestions) => suggestions,
+            Err(e) => {
+                error!("Couldn't suggest friends for user {}: {}", user_id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        for suggestion in &mut suggestions {
+            let user = &mut suggestion.user;
+            user.avatar = user.avatar_version.as_deref().map(|version| avatars.urls(user.id, version));
+        }
+        json_response(StatusCode::OK, &suggestions)
+    }
+}
+
+mod two_factor {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use crate::models::two_factor::TwoFactorError;
+    use crate::models::lockout::Throttle;
+    
+    fn error_response(e: TwoFactorError) -> axum::response::Response {
+        match e {
+            TwoFactorError::AlreadyEnabled => StatusCode::CONFLICT.into_response(),
+            TwoFactorError::NotEnrolled | TwoFactorError::NotEnabled => StatusCode::NOT_FOUND.into_response(),
+            TwoFactorError::Required => StatusCode::FORBIDDEN.into_response(),
+            TwoFactorError::InvalidChallenge => StatusCode::UNAUTHORIZED.into_response(),
+            TwoFactorError::InvalidCode => StatusCode::BAD_REQUEST.into_response(),
+            TwoFactorError::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS.into_response(),
+            TwoFactorError::DatabaseError(e) => {
+                error!("Database error: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct CodeForm {
+        pub code: String,
+    }
+    
+    #[derive(Serialize)]
+    struct RecoveryCodes {
+        recovery_codes: Vec<String>,
+    }
+    
+    #[derive(Deserialize)]
+    pub struct LoginForm {
+        pub challenge: String,
+        pub code: String,
+    }
+    
+    // Second step after `/user/login` answered with a challenge
+    pub async fn login(
+        headers: HeaderMap,
+        ConnectInfo(address): ConnectInfo<SocketAddr>,
+        State(state): State<AppState>,
+        Json(form): Json<LoginForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "two factor login");
+        let _enter = span.enter();
+        
+        let session_config = state.config.session.clone();
+        let two_factor_config = state.config.two_factor.clone();
+        let lockout_config = state.config.lockout.clone();
+        let notifier = state.notifier.clone();
+        let client = client_info(&headers, address);
+        let ip = client.ip_address.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let account = match User::read_challenge_user(&form.challenge, &mut tx).await {
+            Ok(Some(account)) => account,
+            Ok(None) => return error_response(TwoFactorError::InvalidChallenge),
+            Err(e) => return error_response(TwoFactorError::DatabaseError(e)),
+        };
+        
+        // Wrong codes count like wrong passwords, otherwise the code could be guessed with fresh challenges
+        let mut throttles: Vec<Throttle> = ip.iter().map(|ip| Throttle::Ip(ip.clone())).collect();
+        throttles.push(Throttle::Account(account));
+        
+        let mut wait = None;
+        for throttle in &throttles {
+            match throttle.wait(&lockout_config, &mut tx).await {
+                Ok(throttle_wait) => wait = wait.max(throttle_wait),
+                Err(e) => return error_response(TwoFactorError::DatabaseError(e)),
+            }
+        }
+        
+        if let Some(wait) = wait {
+            warn!("Second factor for user {} throttled for {}s", account, wait.num_seconds());
+            if let Err(e) = AuditEntry::record(Some(account), AuditAction::LoginThrottled, ip.as_deref(), None, &mut tx).await {
+                error!("Couldn't record audit entry: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            if let Err(e) = tx.commit().await {
+                error!("Couldn't commit transaction: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            return too_many_requests(wait);
+        }
+        
+        match User::complete_login(&form.challenge, &form.code, &client, &session_config, &two_factor_config, &mut tx).await {
+            Ok((user, tokens)) => {
+                if let Err(e) = Throttle::Account(user.id).clear(&mut tx).await {
+                    error!("Couldn't clear failed logins of user {}: {}", user.id, e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                if let Err(e) = AuditEntry::record(Some(user.id), AuditAction::LoginSucceeded, ip.as_deref(), Some("second factor".to_string()), &mut tx).await {
+                    error!("Couldn't record audit entry: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                login_response(user.id, tokens, tx).await
+            },
+            // Wrong codes are counted, so those are committed too
+            Err(TwoFactorError::InvalidCode) => {
+                let unlock_message = match super::user::record_failed_login(&throttles, Some(account), ip.as_deref(), &lockout_config, &mut tx).await {
+                    Ok(unlock_message) => unlock_message,
+                    Err(e) => return error_response(TwoFactorError::DatabaseError(e)),
+                };
+                
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                
+                if let Some((recipient, message)) = unlock_message {
+                    tokio::spawn(async move {
+                        if let Err(e) = notifier.send(&recipient, &message).await {
+                            error!("Couldn't deliver unlock link to {:?}: {:?}", recipient, e);
+                        }
+                    });
+                }
+                error_response(TwoFactorError::InvalidCode)
+            },
+            Err(e) => error_response(e)
+        }
+    }
+    
+    pub async fn enroll(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "two factor enroll");
+        let _enter = span.enter();
+        
+        let two_factor_config = state.config.two_factor.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let enrollment = match User::enroll_two_factor(user_id, &two_factor_config, &mut tx).await {
+            Ok(enrollment) => enrollment,
+            Err(e) => return error_response(e),
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        json_response(StatusCode::OK, &enrollment)
+    }
+    
+    pub async fn confirm(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(form): Json<CodeForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "two factor confirm");
+        let _enter = span.enter();
+        
+        let two_factor_config = state.config.two_factor.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let recovery_codes = match User::enable_two_factor(user_id, &form.code, &two_factor_config, &mut tx).await {
+            Ok(recovery_codes) => recovery_codes,
+            Err(e) => return error_response(e),
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        json_response(StatusCode::OK, &RecoveryCodes { recovery_codes })
+    }
+    
+    pub async fn regenerate_recovery_codes(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(form): Json<CodeForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "two factor recovery codes");
+        let _enter = span.enter();
+        
+        let two_factor_config = state.config.two_factor.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let recovery_codes = match User::regenerate_recovery_codes(user_id, &form.code, &two_factor_config, &mut tx).await {
+            Ok(recovery_codes) => recovery_codes,
+            Err(e) => return error_response(e),
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        info!("User {} regenerated recovery codes", user_id);
+        json_response(StatusCode::OK, &RecoveryCodes { recovery_codes })
+    }
+    
+    pub async fn disable(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(form): Json<CodeForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "two factor disable");
+        let _enter = span.enter();
+        
+        let two_factor_config = state.config.two_factor.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        if let Err(e) = User::disable_two_factor(user_id, &form.code, &two_factor_config, &mut tx).await {
+            return error_response(e);
+        }
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        StatusCode::NO_CONTENT.into_response()
+    }
+}
+
+mod oidc {
+    use super::*;
+    use serde::Deserialize;
+    use crate::oidc::{LoginRequest, OidcError};
+    use crate::models::identity::IdentityError;
+    
+    pub async fn providers(
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        json_response(StatusCode::OK, &state.oidc.providers())
+    }
+    
+    // The frontend sends the user to the returned url, the provider sends them back to its redirect_uri
+    pub async fn start(
+        State(state): State<AppState>,
+        Path(provider): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "oidc start");
+        let _enter = span.enter();
+        
+        let (request, url) = match state.oidc.start_login(&provider) {
+            Ok(login) => login,
+            Err(OidcError::UnknownProvider) => return StatusCode::NOT_FOUND.into_response(),
+            Err(e) => {
+                error!("Couldn't start login with {}: {:?}", provider, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        let login_ttl = state.config.oidc.login_ttl();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        if let Err(e) = request.save(login_ttl, &mut tx).await {
+            error!("Couldn't save login request: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        json_response(StatusCode::OK, &serde_json::json!({ "authorization_url": url }))
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct CallbackForm {
+        pub code: String,
+        pub state: String,
+    }
+    
+    pub async fn callback(
+        headers: HeaderMap,
+        ConnectInfo(address): ConnectInfo<SocketAddr>,
+        State(state): State<AppState>,
+        Path(provider): Path<String>,
+        Json(form): Json<CallbackForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "oidc callback");
+        let _enter = span.enter();
+        
+        let session_config = state.config.session.clone();
+        let two_factor_config = state.config.two_factor.clone();
+        let client = client_info(&headers, address);
+        let oidc = state.oidc.clone();
+        
+        // Taken in its own transaction, no connection is held while the provider is asked
+        let mut tx = match get_transaction(state.clone()).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let request = match LoginRequest::take(&provider, &form.state, &mut tx).await {
+            Ok(Some(request)) => request,
+            Ok(None) => {
+                warn!("Unknown or expired login state for {}", provider);
+                return StatusCode::BAD_REQUEST.into_response();
+            },
+            Err(e) => {
+                error!("Couldn't read login request: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        let claims = match oidc.finish_login(&request, &form.code).await {
+            Ok(claims) => claims,
+            Err(OidcError::UnknownProvider) => return StatusCode::NOT_FOUND.into_response(),
+            Err(OidcError::Rejected(e)) => {
+                warn!("{} rejected the login code: {}", provider, e);
+                return StatusCode::UNAUTHORIZED.into_response();
+            },
+            Err(OidcError::InvalidIdToken(reason)) => {
+                warn!("Invalid ID token from {}: {}", provider, reason);
+                return StatusCode::UNAUTHORIZED.into_response();
+            },
+            Err(e) => {
+                error!("Couldn't reach {}: {:?}", provider, e);
+                return StatusCode::BAD_GATEWAY.into_response();
+            }
+        };
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let (user, tokens) = match User::login_external(&provider, &claims, &client, &session_config, &two_factor_config, &mut tx).await {
+            Ok(LoginStep::Done(user, tokens)) => (user, tokens),
+            Ok(LoginStep::TwoFactor(challenge)) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                return json_response(StatusCode::ACCEPTED, &challenge);
+            },
+            Err(IdentityError::MissingEmail) => {
+                warn!("{} didn't share a usable email for a new account", provider);
+                return StatusCode::BAD_REQUEST.into_response();
+            },
+            Err(e) => {
+                error!("Couldn't log in with {}: {:?}", provider, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = AuditEntry::record(Some(user.id), AuditAction::LoginSucceeded, client.ip_address.as_deref(), Some(provider.clone()), &mut tx).await {
+            error!("Couldn't record audit entry: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        login_response(user.id, tokens, tx).await
+    }
+    
+    pub async fn identities(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "oidc identities");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let identities = match User::read_identities(user_id, &mut tx).await {
+            Ok(identities) => identities,
+            Err(e) => {
+                error!("Couldn't read identities of user {}: {}", user_id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        json_response(StatusCode::OK, &identities)
+    }
+}
+
+mod task {
+    use reqwest::RequestBuilder;
+
+    use super::*;
+   
+    pub async fn get(
+        Path(id_str): Path<String>, 
+        State(state): State<AppState>) -> impl IntoResponse {
+        
+        let span = span!(tracing::Level::INFO, "task get");
+        let _enter = span.enter();
+        
+        let id = match Uuid::parse_str(&id_str) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Invalid UUID: {}", e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+    
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+
+        let task = Task::read(id, &mut tx).await;
+        match task {
+            Ok(task) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                info!("Successfully read task {}", id);
+                Json(task).into_response()
+            },
+            
+            Err(e) => match e {
+                sqlx::Error::RowNotFound => {
+                    warn!("Entry {} not found", id);
+                    StatusCode::NOT_FOUND.into_response()
+                },
+                
+                _ => {
+                    error!("Other error!");
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                },
+            },
+        }
+    }
+    
+    pub async fn get_random(State(state): State<AppState>) -> impl IntoResponse {
+        use sqlx::query;
+        use rand::seq::SliceRandom;
+        use axum::http::Response;
+        
+        let span = span!(tracing::Level::INFO, "task get random");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let task_id = match query!("SELECT id FROM tasks")
+            .fetch_all(tx.as_mut()).await {
+                Ok(records) => {
+                    let mut rng = rand::thread_rng();
+                    match records.choose(&mut rng) {
+                        Some(record) => {
+                            match uuid::Uuid::parse_str(&record.id) {
+                                Ok(id) => id,
+                                Err(e) => {
+                                    error!("Couldn't parse UUID: {}", e);
+                                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                                }
+                            }
+                        }
+                        None => {
+                            warn!("No tasks found");
+                            return StatusCode::NOT_FOUND.into_response();
+                        }
+                    }
+                },
+                Err(e) => {
+                    error!("Couldn't get random task: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+        
+        let task = match Task::read(task_id, &mut tx).await {
+            Ok(task) => task,
+            Err(e) => {
+                error!("Couldn't read task: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        let json = serde_json::to_string(&task);
+        
+        if let Err(e) = json {
+            error!("Couldn't serialize task: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        match Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(json.expect("This result is an Err, THIS SHOULDN'T HAPPEN").into()) {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("Couldn't build response: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+    }
+    
+    pub async fn get_other_than(
+        State(state): State<AppState>,
+        Json(task_ids): Json<Vec<Uuid>>,
+    ) -> impl IntoResponse {
+        use sqlx::query;
+        use axum::http::Response;
+        use rand::seq::SliceRandom;
+        
+        let span = span!(tracing::Level::INFO, "task get other than");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let task_id = match query!("SELECT id FROM tasks")
+            .fetch_all(tx.as_mut()).await {
+                Ok(records) => {
+                    let mut rng = rand::thread_rng();
+                    loop {
+                        match records.choose(&mut rng) {
+                            Some(record) => {
+                                match uuid::Uuid::parse_str(&record.id) {
+                                    Ok(id) => {
+                                        if !task_ids.contains(&id) {
+                                            break id;
+                                        }
+                                    },
+                                    Err(e) => {
+                                        error!("Couldn't parse UUID: {}", e);
+                                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                                    }
+                                }
+                            }
+                            None => {
+                                warn!("No tasks found");
+                                return StatusCode::NOT_FOUND.into_response();
+                            }
+                        }
+                    }
+                },
+                Err(e) => {
+                    error!("Couldn't get random task: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+        
+        let task = match Task::read(task_id, &mut tx).await {
+            Ok(task) => task,
+            Err(e) => {
+                error!("Couldn't read task: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        let json = serde_json::to_string(&task);
+        
+        if let Err(e) = json {
+            error!("Couldn't serialize task: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        match Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(json.expect("This result is an Err, THIS SHOULDN'T HAPPEN").into()) {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("Couldn't build response: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+    }
+}
+
+mod answer {
+    use axum::http::Response;
+    use super::*;
+    use serde::Deserialize;
+    use crate::models::answer::*;
+    use crate::grading::{GradingJob, GradingStatus};
+    
+    pub async fn get(
+        Path(id_str) : Path<String>, 
+        State(state): State<AppState>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "answer get");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        if let Err(err_response) = validate_token(headers, &mut tx).await {
+            return err_response.into_response();
+        }
+        
+        let id = match Uuid::parse_str(&id_str) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Invalid UUID: {}", e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        
+        match Answer::read(id, &mut tx).await {
+            Ok(answer) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                
+                match answer {
+                    Some(answer) => {
+                        info!("Successfully read answer {}", id);
+                        
+                        let serialized = match answer.serialize() {
+                            Ok(serialized) => serialized,
+                            Err(e) => {
+                                error!("Couldn't serialize answer: {}", e);
+                                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                            }
+                        };
+                        
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .body(serialized.into())
+                            .unwrap_or_else(|_| {
+                                error!("Couldn't build response");
+                                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                            })
+                    },
+                    None => {
+                        warn!("Answer {} not found", id);
+                        StatusCode::NOT_FOUND.into_response()
+                    },
+                }
+            },
+            Err(e) => match e {
+                sqlx::Error::RowNotFound => {
+                    warn!("Answer {} not found", id);
+                    StatusCode::NOT_FOUND.into_response()
+                },
+                _ => {
+                    error!("Other error!");
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                },
+            },
+        }
+    }
+    
+    pub async fn get_result(
+        Path(id_str) : Path<String>, 
+        State(state): State<AppState>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        use serde_json::json;
+        
+        let span = span!(tracing::Level::INFO, "answer result get");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let id = match Uuid::parse_str(&id_str) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Invalid UUID: {}", e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        
+        let answer = match Answer::read(id, &mut tx).await {
+            Ok(Some(answer)) => answer,
+            Ok(None) => {
+                warn!("Answer {} not found", id);
+                return StatusCode::NOT_FOUND.into_response();
+            },
+            Err(e) => {
+                error!("Couldn't read answer {}: {}", id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(err_response) = check_authorization(headers, &answer.user_id, &mut tx).await {
+            return err_response.into_response();
+        }
+        
+        let job = match GradingJob::read(id, &mut tx).await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                warn!("Answer {} was never queued for grading", id);
+                return StatusCode::NOT_FOUND.into_response();
+            },
+            Err(e) => {
+                error!("Couldn't read grading job {}: {}", id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        let (status, json) = match job.status {
+            GradingStatus::Pending | GradingStatus::Running => (StatusCode::ACCEPTED, json!({ "id": id, "status": job.status })),
+            GradingStatus::Failed => (StatusCode::OK, json!({ "id": id, "status": job.status, "error": job.last_error })),
+            GradingStatus::Done => match VerifyResult::read(id, &mut tx).await {
+                Ok(Some(result)) => {
+                    let achievements = match User::read_answer_achievements(id, &mut tx).await {
+                        Ok(achievements) => achievements,
+                        Err(e) => {
+                            error!("Couldn't read achievements of answer {}: {}", id, e);
+                            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                        }
+                    };
+                    (StatusCode::OK, json!({
+                        "id": id,
+                        "status": job.status,
+                        "correct": result.correct,
+                        "explanation": result.explanation,
+                        "achievements": achievements
+                    }))
+                },
+                Ok(None) => {
+                    error!("Answer {} is graded but has no result", id);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                },
+                Err(e) => {
+                    error!("Couldn't read result of answer {}: {}", id, e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        json_response(status, &json)
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct AnswerForm {
+        pub user_id: Uuid,
+        pub task_id: Uuid,
+        pub content: Option<crate::models::answer::AnswerContent> 
+    }
+    
+    impl AnswerForm {
+        fn into_answer(self) -> Answer {
+            Answer {
+                id: Uuid::new_v4(),
+                user_id: self.user_id,
+                task_id: self.task_id,
+                content: self.content,
+            }
+        }
+    }
+    
+    pub async fn post(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(answer_form): Json<AnswerForm>,
+    ) -> impl IntoResponse {
+        use serde_json::json;
+        
+        let span = span!(tracing::Level::INFO, "answer post");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        if let Err(err_response) = check_authorization(headers, &answer_form.user_id, &mut tx).await {
+            return err_response.into_response();
+        }
+       
+        let answer = answer_form.into_answer();
+        
+        match answer.create(&mut tx).await {
+            Ok(id) => {
+                info!("Answer successfully created.");
+                
+                // Verifiers can be slow, grading happens in the background
+                if let Err(e) = GradingJob::enqueue(id, &mut tx).await {
+                    error!("Couldn't enqueue grading of answer {}: {}", id, e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                
+                let json = json!({
+                    "id": id,
+                    "status": GradingStatus::Pending
+                });
+                
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                
+                let response = Response::builder()
+                    .status(StatusCode::ACCEPTED)
+                    .header("Location", format!("/answer/{}/result", id))
+                    .body(json.to_string().into());
+                match response {
+                    Ok(response) => response,
+                    Err(e) => {
+                        error!("Couldn't create response: {}", e);
+                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    }
+                }
+            },
+            Err(e) => {
+                error!("Couldn't create answer: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        } 
+    }
+    
+    pub async fn put(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(answer): Json<Answer>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "answer put");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        if let Err(err_response) = check_authorization(headers, &answer.user_id, &mut tx).await {
+            return err_response.into_response();
+        }
+        
+        match answer.update(&mut tx).await {
+            Ok(_) => {
+                info!("Answer {} updated", answer.id);
+                StatusCode::OK.into_response()
+            },
+            Err(e) => {
+                error!("Couldn't update answer {}: {}", answer.id, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+    
+    pub async fn delete(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let id = match Uuid::parse_str(&id_str) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Invalid UUID: {}", e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        
+        if let Err(err_response) = check_authorization(headers, &id, &mut tx).await {
+            return err_response.into_response();
+        }
+        
+        match Answer::delete(id, &mut tx).await {
+            Ok(_) => {
+                info!("Answer {} successfully deleted", id);
+                StatusCode::NO_CONTENT.into_response()
+            },
+            Err(e) => {
+                error!("Couldn't delete answer {}: {}", id, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+}
+
+mod password_reset {
+    use super::*;
+    use serde::Deserialize;
+    use crate::models::password_reset::PasswordResetError;
+    use crate::notifier::{Message, Recipient};
+    
+    #[derive(Deserialize, Debug)]
+    pub struct ForgotForm {
+        pub email: Option<String>,
+        pub phone: Option<String>,
+    }
+    
+    // Answers the same whether the user exists or not, so it can't be used to look up accounts
+    pub async fn forgot(
+        State(state): State<AppState>,
+        Json(form): Json<ForgotForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "password forgot");
+        let _enter = span.enter();
+        
+        let recipient = match (form.email, form.phone) {
+            (Some(email), _) => Recipient::Email(email),
+            (None, Some(phone)) => Recipient::Phone(phone),
+            (None, None) => {
+                warn!("Password reset requested without email or phone");
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        
+        let notifier = state.notifier.clone();
+        let reset_config = state.config.password_reset.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user = match &recipient {
+            Recipient::Email(email) => User::read_by_contact(Some(email), None, &mut tx).await,
+            Recipient::Phone(phone) => User::read_by_contact(None, Some(phone), &mut tx).await,
+        };
+        
+        let user = match user {
+            Ok(Some(user)) => user,
+            Ok(None) => {
+                info!("Password reset requested for unknown {:?}", recipient);
+                return StatusCode::ACCEPTED.into_response();
+            },
+            Err(e) => {
+                error!("Couldn't look up user: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        let token = match User::create_password_reset(user.id, reset_config.ttl(), &mut tx).await {
+            Ok(token) => token,
+            Err(e) => {
+                error!("Couldn't create password reset for user {}: {}", user.id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        let message = Message {
+            subject: "Reset your code samurai password".to_string(),
+            body: format!(
+                "Hi {}!\n\nUse this link to set a new password, it expires in {} minutes:\n{}{}\n\nIf you didn't ask for it, just ignore this message.",
+                user.username, reset_config.ttl_minutes, reset_config.link, token
+            ),
+        };
+        
+        // Delivery can be slow, the user gets the same answer either way
+        tokio::spawn(async move {
+            if let Err(e) = notifier.send(&recipient, &message).await {
+                error!("Couldn't deliver password reset to user {}: {:?}", user.id, e);
+            }
+        });
+        
+        StatusCode::ACCEPTED.into_response()
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct ResetForm {
+        pub token: String,
+        pub password: String,
+    }
+    
+    pub async fn reset(
+        State(state): State<AppState>,
+        Json(form): Json<ResetForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "password reset");
+        let _enter = span.enter();
+        
+        if let Some(response) = check_password(&state.password_policy, None, &form.password).await {
+            return response;
+        }
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        match User::reset_password(&form.token, form.password, &mut tx).await {
+            Ok(_) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                StatusCode::NO_CONTENT.into_response()
+            },
+            Err(PasswordResetError::InvalidToken) | Err(PasswordResetError::EmptyPassword) => StatusCode::BAD_REQUEST.into_response(),
+            Err(PasswordResetError::DatabaseError(e)) => {
+                error!("Database error: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+            Err(PasswordResetError::HashError(e)) => {
+                error!("Hash error: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct ChangeForm {
+        pub current_password: String,
+        pub new_password: String,
+    }
+    
+    // Signs out every other session, like a reset does for all of them
+    pub async fn change(
+        headers: HeaderMap,
+        ConnectInfo(address): ConnectInfo<SocketAddr>,
+        State(state): State<AppState>,
+        Json(form): Json<ChangeForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "password change");
+        let _enter = span.enter();
+        
+        let password_policy = state.password_policy.clone();
+        let client = client_info(&headers, address);
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let (session_id, user_id) = match session::get_session_and_user_id(headers, &mut tx).await {
+            Ok(ids) => ids,
+            Err(response) => return response,
+        };
+        
+        let user = match User::read(user_id, &mut tx).await {
+            Ok(user) => user,
+            Err(e) => {
+                error!("Couldn't read user {}: {}", user_id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Some(response) = check_password(&password_policy, Some(&user.username), &form.new_password).await {
+            return response;
+        }
+        
+        match User::change_password(user_id, &form.current_password, &form.new_password, &mut tx).await {
+            Ok(()) => (),
+            Err(UserError::BadCredentials) => return StatusCode::FORBIDDEN.into_response(),
+            Err(UserError::NoSuchUser) => return StatusCode::NOT_FOUND.into_response(),
+            Err(e) => {
+                error!("Couldn't change password of user {}: {:?}", user_id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+        
+        if let Err(e) = Session::revoke_others(user_id, session_id, &mut tx).await {
+            error!("Couldn't revoke sessions of user {}: {}", user_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        if let Err(e) = AuditEntry::record(Some(user_id), AuditAction::PasswordChanged, client.ip_address.as_deref(), None, &mut tx).await {
+            error!("Couldn't record audit entry: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        StatusCode::NO_CONTENT.into_response()
+    }
+}
+
+mod verification {
+    use super::*;
+    use serde::Deserialize;
+    use crate::config::VerificationConfig;
+    use crate::models::verification::{Channel, VerificationError};
+    use crate::notifier::{Message, Recipient};
+    
+    // Sent in the background, a slow delivery shouldn't hold up the response
+    pub fn deliver_code(notifier: Arc<dyn Notifier>, recipient: Recipient, username: &str, code: String, config: &VerificationConfig) {
+        let message = Message {
+            subject: "Your code samurai verification code".to_string(),
+            body: format!(
+                "Hi {}!\n\nYour verification code is {}, it expires in {} minutes.",
+                username, code, config.code_ttl_minutes
+            ),
+        };
+        
+        tokio::spawn(async move {
+            if let Err(e) = notifier.send(&recipient, &message).await {
+                error!("Couldn't deliver verification code to {:?}: {:?}", recipient, e);
+            }
+        });
+    }
+    
+    fn error_response(e: VerificationError) -> axum::response::Response {
+        match e {
+            VerificationError::NoSuchContact => StatusCode::NOT_FOUND.into_response(),
+            VerificationError::AlreadyVerified => StatusCode::CONFLICT.into_response(),
+            VerificationError::InvalidCode => StatusCode::BAD_REQUEST.into_response(),
+            VerificationError::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS.into_response(),
+            VerificationError::TooSoon(wait) => too_many_requests(wait),
+            VerificationError::DatabaseError(e) => {
+                error!("Database error: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct VerifyForm {
+        pub channel: Channel,
+        pub code: String,
+    }
+    
+    pub async fn verify(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(form): Json<VerifyForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "verify contact");
+        let _enter = span.enter();
+        
+        let verification_config = state.config.verification.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let result = User::verify_contact(user_id, form.channel, form.code.trim(), &verification_config, &mut tx).await;
+        
+        // Wrong codes are counted, so those are committed too
+        if matches!(result, Ok(_) | Err(VerificationError::InvalidCode)) {
+            if let Err(e) = tx.commit().await {
+                error!("Couldn't commit transaction: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+        
+        match result {
+            Ok(_) => StatusCode::NO_CONTENT.into_response(),
+            Err(e) => error_response(e),
+        }
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct ResendForm {
+        pub channel: Channel,
+    }
+    
+    pub async fn resend(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(form): Json<ResendForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "resend verification");
+        let _enter = span.enter();
+        
+        let notifier = state.notifier.clone();
+        let verification_config = state.config.verification.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let (recipient, code) = match User::create_verification(user_id, form.channel, &verification_config, &mut tx).await {
+            Ok(code) => code,
+            Err(e) => return error_response(e),
+        };
+        
+        let user = match User::read(user_id, &mut tx).await {
+            Ok(user) => user,
+            Err(e) => {
+                error!("Couldn't read user {}: {}", user_id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        deliver_code(notifier, recipient, &user.username, code, &verification_config);
+        StatusCode::ACCEPTED.into_response()
+    }
+}
+
+mod friend {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use crate::models::friend::*;
+    
+    fn friend_error_response(e: FriendError) -> axum::response::Response {
+        match e {
+            FriendError::SelfReference => {
+                warn!("User tried to befriend or block themselves");
+                StatusCode::BAD_REQUEST.into_response()
+            },
+            FriendError::NoSuchUser => {
+                warn!("No such user");
+                StatusCode::NOT_FOUND.into_response()
+            },
+            FriendError::NoSuchRequest => {
+                warn!("No such friend request");
+                StatusCode::NOT_FOUND.into_response()
+            },
+            FriendError::AlreadyFriends | FriendError::RequestExists => {
+                warn!("Users are already friends or a request is pending");
+                StatusCode::CONFLICT.into_response()
+            },
+            FriendError::Blocked => {
+                warn!("Friend request between blocked users");
+                StatusCode::FORBIDDEN.into_response()
+            },
+            FriendError::NotAuthorized => {
+                warn!("Unauthorized friend request operation");
+                StatusCode::FORBIDDEN.into_response()
+            },
+            FriendError::DatabaseError(e) => {
+                error!("Database error!\nError: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+    
+    fn parse_id(id_str: &str) -> Result<Uuid, axum::response::Response> {
+        Uuid::parse_str(id_str).map_err(|e| {
+            warn!("Invalid UUID: {}", e);
+            StatusCode::BAD_REQUEST.into_response()
+        })
+    }
+    
+    pub async fn get_friends(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "friends get");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let id = match parse_id(&id_str) {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+        
+        // The friend list is private, others only see what the profile shows
+        if let Err(err_response) = check_authorization(headers, &id, &mut tx).await {
+            return err_response.into_response();
+        }
+        
+        match User::read_friends(id, &mut tx).await {
+            Ok(friends) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                json_response(StatusCode::OK, &friends)
+            },
+            Err(e) => {
+                error!("Couldn't read friends of user {}: {}", id, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+    
+    pub async fn unfriend(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "unfriend");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let friend_id = match parse_id(&id_str) {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+        
+        match User::unfriend(user_id, friend_id, &mut tx).await {
+            Ok(_) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                info!("User {} unfriended {}", user_id, friend_id);
+                StatusCode::NO_CONTENT.into_response()
+            },
+            Err(e) => friend_error_response(e),
+        }
+    }
+    
+    #[derive(Serialize, Debug)]
+    pub struct FriendRequests {
+        pub incoming: Vec<FriendRequest>,
+        pub outgoing: Vec<FriendRequest>,
+    }
+    
+    pub async fn get_requests(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "friend requests get");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let incoming = match FriendRequest::read_incoming(user_id, &mut tx).await {
+            Ok(requests) => requests,
+            Err(e) => {
+                error!("Couldn't read incoming friend requests: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        let outgoing = match FriendRequest::read_outgoing(user_id, &mut tx).await {
+            Ok(requests) => requests,
+            Err(e) => {
+                error!("Couldn't read outgoing friend requests: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        json_response(StatusCode::OK, &FriendRequests { incoming, outgoing })
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct FriendRequestForm {
+        pub receiver_id: Uuid,
+    }
+    
+    pub async fn send_request(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(form): Json<FriendRequestForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "friend request send");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let request = FriendRequest::new(user_id, form.receiver_id);
+        
+        match request.send(&mut tx).await {
+            Ok(_) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                
+                let mut response = json_response(StatusCode::CREATED, &request);
+                if let Ok(location) = HeaderValue::from_str(&format!("/friends/requests/{}", request.id)) {
+                    response.headers_mut().insert(header::LOCATION, location);
+                }
+                response
+            },
+            Err(e) => friend_error_response(e),
+        }
+    }
+    
+    #[derive(Debug)]
+    enum RequestAction {
+        Accept,
+        Decline,
+        Cancel,
+    }
+    
+    async fn answer_request(
+        headers: HeaderMap,
+        state: AppState,
+        id_str: String,
+        action: RequestAction,
+    ) -> axum::response::Response {
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let id = match parse_id(&id_str) {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+        
+        let (result, success_status) = match action {
+            RequestAction::Accept => (FriendRequest::accept(id, user_id, &mut tx).await, StatusCode::OK),
+            RequestAction::Decline => (FriendRequest::decline(id, user_id, &mut tx).await, StatusCode::OK),
+            RequestAction::Cancel => (FriendRequest::cancel(id, user_id, &mut tx).await, StatusCode::NO_CONTENT),
+        };
+        
+        match result {
+            Ok(_) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                info!("Friend request {}: {:?}", id, action);
+                success_status.into_response()
+            },
+            Err(e) => friend_error_response(e),
+        }
+    }
+    
+    pub async fn accept_request(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "friend request accept");
+        let _enter = span.enter();
+        
+        answer_request(headers, state, id_str, RequestAction::Accept).await
+    }
+    
+    pub async fn decline_request(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "friend request decline");
+        let _enter = span.enter();
+        
+        answer_request(headers, state, id_str, RequestAction::Decline).await
+    }
+    
+    pub async fn cancel_request(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "friend request cancel");
+        let _enter = span.enter();
+        
+        answer_request(headers, state, id_str, RequestAction::Cancel).await
+    }
+    
+    pub async fn get_blocked(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "blocks get");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        match User::read_blocked(user_id, &mut tx).await {
+            Ok(blocked) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                json_response(StatusCode::OK, &blocked)
+            },
+            Err(e) => {
+                error!("Couldn't read blocked users: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+    
+    pub async fn block(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "block");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let blocked_id = match parse_id(&id_str) {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+        
+        match User::block(user_id, blocked_id, &mut tx).await {
+            Ok(_) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                StatusCode::OK.into_response()
+            },
+            Err(e) => friend_error_response(e),
+        }
+    }
+    
+    pub async fn unblock(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "unblock");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let blocked_id = match parse_id(&id_str) {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+        
+        match User::unblock(user_id, blocked_id, &mut tx).await {
+            Ok(_) => {
+                if let Err(e) = tx.commit().await {
+                    error!("Couldn't commit transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                info!("User {} unblocked {}", user_id, blocked_id);
+                StatusCode::NO_CONTENT.into_response()
+            },
+            Err(e) => {
+                error!("Couldn't unblock user: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+}
+
+mod classroom {
+    use super::*;
+    use serde::Deserialize;
+    use axum::extract::Query;
+    use chrono::{DateTime, Utc};
+    use crate::models::classroom::*;
+    use crate::models::gradebook::Gradebook;
+    use crate::models::role::Role;
+    use crate::config::TwoFactorConfig;
+    
+    fn classroom_error_response(e: ClassroomError) -> axum::response::Response {
+        match e {
+            ClassroomError::NoSuchMember => {
+                warn!("No such classroom member");
+                StatusCode::NOT_FOUND.into_response()
+            },
+            ClassroomError::BadInviteCode => {
+                warn!("Unknown invite code");
+                StatusCode::NOT_FOUND.into_response()
+            },
+            ClassroomError::AlreadyMember => {
+                warn!("User is already in the classroom");
+                StatusCode::CONFLICT.into_response()
+            },
+            ClassroomError::InvalidName => {
+                warn!("Name has to be 1 to {} characters long", MAX_NAME_LENGTH);
+                field_errors(BTreeMap::from([("name", vec![format!("Has to be 1 to {} characters long", MAX_NAME_LENGTH)])]))
+            },
+            ClassroomError::NoTasks => {
+                warn!("Assignment without tasks");
+                field_errors(BTreeMap::from([("target", vec!["Needs at least one task".to_string()])]))
+            },
+            ClassroomError::NoSuchTask => {
+                warn!("Assignment with a task that doesn't exist");
+                field_errors(BTreeMap::from([("target", vec!["No such task".to_string()])]))
+            },
+            ClassroomError::NoAssignees => {
+                warn!("Assignment for an empty list of students");
+                field_errors(BTreeMap::from([("assignees", vec!["Needs at least one student, leave it out for the whole classroom".to_string()])]))
+            },
+            ClassroomError::InvalidSchedule => {
+                warn!("Assignment opens after it's due or closes before");
+                field_errors(BTreeMap::from([("due_time", vec!["Has to be after open_time and not after close_time".to_string()])]))
+            },
+            ClassroomError::InvalidAttemptLimit => {
+                warn!("Assignment without any attempts");
+                field_errors(BTreeMap::from([("max_attempts", vec!["Has to be at least 1".to_string()])]))
+            },
+            ClassroomError::InvalidLatePolicy => {
+                warn!("Late penalty out of range");
+                field_errors(BTreeMap::from([("late_policy", vec!["Penalty has to be 1 to 100 percent per day".to_string()])]))
+            },
+            ClassroomError::NotGradable => {
+                warn!("Curriculum level assignments have no tasks to grade");
+                StatusCode::CONFLICT.into_response()
+            },
+            ClassroomError::DatabaseError(e) => {
+                error!("Database error!\nError: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+    
+    fn parse_id(id_str: &str) -> Result<Uuid, axum::response::Response> {
+        Uuid::parse_str(id_str).map_err(|e| {
+            warn!("Invalid UUID: {}", e);
+            StatusCode::BAD_REQUEST.into_response()
+        })
+    }
+    
+    // Only teachers and admins get to open classrooms
+    async fn check_teacher(user_id: Uuid, config: &TwoFactorConfig, tx: &mut Transaction<'static, MySql>) -> Result<(), axum::response::Response> {
+        match User::read_active_roles(user_id, config, tx).await {
+            Ok(roles) if roles.contains(&Role::Teacher) || roles.contains(&Role::Admin) => Ok(()),
+            Ok(_) => {
+                warn!("User {} isn't a teacher", user_id);
+                Err(StatusCode::FORBIDDEN.into_response())
+            },
+            Err(e) => {
+                error!("Couldn't read roles: {}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+        }
+    }
+    
+    /// The classroom as `user_id` gets to see it, classrooms the user isn't in look like missing ones
+    async fn read_classroom(id_str: &str, user_id: Uuid, teacher_only: bool, tx: &mut Transaction<'static, MySql>) -> Result<Classroom, axum::response::Response> {
+        let id = parse_id(id_str)?;
+        
+        let mut classroom = match Classroom::read(id, tx).await {
+            Ok(Some(classroom)) => classroom,
+            Ok(None) => {
+                warn!("No classroom with id {}", id);
+                return Err(StatusCode::NOT_FOUND.into_response());
+            },
+            Err(e) => {
+                error!("Couldn't read classroom: {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+            }
+        };
+        
+        if classroom.teacher_id == user_id {
+            return Ok(classroom);
+        }
+        
+        match classroom.is_member(user_id, tx).await {
+            Ok(true) if teacher_only => {
+                warn!("Student {} tried to manage classroom {}", user_id, id);
+                Err(StatusCode::FORBIDDEN.into_response())
+            },
+            Ok(true) => {
+                classroom.invite_code = None;
+                Ok(classroom)
+            },
+            Ok(false) => {
+                warn!("User {} isn't in classroom {}", user_id, id);
+                Err(StatusCode::NOT_FOUND.into_response())
+            },
+            Err(e) => {
+                error!("Couldn't check classroom membership: {}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+        }
+    }
+    
+    async fn commit(tx: Transaction<'static, MySql>) -> Result<(), axum::response::Response> {
+        tx.commit().await.map_err(|e| {
+            error!("Couldn't commit transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct ClassroomForm {
+        pub name: String,
+    }
+    
+    pub async fn create(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(form): Json<ClassroomForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "classroom create");
+        let _enter = span.enter();
+        
+        let two_factor_config = state.config.two_factor.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        if let Err(response) = check_teacher(user_id, &two_factor_config, &mut tx).await {
+            return response;
+        }
+        
+        let mut classroom = match Classroom::new(user_id, &form.name) {
+            Ok(classroom) => classroom,
+            Err(e) => return classroom_error_response(e),
+        };
+        
+        if let Err(e) = classroom.create(&mut tx).await {
+            error!("Couldn't create classroom: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        if let Err(response) = commit(tx).await {
+            return response;
+        }
+        
+        let mut response = json_response(StatusCode::CREATED, &classroom);
+        if let Ok(location) = HeaderValue::from_str(&format!("/classrooms/{}", classroom.id)) {
+            response.headers_mut().insert(header::LOCATION, location);
+        }
+        response
+    }
+    
+    pub async fn get_all(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "classrooms get");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        match Classroom::read_for_user(user_id, &mut tx).await {
+            Ok(classrooms) => match commit(tx).await {
+                Ok(_) => json_response(StatusCode::OK, &classrooms),
+                Err(response) => response,
+            },
+            Err(e) => {
+                error!("Couldn't read classrooms: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+    
+    pub async fn get(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "classroom get");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        match read_classroom(&id_str, user_id, false, &mut tx).await {
+            Ok(classroom) => match commit(tx).await {
+                Ok(_) => json_response(StatusCode::OK, &classroom),
+                Err(response) => response,
+            },
+            Err(response) => response,
+        }
+    }
+    
+    pub async fn delete(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "classroom delete");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let classroom = match read_classroom(&id_str, user_id, true, &mut tx).await {
+            Ok(classroom) => classroom,
+            Err(response) => return response,
+        };
+        
+        if let Err(e) = Classroom::delete(classroom.id, &mut tx).await {
+            error!("Couldn't delete classroom: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        match commit(tx).await {
+            Ok(_) => StatusCode::NO_CONTENT.into_response(),
+            Err(response) => response,
+        }
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct JoinForm {
+        pub invite_code: String,
+    }
+    
+    pub async fn join(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Json(form): Json<JoinForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "classroom join");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        match Classroom::join(&form.invite_code, user_id, &mut tx).await {
+            Ok(classroom) => match commit(tx).await {
+                Ok(_) => json_response(StatusCode::OK, &classroom),
+                Err(response) => response,
+            },
+            Err(e) => classroom_error_response(e),
+        }
+    }
+    
+    pub async fn regenerate_invite_code(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "classroom invite code");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let mut classroom = match read_classroom(&id_str, user_id, true, &mut tx).await {
+            Ok(classroom) => classroom,
+            Err(response) => return response,
+        };
+        
+        if let Err(e) = classroom.regenerate_invite_code(&mut tx).await {
+            error!("Couldn't change the invite code: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        match commit(tx).await {
+            Ok(_) => json_response(StatusCode::OK, &classroom),
+            Err(response) => response,
+        }
+    }
+    
+    /// Teachers remove students, students can only leave themselves
+    pub async fn remove_student(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path((id_str, student_id_str)): Path<(String, String)>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "classroom remove student");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let student_id = match parse_id(&student_id_str) {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+        
+        let classroom = match read_classroom(&id_str, user_id, student_id != user_id, &mut tx).await {
+            Ok(classroom) => classroom,
+            Err(response) => return response,
+        };
+        
+        if let Err(e) = classroom.remove_member(student_id, &mut tx).await {
+            return classroom_error_response(e);
+        }
+        
+        match commit(tx).await {
+            Ok(_) => StatusCode::NO_CONTENT.into_response(),
+            Err(response) => response,
+        }
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct AssignmentForm {
+        pub title: String,
+        pub target: AssignmentTarget,
+        pub due_time: DateTime<Utc>,
+        #[serde(flatten)]
+        pub rules: AssignmentRules,
+    }
+    
+    pub async fn create_assignment(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+        Json(form): Json<AssignmentForm>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "assignment create");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let classroom = match read_classroom(&id_str, user_id, true, &mut tx).await {
+            Ok(classroom) => classroom,
+            Err(response) => return response,
+        };
+        
+        let assignment = match Assignment::new(classroom.id, &form.title, form.target, form.due_time, form.rules) {
+            Ok(assignment) => assignment,
+            Err(ClassroomError::InvalidName) => {
+                warn!("Title has to be 1 to {} characters long", MAX_NAME_LENGTH);
+                return field_errors(BTreeMap::from([("title", vec![format!("Has to be 1 to {} characters long", MAX_NAME_LENGTH)])]));
+            },
+            Err(e) => return classroom_error_response(e),
+        };
+        
+        match assignment.create(&mut tx).await {
+            Ok(_) => (),
+            Err(ClassroomError::NoSuchMember) => {
+                warn!("Assignment for a student who isn't in classroom {}", classroom.id);
+                return field_errors(BTreeMap::from([("assignees", vec!["Not a student of the classroom".to_string()])]));
+            },
+            Err(e) => return classroom_error_response(e),
+        }
+        
+        if let Err(response) = commit(tx).await {
+            return response;
+        }
+        
+        let mut response = json_response(StatusCode::CREATED, &assignment);
+        if let Ok(location) = HeaderValue::from_str(&format!("/classrooms/{}/assignments/{}", classroom.id, assignment.id)) {
+            response.headers_mut().insert(header::LOCATION, location);
+        }
+        response
+    }
+    
+    pub async fn get_assignments(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "assignments get");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let classroom = match read_classroom(&id_str, user_id, false, &mut tx).await {
+            Ok(classroom) => classroom,
+            Err(response) => return response,
+        };
+        
+        match Assignment::read_all(classroom.id, &mut tx).await {
+            Ok(assignments) => match commit(tx).await {
+                Ok(_) => json_response(StatusCode::OK, &assignments),
+                Err(response) => response,
+            },
+            Err(e) => {
+                error!("Couldn't read assignments: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+    
+    pub async fn delete_assignment(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path((id_str, assignment_id_str)): Path<(String, String)>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "assignment delete");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let assignment_id = match parse_id(&assignment_id_str) {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+        
+        let classroom = match read_classroom(&id_str, user_id, true, &mut tx).await {
+            Ok(classroom) => classroom,
+            Err(response) => return response,
+        };
+        
+        match Assignment::delete(classroom.id, assignment_id, &mut tx).await {
+            Ok(true) => match commit(tx).await {
+                Ok(_) => StatusCode::NO_CONTENT.into_response(),
+                Err(response) => response,
+            },
+            Ok(false) => {
+                warn!("No assignment {} in classroom {}", assignment_id, classroom.id);
+                StatusCode::NOT_FOUND.into_response()
+            },
+            Err(e) => {
+                error!("Couldn't delete assignment: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+    
+    // The assignment along with its classroom, as `user_id` gets to see it
+    async fn read_assignment(ids: &(String, String), user_id: Uuid, teacher_only: bool, tx: &mut Transaction<'static, MySql>) -> Result<Assignment, axum::response::Response> {
+        let assignment_id = parse_id(&ids.1)?;
+        let classroom = read_classroom(&ids.0, user_id, teacher_only, tx).await?;
+        
+        match Assignment::read(classroom.id, assignment_id, tx).await {
+            Ok(Some(assignment)) => Ok(assignment),
+            Ok(None) => {
+                warn!("No assignment {} in classroom {}", assignment_id, classroom.id);
+                Err(StatusCode::NOT_FOUND.into_response())
+            },
+            Err(e) => {
+                error!("Couldn't read assignment: {}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+        }
+    }
+    
+    #[derive(Deserialize, Debug, Default, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum GradebookFormat {
+        #[default]
+        Json,
+        Csv,
+    }
+    
+    #[derive(Deserialize, Debug)]
+    pub struct GradebookQuery {
+        #[serde(default)]
+        pub format: GradebookFormat,
+    }
+    
+    pub async fn get_gradebook(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(ids): Path<(String, String)>,
+        Query(query): Query<GradebookQuery>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "gradebook get");
+        let _enter = span.enter();
+        
+        let verifier = state.config.verifier.clone();
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let assignment = match read_assignment(&ids, user_id, true, &mut tx).await {
+            Ok(assignment) => assignment,
+            Err(response) => return response,
+        };
+        
+        let gradebook = match Gradebook::read(assignment, None, &verifier, &mut tx).await {
+            Ok(gradebook) => gradebook,
+            Err(e) => return classroom_error_response(e),
+        };
+        
+        if let Err(response) = commit(tx).await {
+            return response;
+        }
+        
+        if query.format == GradebookFormat::Json {
+            return json_response(StatusCode::OK, &gradebook);
+        }
+        
+        axum::http::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+            .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"gradebook-{}.csv\"", gradebook.assignment.id))
+            .body(gradebook.to_csv().into())
+            .unwrap_or_else(|e| {
+                error!("Couldn't build response: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            })
+    }
+    
+    /// A student's own grade
+    pub async fn get_grade(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(ids): Path<(String, String)>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "grade get");
+        let _enter = span.enter();
+        
+        let verifier = state.config.verifier.clone();
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let assignment = match read_assignment(&ids, user_id, false, &mut tx).await {
+            Ok(assignment) => assignment,
+            Err(response) => return response,
+        };
+        
+        let mut gradebook = match Gradebook::read(assignment, Some(user_id), &verifier, &mut tx).await {
+            Ok(gradebook) => gradebook,
+            Err(e) => return classroom_error_response(e),
+        };
+        
+        if let Err(response) = commit(tx).await {
+            return response;
+        }
+        
+        match gradebook.students.pop() {
+            Some(summary) => json_response(StatusCode::OK, &summary),
+            None => {
+                warn!("Assignment {} isn't for user {}", gradebook.assignment.id, user_id);
+                StatusCode::NOT_FOUND.into_response()
             },
-            Err(e) => match e {
-                sqlx::Error::RowNotFound => {
-                    warn!("Answer {} not found", id);
-                    StatusCode::NOT_FOUND.into_response()
-                },
-                _ => {
-                    error!("Other error!");
-                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                },
+        }
+    }
+    
+    pub async fn get_roster(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Path(id_str): Path<String>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "classroom roster");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let classroom = match read_classroom(&id_str, user_id, true, &mut tx).await {
+            Ok(classroom) => classroom,
+            Err(response) => return response,
+        };
+        
+        match classroom.read_roster(Utc::now(), &mut tx).await {
+            Ok(roster) => match commit(tx).await {
+                Ok(_) => json_response(StatusCode::OK, &roster),
+                Err(response) => response,
+            },
+            Err(e) => {
+                error!("Couldn't read roster: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
             },
         }
     }
+}
+
+mod leaderboard {
+    use axum::extract::Query;
+    use super::*;
+    use serde::Deserialize;
+    use crate::models::leaderboard::*;
+    
+    const DEFAULT_PAGE_SIZE: u32 = 20;
+    const MAX_PAGE_SIZE: u32 = 100;
+    const MAX_PAGE: u32 = 10_000;
     
     #[derive(Deserialize, Debug)]
-    pub struct AnswerForm {
-        pub user_id: Uuid,
-        pub task_id: Uuid,
-        pub content: Option<crate::models::answer::AnswerContent> 
+    pub struct PageQuery {
+        pub page: Option<u32>,
+        pub page_size: Option<u32>,
     }
     
-    impl AnswerForm {
-        fn into_answer(self) -> Answer {
-            Answer {
-                id: Uuid::new_v4(),
-                user_id: self.user_id,
-                task_id: self.task_id,
-                content: self.content,
+    impl PageQuery {
+        /// The page and its size, the rows before the page have to fit in the query's offset
+        fn validate(&self) -> Result<(u32, u32), axum::response::Response> {
+            let page = self.page.unwrap_or(0);
+            let page_size = self.page_size.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+            
+            match page.checked_mul(page_size) {
+                Some(_) if page <= MAX_PAGE => Ok((page, page_size)),
+                _ => {
+                    warn!("Leaderboard page {} is out of range", page);
+                    Err(field_errors(BTreeMap::from([("page", vec![format!("Has to be at most {}", MAX_PAGE)])])))
+                }
             }
         }
     }
     
-    pub async fn post(
+    // Periodically moves finished weekly leagues into the current week
+    pub async fn run_league_rollover(db_pool: MySqlPool) {
+        let mut interval = tokio::time::interval(LEAGUE_ROLLOVER_INTERVAL);
+        loop {
+            interval.tick().await;
+            
+            let mut tx = match db_pool.begin().await {
+                Ok(tx) => tx,
+                Err(e) => {
+                    error!("Couldn't get transaction for league rollover!\nError: {}", e);
+                    continue;
+                }
+            };
+            
+            match LeagueMembership::rollover(&mut tx).await {
+                Ok(_) => {
+                    if let Err(e) = tx.commit().await {
+                        error!("Couldn't commit league rollover: {}", e);
+                    }
+                },
+                Err(e) => error!("League rollover failed: {}", e),
+            }
+        }
+    }
+    
+    async fn leaderboard_response(leaderboard: Result<Leaderboard, sqlx::Error>, tx: Transaction<'static, MySql>) -> axum::response::Response {
+        let leaderboard = match leaderboard {
+            Ok(leaderboard) => leaderboard,
+            Err(e) => {
+                error!("Couldn't read leaderboard: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        json_response(StatusCode::OK, &leaderboard)
+    }
+    
+    pub async fn get_global(
         headers: HeaderMap,
         State(state): State<AppState>,
-        Json(answer_form): Json<AnswerForm>,
+        Query(page): Query<PageQuery>,
     ) -> impl IntoResponse {
-        use serde_json::json;
-        
-        let span = span!(tracing::Level::INFO, "answer post");
+        let span = span!(tracing::Level::INFO, "leaderboard global");
         let _enter = span.enter();
         
+        let (page, page_size) = match page.validate() {
+            Ok(page) => page,
+            Err(response) => return response,
+        };
+        
+        let verified_only = state.config.verification.hide_unverified_from_leaderboards;
+        
         let mut tx = match get_transaction(state).await {
             Ok(tx) => tx,
             Err(e) => return e.into_response(),
         };
         
-        if let Err(err_response) = check_authorization(headers, &answer_form.user_id, &mut tx).await {
+        if let Err(err_response) = validate_token(headers, &mut tx).await {
             return err_response.into_response();
         }
-       
-        let answer = answer_form.into_answer();
         
-        match answer.create(&mut tx).await {
-            Ok(id) => {
-                info!("Answer successfully created.");
-                
-                let verify_result = match answer.verify(&mut tx).await {
-                    Ok(verify_result) => verify_result,
-                    Err(e) => {
-                        error!("Couldn't verify answer: {:#?}", e);
-                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                    }
-                };
-                
-                let mut json = match serde_json::to_value(&verify_result) {
-                    Ok(json) => json,
-                    Err(e) => {
-                        error!("Couldn't serialize verify result: {}", e);
-                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                    }
-                };
-                
-                json["id"] = json!(id);
-                
-                if let Err(e) = tx.commit().await {
-                    error!("Couldn't commit transaction: {}", e);
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                }
-                
-                let response = Response::builder()
-                    .status(StatusCode::CREATED)
-                    .header("Location", format!("/answer/{}", id))
-                    .body(json.to_string().into());
-                match response {
-                    Ok(response) => response,
-                    Err(e) => {
-                        error!("Couldn't create response: {}", e);
-                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                    }
-                }
-            },
-            Err(e) => {
-                error!("Couldn't create answer: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            },
-        } 
+        let leaderboard = Leaderboard::global(page, page_size, verified_only, &mut tx).await;
+        leaderboard_response(leaderboard, tx).await
     }
     
-    pub async fn put(
+    pub async fn get_friends(
         headers: HeaderMap,
         State(state): State<AppState>,
-        Json(answer): Json<Answer>,
+        Query(page): Query<PageQuery>,
     ) -> impl IntoResponse {
-        let span = span!(tracing::Level::INFO, "answer put");
+        let span = span!(tracing::Level::INFO, "leaderboard friends");
         let _enter = span.enter();
         
+        let (page, page_size) = match page.validate() {
+            Ok(page) => page,
+            Err(response) => return response,
+        };
+        
+        let verified_only = state.config.verification.hide_unverified_from_leaderboards;
+        
         let mut tx = match get_transaction(state).await {
             Ok(tx) => tx,
             Err(e) => return e.into_response(),
         };
         
-        if let Err(err_response) = check_authorization(headers, &answer.user_id, &mut tx).await {
-            return err_response.into_response();
-        }
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
         
-        match answer.update(&mut tx).await {
-            Ok(_) => {
-                info!("Answer {} updated", answer.id);
-                StatusCode::OK.into_response()
-            },
-            Err(e) => {
-                error!("Couldn't update answer {}: {}", answer.id, e);
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            },
-        }
+        let leaderboard = Leaderboard::friends(user_id, page, page_size, verified_only, &mut tx).await;
+        leaderboard_response(leaderboard, tx).await
     }
     
-    pub async fn delete(
+    pub async fn get_league(
         headers: HeaderMap,
         State(state): State<AppState>,
-        Path(id_str): Path<String>,
+        Query(page): Query<PageQuery>,
     ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "leaderboard league");
+        let _enter = span.enter();
+        
+        let (page, page_size) = match page.validate() {
+            Ok(page) => page,
+            Err(response) => return response,
+        };
+        
+        let verified_only = state.config.verification.hide_unverified_from_leaderboards;
         
         let mut tx = match get_transaction(state).await {
             Ok(tx) => tx,
             Err(e) => return e.into_response(),
         };
         
-        let id = match Uuid::parse_str(&id_str) {
-            Ok(id) => id,
+        let user_id = match get_authorized_user_id(headers, &mut tx).await {
+            Ok(user_id) => user_id,
+            Err(response) => return response,
+        };
+        
+        let leaderboard = Leaderboard::league(user_id, page, page_size, verified_only, &mut tx).await;
+        leaderboard_response(leaderboard, tx).await
+    }
+}
+
+mod notification {
+    use axum::extract::Query;
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::ReceiverStream;
+    use tokio_stream::{Stream, StreamExt};
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use crate::notifications::Notification;
+    
+    // How long a revoked session can keep receiving notifications
+    const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+    
+    #[derive(Deserialize, Debug)]
+    pub struct TicketQuery {
+        pub ticket: Option<String>,
+    }
+    
+    #[derive(Serialize)]
+    struct Ticket {
+        ticket: String,
+    }
+    
+    // Browsers' EventSource can't set headers, it gets a ticket for the url instead of the auth token
+    pub async fn create_ticket(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        let span = span!(tracing::Level::INFO, "notifications ticket");
+        let _enter = span.enter();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let (session_id, user_id) = match session::get_session_and_user_id(headers, &mut tx).await {
+            Ok(ids) => ids,
+            Err(response) => return response,
+        };
+        
+        let ticket = match Session::create_stream_ticket(session_id, user_id, &mut tx).await {
+            Ok(ticket) => ticket,
             Err(e) => {
-                warn!("Invalid UUID: {}", e);
-                return StatusCode::BAD_REQUEST.into_response();
+                error!("Couldn't create stream ticket for user {}: {}", user_id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
         };
         
-        if let Err(err_response) = check_authorization(headers, &id, &mut tx).await {
-            return err_response.into_response();
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
         
-        match Answer::delete(id, &mut tx).await {
-            Ok(_) => {
-                info!("Answer {} successfully deleted", id);
-                StatusCode::NO_CONTENT.into_response()
-            },
-            Err(e) => {
-                error!("Couldn't delete answer {}: {}", id, e);
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        json_response(StatusCode::OK, &Ticket { ticket })
+    }
+    
+    pub async fn stream(
+        headers: HeaderMap,
+        State(state): State<AppState>,
+        Query(query): Query<TicketQuery>,
+    ) -> axum::response::Response {
+        let span = span!(tracing::Level::INFO, "notifications stream");
+        let _enter = span.enter();
+        
+        let notifications = state.notifications.clone();
+        let db_pool = state.db_pool.clone();
+        
+        let mut tx = match get_transaction(state).await {
+            Ok(tx) => tx,
+            Err(e) => return e.into_response(),
+        };
+        
+        let ids = match query.ticket {
+            Some(ticket) => match Session::redeem_stream_ticket(&ticket, &mut tx).await {
+                Ok(Some(ids)) => Ok(ids),
+                Ok(None) => {
+                    warn!("Unknown, expired or used stream ticket");
+                    Err(StatusCode::UNAUTHORIZED.into_response())
+                },
+                Err(e) => {
+                    error!("Couldn't redeem stream ticket: {}", e);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+                }
             },
+            None => session::get_session_and_user_id(headers, &mut tx).await
+        };
+        let (session_id, user_id) = match ids {
+            Ok(ids) => ids,
+            Err(response) => return response,
+        };
+        
+        if let Err(e) = tx.commit().await {
+            error!("Couldn't commit transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        
+        info!("User {} subscribed to notifications", user_id);
+        
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(forward(notifications.subscribe(user_id), session_id, db_pool, sender));
+        
+        let stream = ReceiverStream::new(receiver)
+            .map(|notification| Event::default().json_data(notification));
+        
+        Sse::new(stream)
+            .keep_alive(KeepAlive::default())
+            .into_response()
+    }
+    
+    // Ends the stream once the client is gone or the session was revoked or expired
+    async fn forward(notifications: impl Stream<Item = Notification>, session_id: Uuid, db_pool: MySqlPool, sender: mpsc::Sender<Notification>) {
+        tokio::pin!(notifications);
+        let mut check = tokio::time::interval(SESSION_CHECK_INTERVAL);
+        check.tick().await;
+        
+        loop {
+            tokio::select! {
+                notification = notifications.next() => match notification {
+                    Some(notification) => if sender.send(notification).await.is_err() {
+                        return;
+                    },
+                    None => return,
+                },
+                _ = check.tick() => match Session::is_active(session_id, &db_pool).await {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        info!("Closing notification stream of session {}, it's no longer active", session_id);
+                        return;
+                    },
+                    Err(e) => error!("Couldn't check session {} of a notification stream: {}", session_id, e),
+                },
+                _ = sender.closed() => return,
+            }
         }
     }
 }
@@ -967,20 +4548,81 @@ mod tests {
     use crate::database;
     use reqwest::StatusCode;
 
+    /// Serves on a free port, so tests don't clash with each other or a running server
+    async fn spawn_server(db_pool: MySqlPool, config: Config) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, db_pool, config));
+        address
+    }
+
     #[tokio::test]
     async fn test_endpoint() {
         let db_pool = database::get_database_connection_pool(None).await.unwrap();
+        let address = spawn_server(db_pool, Config::default()).await;
 
-        // Start the server in a separate Tokio task
-        tokio::spawn(start(db_pool, None, None));
-
-        // Give the server a moment to start
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
-        let response = reqwest::get(format!("http://{}:{}/test", DEFAULT_IP_ADDRESS, DEFAULT_PORT))
+        let response = reqwest::get(format!("{}/test", address))
             .await
             .expect("Failed to execute request.");
 
         assert_eq!(response.status(), StatusCode::OK);
     }
+    
+    // Requests hitting the database should be served concurrently, not one at a time
+    #[tokio::test]
+    async fn test_concurrent_load() {
+        const REQUESTS: usize = 500;
+        const CONCURRENCY: usize = 50;
+        
+        let db_pool = database::get_database_connection_pool(Some(CONCURRENCY as u32)).await.unwrap();
+        let mut config = Config::default();
+        // All requests come from one address, only the pool should limit them here
+        config.rate_limit.enabled = false;
+        let url = format!("{}/task/random", spawn_server(db_pool.clone(), config).await);
+        
+        let client = reqwest::Client::new();
+        
+        // Connections handed out at the same time, more than one means requests overlapped
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let sampler = tokio::spawn({
+            let (db_pool, done) = (db_pool.clone(), done.clone());
+            async move {
+                let mut peak = 0;
+                while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                    peak = peak.max((db_pool.size() as usize).saturating_sub(db_pool.num_idle()));
+                    tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+                }
+                peak
+            }
+        });
+        
+        let mut workers = tokio::task::JoinSet::new();
+        for worker in 0..CONCURRENCY {
+            let client = client.clone();
+            let url = url.clone();
+            workers.spawn(async move {
+                let mut statuses = Vec::new();
+                for _ in (worker..REQUESTS).step_by(CONCURRENCY) {
+                    let response = client.get(&url).send().await.expect("Failed to execute request.");
+                    statuses.push(response.status());
+                }
+                statuses
+            });
+        }
+        
+        let mut statuses = Vec::new();
+        while let Some(result) = workers.join_next().await {
+            statuses.extend(result.unwrap());
+        }
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+        let peak_in_use = sampler.await.unwrap();
+        
+        // A request that couldn't get a connection from the pool would have failed with 500
+        assert_eq!(statuses.len(), REQUESTS);
+        assert!(statuses.iter().all(|status| *status == StatusCode::OK), "Failed requests: {:?}", statuses.iter().filter(|status| **status != StatusCode::OK).collect::<Vec<_>>());
+        
+        // Served one at a time the pool would never need a second connection
+        assert!(peak_in_use > 1, "At most {} connection(s) were in use at once", peak_in_use);
+        assert!(db_pool.size() > 1);
+    }
 }
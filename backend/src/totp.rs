@@ -0,0 +1,122 @@
+use chrono::prelude::*;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+/// What every authenticator app expects, they're also written into the otpauth uri
+pub const STEP_SECS: i64 = 30;
+pub const DIGITS: u32 = 6;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// 160 bits, as RFC 4226 recommends
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Base32 without padding, the way authenticator apps take secrets
+pub fn encode_secret(secret: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in secret {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+pub fn decode_secret(encoded: &str) -> Option<Vec<u8>> {
+    let mut secret = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET.iter().position(|a| *a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            secret.push((buffer >> bits) as u8);
+        }
+    }
+    Some(secret)
+}
+
+pub fn step(time: DateTime<Utc>) -> i64 {
+    time.timestamp().div_euclid(STEP_SECS)
+}
+
+/// RFC 6238 code with SHA-1, the only algorithm all apps support
+pub fn code(secret: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+/// The step `code` was made for, one step of clock drift either way is allowed
+pub fn verify(secret: &[u8], code: &str, now: DateTime<Utc>) -> Option<i64> {
+    let code = code.trim();
+    let current = step(now);
+    (current - 1..=current + 1).find(|step| self::code(secret, *step) == code)
+}
+
+fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b)
+        })
+        .collect()
+}
+
+/// What the QR code shown during enrollment encodes
+pub fn otpauth_uri(issuer: &str, account: &str, encoded_secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer), percent_encode(account), encoded_secret, percent_encode(issuer), DIGITS, STEP_SECS
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc_vectors() {
+        // RFC 6238 appendix B, SHA-1, cut to 6 digits
+        let secret = b"12345678901234567890";
+        for (time, expected) in [(59, "287082"), (1111111109, "081804"), (1234567890, "005924"), (2000000000, "279037")] {
+            assert_eq!(code(secret, time / STEP_SECS), expected);
+        }
+
+        assert_eq!(encode_secret(secret), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(decode_secret("gezdgnbvgy3tqojqgezdgnbvgy3tqojq").unwrap(), secret);
+        assert_eq!(decode_secret("not base32!"), None);
+    }
+
+    #[test]
+    fn test_verify() {
+        let secret = generate_secret();
+        assert_eq!(decode_secret(&encode_secret(&secret)).unwrap(), secret);
+
+        let now = Utc::now();
+        let current = step(now);
+        assert_eq!(verify(&secret, &code(&secret, current), now), Some(current));
+        assert_eq!(verify(&secret, &code(&secret, current - 1), now), Some(current - 1));
+        assert_eq!(verify(&secret, &code(&secret, current - 3), now), None);
+
+        let uri = otpauth_uri("code samurai", "jan kowalski", "ABC");
+        assert_eq!(uri, "otpauth://totp/code%20samurai:jan%20kowalski?secret=ABC&issuer=code%20samurai&algorithm=SHA1&digits=6&period=30");
+    }
+}
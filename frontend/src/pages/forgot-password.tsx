@@ -5,6 +5,7 @@ import React, { useState } from "react";
 import { LanguageDropDown } from "~/components/LanguageDropDown";
 import type { LoginScreenState } from "~/components/LoginScreen";
 import { LoginScreen } from "~/components/LoginScreen";
+import { RequestPasswordReset } from "~/utils/backendUtils";
 
 const MenuIconSvg = (props: ComponentProps<"svg">) => {
   return (
@@ -25,6 +26,8 @@ const ForgotPassword: NextPage = () => {
   const [loginScreenState, setLoginScreenState] =
     useState<LoginScreenState>("HIDDEN");
   const [mobileMenuShown, setMobileMenuShown] = useState(false);
+  const [email, setEmail] = useState("");
+  const [sent, setSent] = useState(false);
   return (
     <div className="bg-darker-purple text-white flex min-h-screen flex-col items-center">
       <header className="flex h-[70px] w-full justify-center font-bold">
@@ -87,10 +90,20 @@ const ForgotPassword: NextPage = () => {
           <input
             className="text-black w-full rounded-2xl border-2 border-dark-purple bg-white px-4 py-3"
             placeholder="your email"
+            value={email}
+            onChange={(e) => setEmail(e.target.value)}
           />
-          <button className="w-full rounded-2xl border-b-4 border-pink-ish bg-dark-purple py-3 font-bold transition hover:bg-pink-ish hover:border-dark-purple">
+          <button
+            className="w-full rounded-2xl border-b-4 border-pink-ish bg-dark-purple py-3 font-bold transition hover:bg-pink-ish hover:border-dark-purple"
+            onClick={async () => setSent(await RequestPasswordReset(email))}
+          >
             please, send me the message
           </button>
+          {sent && (
+            <p className="text-center">
+              if there is an account with this email, the message is on its way.
+            </p>
+          )}
         </div>
       </div>
       <LoginScreen
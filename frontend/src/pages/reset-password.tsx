@@ -0,0 +1,57 @@
+import type { NextPage } from "next";
+import Link from "next/link";
+import { useRouter } from "next/router";
+import React, { useState } from "react";
+import { ResetPassword } from "~/utils/backendUtils";
+
+const ResetPasswordPage: NextPage = () => {
+  const router = useRouter();
+  const [password, setPassword] = useState("");
+  const [result, setResult] = useState<"NONE" | "DONE" | "FAILED">("NONE");
+
+  const token = typeof router.query.token === "string" ? router.query.token : "";
+
+  return (
+    <div className="bg-darker-purple text-white flex min-h-screen flex-col items-center">
+      <header className="flex h-[70px] w-full justify-center font-bold">
+        <div className="flex max-w-5xl grow items-center justify-between px-5">
+          <Link className="text-3xl" href="/">
+            code samurai
+          </Link>
+        </div>
+      </header>
+      <div className="flex w-full grow flex-col items-center gap-5 px-5 pt-5 sm:w-96 sm:pt-52">
+        <h1 className="text-center text-2xl font-bold">
+          set a new password
+        </h1>
+        <div className="flex w-full flex-col gap-2">
+          <input
+            className="text-black w-full rounded-2xl border-2 border-dark-purple bg-white px-4 py-3"
+            placeholder="new password"
+            type="password"
+            value={password}
+            onChange={(e) => setPassword(e.target.value)}
+          />
+          <button
+            className="w-full rounded-2xl border-b-4 border-pink-ish bg-dark-purple py-3 font-bold transition hover:bg-pink-ish hover:border-dark-purple"
+            onClick={async () => setResult((await ResetPassword(token, password)) ? "DONE" : "FAILED")}
+          >
+            change my password
+          </button>
+          {result === "DONE" && (
+            <p className="text-center">
+              done! you can <Link className="underline" href="/?login">log in</Link> with the new password now.
+            </p>
+          )}
+          {result === "FAILED" && (
+            <p className="text-center">
+              this link doesn&apos;t work anymore, ask for a <Link className="underline" href="/forgot-password">new one</Link>.
+            </p>
+          )}
+        </div>
+      </div>
+    </div>
+  );
+};
+
+export default ResetPasswordPage;
@@ -8,6 +8,8 @@ const ENDP_TEST: string = `/test`;
 const ENDP_LOGIN: string = `/user/login`;
 const ENDP_REGISTER: string = `/user/register`;
 const ENDP_LOGOUT: string = `/user/logout`;
+const ENDP_PASSWORD_FORGOT: string = `/user/password/forgot`;
+const ENDP_PASSWORD_RESET: string = `/user/password/reset`;
 const ENDP_TASK_RANDOM: string = `/task/random`;
 const ENDP_TASK_RANDOM_NEXT: string = `/task/next`;
 const ENDP_ANSWER: string = `/answer`;
@@ -81,6 +83,7 @@ export async function UserLogIn (Username: string, Password: string) {
             currentUser.username = Username;
             currentUser.passwordHash = passwordHash;
             currentUser.authToken = (response).headers.get("authorization");
+            currentUser.refreshToken = (response).headers.get("x-refresh-token");
             currentUser.id = await response.text();
             currentUser.loggedIn = true;
             GetCurrentUserData();
@@ -102,6 +105,50 @@ export async function UserLogIn (Username: string, Password: string) {
 }
 
 
+export async function RequestPasswordReset (email: string) {
+    try {
+        const response = await fetch(SERVER + ENDP_PASSWORD_FORGOT, {
+            method: "POST",
+            headers: {
+                'Content-Type': 'application/json'
+            },
+            body: JSON.stringify({ email: email }),
+            mode: "cors"
+        });
+
+        if (response.status === 202) {
+            return true;
+        }
+        console.log("we got " + response.status + " in RequestPasswordReset");
+    } catch (error) {
+        console.log("error in RequestPasswordReset: " + error);
+    }
+
+    return false;
+}
+
+export async function ResetPassword (token: string, password: string) {
+    try {
+        const response = await fetch(SERVER + ENDP_PASSWORD_RESET, {
+            method: "POST",
+            headers: {
+                'Content-Type': 'application/json'
+            },
+            body: JSON.stringify({ token: token, password: password }),
+            mode: "cors"
+        });
+
+        if (response.status === 204) {
+            return true;
+        }
+        console.log("we got " + response.status + " in ResetPassword");
+    } catch (error) {
+        console.log("error in ResetPassword: " + error);
+    }
+
+    return false;
+}
+
 export async function UserRegister (Username: string, Password: string, email: string | null, phone: string | null) {
     try {
         //const salt: string = await bcryptjs.genSalt(3);
@@ -407,9 +454,35 @@ export async function VerifyAnswer(TaskID: string, Answer: string) {
             body: JSON.stringify(prepData)
         })
 
-        if (resp.status === 201) {
-            CorrectAnswer.ID = resp.headers.get("Location")!;
-            const jsonData = JSON.parse(await resp.text());
+        if (resp.status === 202) {
+            const resultLocation = resp.headers.get("Location")!;
+            CorrectAnswer.ID = `${ENDP_ANSWER}/${JSON.parse(await resp.text()).id}`;
+
+            // Answers are graded in the background, wait for the result
+            let result = await fetch(SERVER + resultLocation, {
+                method: "GET",
+                mode: "cors",
+                headers: { 'authorization': currentUser.authToken! }
+            });
+            while (result.status === 202) {
+                await new Promise((resolve) => setTimeout(resolve, 1000));
+                result = await fetch(SERVER + resultLocation, {
+                    method: "GET",
+                    mode: "cors",
+                    headers: { 'authorization': currentUser.authToken! }
+                });
+            }
+
+            if (result.status !== 200) {
+                console.log("we got " + result.status + " while waiting for the grading result");
+                return false;
+            }
+
+            const jsonData = JSON.parse(await result.text());
+            if (jsonData.status !== "Done") {
+                console.log("grading failed: " + jsonData.error);
+                return false;
+            }
             
             if (jsonData.hasOwnProperty("explanation")) {
                 CurrentResult.explanation = jsonData.explanation;
@@ -27,6 +27,7 @@ type User = {
     progress: CourseProgress;
 
     authToken: string | null;
+    refreshToken: string | null;
 };
 
 export const currentUser: User = {
@@ -53,5 +54,6 @@ export const currentUser: User = {
         task: 0
     },
 
-    authToken: " "
+    authToken: " ",
+    refreshToken: null
 }
\ No newline at end of file